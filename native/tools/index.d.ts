@@ -1,9 +1,119 @@
 /* auto-generated by NAPI-RS */
 /* eslint-disable */
-export declare class DownloadTask {
+/**
+ * Analyses many files in parallel, highest priority first. Jobs can be added, re-prioritised
+ * and removed while it runs.
+ */
+export declare class AnalysisScheduler {
+  constructor(options?: AnalysisSchedulerOptions | undefined | null)
+  /** Queues `jobs`. A path that is queued already moves to its new priority. */
+  enqueue(jobs: Array<AnalysisJob>): void
+  /** Moves the queued ones of `paths` to `priority` and returns how many there were */
+  setPriority(paths: Array<string>, priority: number): number
+  /**
+   * Drops the queued ones of `paths` and returns how many there were. Jobs already being
+   * analysed still finish.
+   */
+  remove(paths: Array<string>): number
+  /** Jobs still queued */
+  pending(): number
+  /** Lets running jobs finish but starts no new ones until `resume` */
+  pause(): void
+  resume(): void
+  /** Stops running jobs and clears the queue. The scheduler cannot be used afterwards. */
+  cancel(): void
+  /**
+   * Works through the queue on `threads` workers, passing every result to `on_result` as it
+   * finishes, and resolves to the number of files analysed once the queue is empty. Files that
+   * could not be decoded are reported but not counted. Jobs queued meanwhile are picked up,
+   * while paused it waits for `resume`. Rejects with `Cancelled` after `cancel`.
+   */
+  run(onResult: ((err: Error | null, arg: AnalysisJobResult) => any)): Promise<number>
+}
+
+/**
+ * Full-track analysis. Unlike `analyze_audio_file` it does not stop after
+ * `max_analyze_time`, every field of the result covers the whole track.
+ */
+export declare class AnalysisTask {
   constructor()
   cancel(): void
-  download(url: string, filePath: string, metadata: SongMetadata | undefined | null, threadCount: number, referer: string | undefined | null, onProgress: ((err: Error | null, arg: DownloadProgress) => any), enableHttp2: boolean): Promise<void>
+  /**
+   * Decodes the whole file in bounded memory. Resolves to `null` if the file cannot be
+   * decoded and rejects with `Cancelled` after `cancel`.
+   */
+  analyze(path: string, onProgress?: ((err: Error | null, arg: AnalysisProgress) => any) | undefined | null): Promise<AudioAnalysis | null>
+}
+
+export declare class DownloadTask {
+  constructor(options?: ClientOptions | undefined | null)
+  cancel(): void
+  download(url: string, filePath: string, metadata: SongMetadata | undefined | null, threadCount: number, referer: string | undefined | null, onProgress: ((err: Error | null, arg: DownloadProgress) => any), enableHttp2: boolean, onUrlExpired?: ((err: Error | null, arg: UrlRefreshRequest) => string | Promise<string>) | undefined | null, mirrors?: Array<string> | undefined | null, fixExtension?: boolean | undefined | null): Promise<DownloadResult>
+}
+
+/**
+ * Size-capped song cache. The index lives in `SQLite` next to the files, eviction is LRU
+ * and never touches pinned entries (the current play queue).
+ */
+export declare class MusicCache {
+  constructor(cacheDir: string, maxBytes: number)
+  /**
+   * Registers an unfinished entry and returns the path it should be downloaded to.
+   * Makes room for `expected_size` first.
+   */
+  begin(key: string, expectedSize?: number | undefined | null, extension?: string | undefined | null): string
+  /** Marks an entry as fully cached, taking its size from the file on disk */
+  complete(key: string): boolean
+  /** Looks up an entry and refreshes its last access time */
+  lookup(key: string): CacheEntry | null
+  /** Whether the song can be played entirely from the cache. Does not count as an access. */
+  isComplete(key: string): boolean
+  remove(key: string): boolean
+  /** Replaces the set of pinned keys, usually with the current play queue */
+  pin(keys: Array<string>): void
+  setMaxBytes(maxBytes: number): Array<string>
+  /**
+   * Evicts least recently used entries until the cache fits its budget.
+   * Returns the evicted keys.
+   */
+  evict(): Array<string>
+  /**
+   * Drops index entries whose files are gone and deletes cache files that have no entry.
+   * Files not named like cache entries are left alone. Returns how many entries and files
+   * were removed.
+   */
+  reconcile(): number
+  stats(): CacheStats
+  entries(): Array<CacheEntry>
+}
+
+/**
+ * Loopback HTTP server that serves songs while `DownloadTask` is still writing them.
+ *
+ * Byte ranges that are already on disk are answered immediately, requests for missing
+ * ranges wait and move the chunks they need to the front of the download queue.
+ * Tags written after the download rewrite the file, so pass the metadata to `download`
+ * only for files that are not being played through the server.
+ *
+ * URLs carry random ids and no CORS header is sent unless `allowed_origin` is given, so
+ * other web pages can't read files through the server.
+ */
+export declare class StreamServer {
+  /**
+   * Binds `127.0.0.1:port`, port `0` (the default) picks a free one. `allowed_origin` is
+   * the origin of the app's own pages, e.g. for Web Audio on the stream.
+   */
+  constructor(port?: number | undefined | null, allowedOrigin?: string | undefined | null)
+  get port(): number
+  /**
+   * Returns the URL the player should use for `file_path`. Set `await_download` if a
+   * download to it is about to start, a file already there is then not served until that
+   * download has begun.
+   */
+  serve(filePath: string, contentType?: string | undefined | null, awaitDownload?: boolean | undefined | null): string
+  /** Stops serving a URL returned by `serve` */
+  unserve(url: string): boolean
+  stop(): void
 }
 
 export interface AdvancedTransition {
@@ -17,6 +127,45 @@ export interface AdvancedTransition {
   strategy: string
 }
 
+export interface AnalysisJob {
+  path: string
+  /** Higher runs first, e.g. the upcoming queue above the rest of the library. Defaults to 0 */
+  priority?: number
+}
+
+export interface AnalysisJobResult {
+  path: string
+  /** `None` if the file could not be decoded */
+  analysis?: AudioAnalysis
+  /** Jobs finished since the scheduler was created, this one included */
+  completed: number
+  /** Jobs still queued */
+  pending: number
+}
+
+export interface AnalysisProgress {
+  /** Seconds decoded so far */
+  position: number
+  /** Duration from the container, if it has one */
+  duration?: number
+  /** 0.0 - 1.0, `None` while the duration is unknown */
+  percent?: number
+}
+
+export interface AnalysisSchedulerOptions {
+  /** Worker threads, defaults to all cores but one */
+  threads?: number
+  /** Same as for `analyze_audio_file` */
+  maxAnalyzeTime?: number
+  /**
+   * Analyse the tail as well (`analyze_audio_file`) or only the head (`analyze_audio_file_head`),
+   * defaults to true
+   */
+  includeTail?: boolean
+  /** Analyse whole tracks like `AnalysisTask`, ignores the two options above */
+  fullTrack?: boolean
+}
+
 export declare function analyzeAudioFile(path: string, maxAnalyzeTime?: number | undefined | null): AudioAnalysis | null
 
 export declare function analyzeAudioFileHead(path: string, maxAnalyzeTime?: number | undefined | null): AudioAnalysis | null
@@ -28,7 +177,13 @@ export interface AudioAnalysis {
   fade_in_pos: number
   fade_out_pos: number
   first_beat_pos?: number
+  downbeat_pos?: number
+  beat_grid?: Array<number>
   loudness?: number
+  loudness_range?: number
+  momentary_max?: number
+  short_term_max?: number
+  true_peak?: number
   drop_pos?: number
   version: number
   analyze_window: number
@@ -46,6 +201,40 @@ export interface AudioAnalysis {
   key_mode?: number
   key_confidence?: number
   camelot_key?: string
+  key_timeline?: Array<KeySegment>
+  sections?: Array<Section>
+  vocal_segments?: Array<VocalSegment>
+}
+
+export type AudioContainer =  'Mp3'|
+'Flac'|
+/** MP4 / M4A, usually AAC or ALAC */
+'Mp4'|
+/** Raw ADTS AAC */
+'Aac'|
+/** Ogg Vorbis (or Ogg FLAC) */
+'Ogg'|
+'Opus'|
+'Wav'|
+'Aiff'|
+'Ape'|
+'WavPack'|
+/** MPEG transport stream, what HLS usually delivers. Cannot carry tags */
+'MpegTs';
+
+export interface AudioProperties {
+  duration: number
+  /** kbps */
+  overallBitrate?: number
+  /** kbps */
+  audioBitrate?: number
+  sampleRate?: number
+  bitDepth?: number
+  channels?: number
+  /** Container format, e.g. `Flac`, `Mpeg`, `Mp4` */
+  fileType: string
+  /** Tag formats present in the file, e.g. `Id3v2`, `VorbisComments` */
+  tagTypes: Array<string>
 }
 
 export interface AutomationPoint {
@@ -55,14 +244,186 @@ export interface AutomationPoint {
   highCut: number
 }
 
+export interface BatchEditFailure {
+  path: string
+  error: string
+}
+
+export interface BatchEditOptions {
+  /** Where undo journals are stored, defaults to a directory in the user's app data dir */
+  journalDir?: string
+  /** Restore every already edited file if any file fails, defaults to `false` */
+  atomic?: boolean
+  /** Use the same crash-safe write as `WriteTagOptions.safeWrite` */
+  safeWrite?: boolean
+}
+
+export interface BatchEditProgress {
+  current: number
+  total: number
+  path: string
+  /** Set if this file failed */
+  error?: string
+}
+
+export interface BatchEditResult {
+  /** Pass to `revert_batch` to restore the previous values, `None` if nothing was changed */
+  journalId?: string
+  succeeded: number
+  failed: Array<BatchEditFailure>
+}
+
+/**
+ * Applies field edits to many files in parallel and records the previous values in an
+ * undo journal for `revert_batch`
+ */
+export declare function batchEditTags(paths: Array<string>, edits: Array<TagEdit>, onProgress?: ((err: Error | null, arg: BatchEditProgress) => any) | undefined | null, options?: BatchEditOptions | undefined | null): Promise<BatchEditResult>
+
+export interface CacheEntry {
+  key: string
+  /** Absolute path of the cached file */
+  path: string
+  size: number
+  /** Final size, if known when the entry was started */
+  expectedSize?: number
+  complete: boolean
+  /** Unix time in milliseconds */
+  lastAccess: number
+}
+
+export interface CacheStats {
+  /** Bytes counted against the budget, unfinished entries count with their expected size */
+  totalBytes: number
+  maxBytes: number
+  entryCount: number
+  completeCount: number
+  pinnedCount: number
+}
+
+/**
+ * Removes cached results for `path`, or all of them. Returns the number of removed rows.
+ */
+export declare function clearAnalysisCache(path?: string | undefined | null): number
+
+export interface ClientOptions {
+  /** Proxy URL, e.g. `http://host:port`, `https://host:port` or `socks5h://host:port` */
+  proxy?: string
+  proxyUsername?: string
+  proxyPassword?: string
+  /** Extra headers sent with every request */
+  headers?: Record<string, string>
+  /** Domain (or URL) -> `Set-Cookie` style strings used to seed the cookie jar */
+  cookies?: Record<string, Array<string>>
+  userAgent?: string
+  connectTimeoutMs?: number
+  readTimeoutMs?: number
+  /** Paths to PEM (or DER) encoded CA certificates to trust in addition to the system ones */
+  extraCaCerts?: Array<string>
+}
+
+/**
+ * Measures `ReplayGain` 2.0 track (and optionally album) gain and peak with the EBU R128
+ * loudness meter, and can write the results as `REPLAYGAIN_*` tags
+ */
+export declare function computeReplaygain(paths: Array<string>, albumMode: boolean, onProgress?: ((err: Error | null, arg: ReplayGainProgress) => any) | undefined | null, options?: ReplayGainOptions | undefined | null): Promise<ReplayGainResult>
+
+/** Reads data produced by `encode_waveform` */
+export declare function decodeWaveform(data: Buffer): Waveform
+
 export interface DownloadProgress {
   percent: number
   transferredBytes: number
   totalBytes: number
+  /** Smoothed transfer rate in bytes per second */
+  speed: number
+  /** Estimated seconds remaining, `None` while the speed or total size is unknown */
+  eta?: number
+  /** Failed attempts that were retried so far */
+  retries: number
+  activeConnections: number
+  /** One byte per chunk for multi-threaded downloads: 0 pending, 1 downloading, 2 done */
+  chunkStates?: Array<number>
+}
+
+export interface DownloadResult {
+  /** Where the file ended up, differs from the requested path if the extension was fixed */
+  filePath: string
+  /** Real container sniffed from the content (or `Content-Type`), `None` if unrecognized */
+  container?: AudioContainer
+  contentType?: string
+  /** Whether the file was renamed to match `container` */
+  renamed: boolean
+}
+
+export interface EmbeddedPicture {
+  /** e.g. `CoverFront`, `CoverBack`, `Artist` */
+  pictureType: string
+  mimeType?: string
+  description?: string
+  data: Buffer
+}
+
+/** Packs a waveform into a compact binary form with 16-bit peaks, for caching on disk */
+export declare function encodeWaveform(waveform: Waveform): Buffer
+
+export interface GaplessInfo {
+  source: GaplessSource
+  /** Rate the sample counts are in, always 48000 for Opus */
+  sampleRate: number
+  /**
+   * Priming samples to drop from the start. For MP3 this excludes the 529 samples of
+   * decoder delay most decoders add on top
+   */
+  encoderDelay: number
+  /**
+   * Samples to drop from the end, `None` for Ogg where the last granule position
+   * already ends the stream
+   */
+  padding?: number
+  /** Samples per channel left after trimming */
+  totalSamples: number
+  /** `total_samples` in milliseconds, like `MusicTrack.duration` */
+  duration: number
 }
 
+export type GaplessSource =  /** LAME extension of the MP3 Xing/Info header, also written by `FFmpeg` */
+'Lame'|
+/** `iTunSMPB` atom written by iTunes and most AAC encoders */
+'ITunSmpb'|
+/** Opus pre-skip and the last granule position */
+'Opus'|
+/** Last granule position of an Ogg Vorbis stream */
+'Vorbis';
+
+/**
+ * Decodes the whole file once and computes min/max/RMS peaks per channel for every
+ * resolution, given as frames per point (e.g. `[256, 1024, 4096]`)
+ */
+export declare function generateWaveform(path: string, resolutions: Array<number>, onProgress?: ((err: Error | null, arg: AnalysisProgress) => any) | undefined | null): Promise<Waveform>
+
 export declare function getTaskbarCreatedMessageId(): number
 
+export interface KeySegment {
+  start: number
+  end: number
+  /** Pitch class of the tonic, 0 = C */
+  keyRoot: number
+  /** 0 = major, 1 = minor */
+  keyMode: number
+  camelotKey?: string
+  confidence: number
+}
+
+export interface MusicFileMetadata {
+  /**
+   * Same shape `write_music_metadata` accepts, `lyric` is always plain text and
+   * `synced_lyric` is LRC
+   */
+  metadata: SongMetadata
+  properties: AudioProperties
+  pictures: Array<EmbeddedPicture>
+}
+
 export interface MusicTrack {
   id: string
   path: string
@@ -77,6 +438,96 @@ export interface MusicTrack {
   trackNumber?: number
 }
 
+export type PathTarget =  /** The filesystem rules of the current platform */
+'Auto'|
+/** NTFS / FAT / exFAT, also the safe choice for removable drives */
+'Windows'|
+'MacOs'|
+'Linux';
+
+/**
+ * Reads encoder delay, padding and the exact sample count of MP3 (LAME header), MP4/AAC
+ * (`iTunSMPB`), Opus and Vorbis files. Resolves to `null` if the file carries none.
+ */
+export declare function readGaplessInfo(filePath: string): Promise<GaplessInfo | null>
+
+/** Reads tags, technical properties, embedded pictures and lyrics of a local file */
+export declare function readMusicMetadata(filePath: string): Promise<MusicFileMetadata>
+
+/**
+ * Renders a download path from a template such as
+ * `{album_artist}/{album}/{disc}-{track:02} {title}`
+ *
+ * Supported fields: `title`, `artist`, `album`, `album_artist` (falls back to `artist`),
+ * `genre`, `year`, `track`, `disc`. A `:0N` suffix zero-pads numbers to `N` digits.
+ * `/` in the template separates directories, while `/` inside field values does not.
+ */
+export declare function renderDownloadPath(template: string, metadata: SongMetadata, options?: RenderPathOptions | undefined | null): string
+
+export interface RenderPathOptions {
+  /** Directory the rendered relative path is joined to */
+  baseDir?: string
+  /** File extension, with or without the leading dot */
+  extension?: string
+  target?: PathTarget
+  /** Replaces characters the target filesystem does not allow, defaults to `_` */
+  replacement?: string
+  /** Maximum length of a single path component, defaults to 255 */
+  maxComponentLength?: number
+  /** Maximum length of the whole path, defaults to 259 on Windows and 4096 elsewhere */
+  maxPathLength?: number
+  /** Append ` (1)`, ` (2)`, ... when the path already exists, defaults to `true` */
+  resolveCollisions?: boolean
+  /** Paths already claimed by other pending downloads, treated as existing */
+  reservedPaths?: Array<string>
+}
+
+/**
+ * Renders the spectrum of `path` over time as a PNG, time left to right and frequency
+ * bottom to top
+ */
+export declare function renderSpectrogram(path: string, options?: SpectrogramOptions | undefined | null): Promise<Buffer>
+
+export interface ReplayGainOptions {
+  /** Write `REPLAYGAIN_*` tags to the files, defaults to `false` */
+  writeTags?: boolean
+  /** Use the same crash-safe write as `WriteTagOptions.safeWrite` */
+  safeWrite?: boolean
+}
+
+export interface ReplayGainProgress {
+  current: number
+  total: number
+  path: string
+  /** Set if this file could not be measured */
+  error?: string
+}
+
+export interface ReplayGainResult {
+  tracks: Array<ReplayGainTrack>
+  /** Only in album mode, computed over all tracks as if played back to back */
+  albumGain?: number
+  albumPeak?: number
+}
+
+export interface ReplayGainTrack {
+  path: string
+  /** Gated integrated loudness in LUFS */
+  loudness?: number
+  /** Gain in dB that brings the track to -18 LUFS */
+  gain?: number
+  /** Linear true peak, 1.0 is full scale */
+  peak?: number
+  /** Set if measuring or tagging this file failed */
+  error?: string
+}
+
+/**
+ * Restores the values recorded by `batch_edit_tags`. The journal is removed once every
+ * file was restored, failed files are returned and stay in the journal for another try.
+ */
+export declare function revertBatch(journalId: string, journalDir?: string | undefined | null): Promise<Array<BatchEditFailure>>
+
 export interface ScanEvent {
   event: string
   tracks?: Array<MusicTrack>
@@ -91,6 +542,28 @@ export interface ScanProgress {
   total: number
 }
 
+export interface Section {
+  start: number
+  end: number
+  label: SectionLabel
+  /** Sections of the same group repeat the same material, numbered by first appearance */
+  group: number
+  /** Mean level relative to the loudest section, 0.0 - 1.0 */
+  energy: number
+}
+
+export type SectionLabel =  'Intro'|
+'Verse'|
+'Chorus'|
+'Bridge'|
+'Outro';
+
+/**
+ * Enables the persistent analysis cache in the `SQLite` database at `db_path` (created if
+ * missing), or disables it when `None`. Rows from older analysis versions are dropped.
+ */
+export declare function setAnalysisCache(dbPath?: string | undefined | null): void
+
 export interface SongMetadata {
   title: string
   artist: string
@@ -103,12 +576,104 @@ export interface SongMetadata {
   year?: number
   trackNumber?: number
   discNumber?: number
+  /** Individual artists, written as a multi-value field. `artist` stays the display string */
+  artists?: Array<string>
+  /** LRC formatted lyric, written as ID3 SYLT, Vorbis `LYRICS` or MP4 `©lyr` */
+  syncedLyric?: string
+  /** Translated lyric, LRC or plain text */
+  translatedLyric?: string
+  /**
+   * Source IDs written as custom fields (TXXX in ID3), e.g. `{ "NCM_ID": "1234" }`. Names are
+   * upper-cased and get an `_ID` suffix if they lack one
+   */
+  sourceIds?: Record<string, string>
+  musicbrainzRecordingId?: string
+  musicbrainzReleaseTrackId?: string
+  musicbrainzReleaseId?: string
+  musicbrainzReleaseGroupId?: string
+  musicbrainzArtistId?: string
+  musicbrainzAlbumArtistId?: string
+  /** Gain in dB */
+  replaygainTrackGain?: number
+  /** Linear sample peak, 1.0 is full scale */
+  replaygainTrackPeak?: number
+  replaygainAlbumGain?: number
+  replaygainAlbumPeak?: number
+}
+
+export type SpectrogramColorMap =  'Magma'|
+'Inferno'|
+'Viridis'|
+'Grayscale';
+
+export interface SpectrogramOptions {
+  /** Image size in pixels, defaults to 1024 x 512 */
+  width?: number
+  height?: number
+  /** Power of two between 64 and 65536, defaults to 4096 */
+  fftSize?: number
+  /** Fraction of each FFT frame shared with the next, 0.0 - 0.95, defaults to 0.5 */
+  overlap?: number
+  /** Defaults to `Hann` */
+  window?: SpectrogramWindow
+  /** Logarithmic frequency axis from 20 Hz, defaults to linear */
+  logFrequency?: boolean
+  /** Defaults to `Magma` */
+  colorMap?: SpectrogramColorMap
+  /** Defaults to `Mix` */
+  view?: SpectrogramView
+  /** Time range in seconds, defaults to the whole track */
+  start?: number
+  end?: number
+  /** Level drawn as the darkest colour in dBFS, defaults to -120 */
+  minDb?: number
 }
 
+export type SpectrogramView =  /** All channels mixed to mono */
+'Mix'|
+/** One band per channel, the first channel on top */
+'Channels'|
+/** Mid on top and side below, mono files fall back to `Mix` */
+'MidSide';
+
+export type SpectrogramWindow =  'Hann'|
+'Hamming'|
+'Blackman'|
+/** Lowest side lobes, best for spotting faint content */
+'BlackmanHarris'|
+'Rectangular';
+
 export declare function suggestLongMix(currentPath: string, nextPath: string): AdvancedTransition | null
 
+/** Same as `suggest_long_mix`, taking analyses computed earlier like `suggest_transition_from` */
+export declare function suggestLongMixFrom(current: AudioAnalysis | string, next: AudioAnalysis | string): AdvancedTransition | null
+
 export declare function suggestTransition(currentPath: string, nextPath: string): TransitionProposal | null
 
+/**
+ * Same as `suggest_transition`, but each track can be given as the `AudioAnalysis` computed
+ * earlier (needs the tail, i.e. `analyzeAudioFile`, for the current track) so nothing is decoded
+ */
+export declare function suggestTransitionFrom(current: AudioAnalysis | string, next: AudioAnalysis | string): TransitionProposal | null
+
+export interface TagEdit {
+  /**
+   * `title`, `artist`, `album`, `albumArtist`, `genre`, `year`, `trackNumber`, `trackTotal`,
+   * `discNumber`, `discTotal`, `comment`, `composer` or `lyric`
+   */
+  field: string
+  op: TagEditOp
+  value?: string
+  pattern?: string
+}
+
+export type TagEditOp =  'Set'|
+'Clear'|
+/** Appends `value` to the current value, or sets it if the field is empty */
+'Append'|
+/** Replaces all matches of `pattern` with `value`, `$1` style groups are supported */
+'RegexReplace';
+
 export interface TransitionProposal {
   duration: number
   current_track_mix_out: number
@@ -120,4 +685,52 @@ export interface TransitionProposal {
   bpm_compatible: boolean
 }
 
-export declare function writeMusicMetadata(filePath: string, metadata: SongMetadata, coverPath?: string | undefined | null): Promise<void>
+export interface UrlRefreshRequest {
+  /** The URL that stopped working */
+  url: string
+  /** HTTP status that triggered the refresh (401, 403 or 410) */
+  status: number
+}
+
+export interface VocalSegment {
+  start: number
+  end: number
+  /** Mean vocal probability over the segment, 0.0 - 1.0 */
+  confidence: number
+}
+
+export interface Waveform {
+  sampleRate: number
+  channels: number
+  /** Decoded length in seconds */
+  duration: number
+  /** In the order of the requested resolutions */
+  levels: Array<WaveformLevel>
+}
+
+export interface WaveformLevel {
+  /** Frames summarised by each point */
+  samplesPerPixel: number
+  /** One entry per channel, values are -1.0 - 1.0 */
+  channels: Array<WaveformPeaks>
+}
+
+export interface WaveformPeaks {
+  min: Array<number>
+  max: Array<number>
+  rms: Array<number>
+}
+
+export declare function writeMusicMetadata(filePath: string, metadata: SongMetadata, coverPath?: string | undefined | null, options?: WriteTagOptions | undefined | null): Promise<void>
+
+export interface WriteTagOptions {
+  /** Downscale the cover so neither side exceeds this many pixels before embedding */
+  coverMaxSize?: number
+  /**
+   * Write to a temporary copy, verify it still decodes and then atomically replace the
+   * original, defaults to `false`
+   */
+  safeWrite?: boolean
+  /** With `safe_write`, keep the original file as `<name>.bak` */
+  keepBackup?: boolean
+}
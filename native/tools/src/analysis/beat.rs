@@ -0,0 +1,401 @@
+// Beat tracking: multi-band spectral flux onsets, tempo estimation with octave disambiguation,
+// dynamic programming beat tracking (Ellis 2007) and a 4/4 downbeat estimate
+
+use num_complex::Complex32;
+use rustfft::{Fft, FftPlanner};
+use std::ops::Range;
+use std::sync::Arc;
+
+// Onset strength frames per second
+const ONSET_RATE: u32 = 100;
+// Band edges in Hz, the lowest band (kick / bass) also drives the downbeat estimate
+const BAND_EDGES: [f32; 7] = [30.0, 150.0, 400.0, 1_000.0, 2_500.0, 6_000.0, 16_000.0];
+pub(super) const BAND_COUNT: usize = BAND_EDGES.len() - 1;
+// Log compression of band energy, makes the flux independent of the playback level
+const LOG_COMPRESSION: f32 = 1e5;
+
+const MIN_BPM: f64 = 50.0;
+const MAX_BPM: f64 = 220.0;
+// Log-normal tempo prior, most music is felt around 120 BPM
+const PRIOR_BPM: f64 = 120.0;
+const PRIOR_OCTAVES: f64 = 1.0;
+// A known tempo (from the head of the track) constrains the tail much tighter
+const HINT_OCTAVES: f64 = 0.2;
+// How strongly the tracker keeps the beat period, higher allows less drift
+const TIGHTNESS: f64 = 100.0;
+const MIN_SECONDS: f64 = 5.0;
+const BEATS_PER_BAR: usize = 4;
+
+pub(super) fn onset_rate(sample_rate: u32) -> f64 {
+    f64::from(sample_rate) / hop_len(sample_rate) as f64
+}
+
+fn hop_len(sample_rate: u32) -> usize {
+    (sample_rate / ONSET_RATE).max(1) as usize
+}
+
+/// Streams mono samples into an onset strength envelope at `onset_rate`
+pub(super) struct OnsetDetector {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // The last `frame_len` samples written twice so a frame never wraps
+    history: Vec<f32>,
+    pos: usize,
+    buffer: Vec<Complex32>,
+    hop: usize,
+    since_hop: usize,
+    bands: Vec<Range<usize>>,
+    prev: Option<Vec<f32>>,
+}
+
+impl OnsetDetector {
+    pub(super) fn new(sample_rate: u32) -> Self {
+        // ~25 ms at any rate, 2048 at 44.1 / 48 kHz
+        let frame_len = (sample_rate as usize / 40).next_power_of_two().max(256);
+        let bin =
+            |hz: f32| ((hz * frame_len as f32 / sample_rate as f32) as usize).min(frame_len / 2);
+        let bands = BAND_EDGES
+            .windows(2)
+            .map(|edges| bin(edges[0]).max(1)..bin(edges[1]))
+            .filter(|band| !band.is_empty())
+            .collect();
+        let window = (0..frame_len)
+            .map(|i| {
+                0.5f32.mul_add(
+                    -(2.0 * std::f32::consts::PI * i as f32 / frame_len as f32).cos(),
+                    0.5,
+                )
+            })
+            .collect();
+
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(frame_len),
+            window,
+            history: vec![0.0; frame_len * 2],
+            pos: 0,
+            buffer: vec![Complex32::new(0.0, 0.0); frame_len],
+            hop: hop_len(sample_rate),
+            since_hop: 0,
+            bands,
+            prev: None,
+        }
+    }
+
+    /// Returns `(onset strength, low band onset strength)` once per hop
+    pub(super) fn process(&mut self, sample: f32) -> Option<(f32, f32)> {
+        let frame_len = self.window.len();
+        self.pos = (self.pos + 1) % frame_len;
+        self.history[self.pos] = sample;
+        self.history[self.pos + frame_len] = sample;
+
+        self.since_hop += 1;
+        if self.since_hop < self.hop {
+            return None;
+        }
+        self.since_hop = 0;
+
+        let frame = &self.history[self.pos + 1..=self.pos + frame_len];
+        for ((out, &x), &w) in self.buffer.iter_mut().zip(frame).zip(&self.window) {
+            *out = Complex32::new(x * w, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        let scale = 1.0 / (frame_len * frame_len) as f32;
+        let energy: Vec<f32> = self
+            .bands
+            .iter()
+            .map(|band| {
+                let e: f32 = self.buffer[band.clone()]
+                    .iter()
+                    .map(Complex32::norm_sqr)
+                    .sum();
+                (LOG_COMPRESSION * e * scale).ln_1p()
+            })
+            .collect();
+
+        let flux = self.prev.as_ref().map_or((0.0, 0.0), |prev| {
+            let rise = |b: usize| (energy[b] - prev[b]).max(0.0);
+            let total: f32 = (0..energy.len()).map(rise).sum();
+            (total / energy.len().max(1) as f32, rise(0))
+        });
+        self.prev = Some(energy);
+        Some(flux)
+    }
+
+    /// Log energy of each band in the latest frame, a coarse timbre description. Bands above
+    /// the Nyquist frequency stay 0.
+    pub(super) fn band_energy(&self) -> [f32; BAND_COUNT] {
+        let mut bands = [0.0; BAND_COUNT];
+        if let Some(prev) = &self.prev {
+            for (band, &e) in bands.iter_mut().zip(prev) {
+                *band = e;
+            }
+        }
+        bands
+    }
+}
+
+pub(super) struct BeatTrack {
+    pub bpm: f64,
+    /// 0.0 - 1.0, how periodic the onsets are at the beat period
+    pub confidence: f64,
+    /// Beat times in seconds
+    pub beats: Vec<f64>,
+    /// Index of the first downbeat in `beats`
+    pub downbeat: Option<usize>,
+}
+
+impl BeatTrack {
+    /// Times of the bar lines, empty if the downbeat is unknown
+    pub(super) fn bars(&self) -> impl Iterator<Item = f64> + '_ {
+        let start = self.downbeat.unwrap_or(self.beats.len());
+        self.beats
+            .iter()
+            .skip(start)
+            .step_by(BEATS_PER_BAR)
+            .copied()
+    }
+}
+
+/// Tracks the beats of one analysed segment. `offset` is the time of the first onset frame,
+/// `tempo_hint` a tempo already known from another part of the track.
+pub(super) fn track_beats(
+    onset: &[f32],
+    low_onset: &[f32],
+    rate: f64,
+    offset: f64,
+    tempo_hint: Option<f64>,
+) -> Option<BeatTrack> {
+    if (onset.len() as f64) < MIN_SECONDS * rate {
+        return None;
+    }
+    let strength = normalize(onset, rate);
+    let (period, confidence) = estimate_period(&strength, rate, tempo_hint)?;
+    // Slightly early or late onsets still count
+    let local = smooth(&strength, (period / 32.0).max(1.0));
+    let frames = track(&local, period)?;
+
+    let beats: Vec<f64> = frames
+        .iter()
+        .map(|&frame| offset + frame as f64 / rate)
+        .collect();
+
+    Some(BeatTrack {
+        bpm: 60.0 / fitted_period(&beats).unwrap_or(period / rate),
+        confidence,
+        downbeat: find_downbeat(&frames, low_onset),
+        beats,
+    })
+}
+
+// Removes the slowly varying part and scales to unit deviation, leaving the sharp rises
+fn normalize(onset: &[f32], rate: f64) -> Vec<f64> {
+    let half = (rate / 2.0) as usize;
+    let mut prefix = Vec::with_capacity(onset.len() + 1);
+    prefix.push(0.0);
+    for &x in onset {
+        prefix.push(prefix.last().copied().unwrap_or(0.0) + f64::from(x));
+    }
+    let mut detrended: Vec<f64> = (0..onset.len())
+        .map(|i| {
+            let lo = i.saturating_sub(half);
+            let hi = (i + half + 1).min(onset.len());
+            let mean = (prefix[hi] - prefix[lo]) / (hi - lo) as f64;
+            (f64::from(onset[i]) - mean).max(0.0)
+        })
+        .collect();
+    let deviation = (detrended.iter().map(|x| x * x).sum::<f64>() / detrended.len() as f64).sqrt();
+    if deviation > 0.0 {
+        for x in &mut detrended {
+            *x /= deviation;
+        }
+    }
+    detrended
+}
+
+// Gaussian smoothing, `sigma` in frames
+fn smooth(strength: &[f64], sigma: f64) -> Vec<f64> {
+    let radius = (sigma * 3.0) as usize;
+    let kernel: Vec<f64> = (0..=radius * 2)
+        .map(|i| {
+            let x = (i as f64 - radius as f64) / sigma;
+            (-0.5 * x * x).exp()
+        })
+        .collect();
+    (0..strength.len())
+        .map(|i| {
+            kernel
+                .iter()
+                .enumerate()
+                .filter_map(|(k, w)| {
+                    let j = (i + k).checked_sub(radius)?;
+                    strength.get(j).map(|s| s * w)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+// Unbiased autocorrelation for lags `0..max_lag`
+fn autocorrelation(strength: &[f64], max_lag: usize) -> Vec<f64> {
+    (0..max_lag.min(strength.len()))
+        .map(|lag| {
+            let n = strength.len() - lag;
+            strength[..n]
+                .iter()
+                .zip(&strength[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / n as f64
+        })
+        .collect()
+}
+
+// Beat period in frames and its confidence
+fn estimate_period(strength: &[f64], rate: f64, tempo_hint: Option<f64>) -> Option<(f64, f64)> {
+    let lag_of = |bpm: f64| 60.0 * rate / bpm;
+    let min_lag = lag_of(MAX_BPM).floor() as usize;
+    let max_lag = lag_of(MIN_BPM).ceil() as usize;
+    // Onsets are sharper than a frame, smoothing keeps ACF peaks between two lags intact
+    let acf = autocorrelation(&smooth(strength, 1.5), max_lag * 4 + 2);
+    if acf.len() < max_lag * 4 + 2 {
+        return None;
+    }
+    let acf_at = |lag: f64| {
+        let i = lag as usize;
+        let frac = lag - i as f64;
+        (acf[i + 1] - acf[i]).mul_add(frac, acf[i])
+    };
+
+    let (center, width) = tempo_hint.map_or((PRIOR_BPM, PRIOR_OCTAVES), |bpm| (bpm, HINT_OCTAVES));
+    // A beat also correlates with the half and full bar, fast subdivisions (hi-hats) do not
+    let score = |lag: f64| {
+        let octaves = (rate * 60.0 / lag / center).log2() / width;
+        let comb = 0.25f64.mul_add(
+            acf_at(lag * 4.0),
+            0.5f64.mul_add(acf_at(lag * 2.0), acf_at(lag)),
+        );
+        (-0.5 * octaves * octaves).exp() * comb
+    };
+
+    let (best_lag, best) = (min_lag..=max_lag)
+        .map(|lag| (lag, score(lag as f64)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if best <= 0.0 {
+        return None;
+    }
+
+    // Parabolic refinement, one frame is ~2.5 BPM at 120 BPM
+    let (prev, next) = (score(best_lag as f64 - 1.0), score(best_lag as f64 + 1.0));
+    let curvature = 2.0f64.mul_add(-best, prev + next);
+    let shift = if curvature < 0.0 {
+        (0.5 * (prev - next) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let period = best_lag as f64 + shift;
+
+    // Periodicity: how much of the onset energy repeats one period later, above the level
+    // any lag gets by chance. Near 0 for noise or free rhythm
+    let baseline = acf[min_lag..=max_lag].iter().sum::<f64>() / (max_lag - min_lag + 1) as f64;
+    let confidence = if acf[0] > baseline {
+        ((acf_at(period) - baseline) / (acf[0] - baseline)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    Some((period, confidence))
+}
+
+// Dynamic programming beat tracker: every beat maximizes its onset strength plus the best
+// predecessor about one period earlier, penalized by how far the interval strays from it
+fn track(local: &[f64], period: f64) -> Option<Vec<usize>> {
+    let min_step = (period / 2.0).round() as usize;
+    let max_step = (period * 2.0).round() as usize;
+    let mut cumulative = local.to_vec();
+    let mut backlink = vec![None; local.len()];
+    for t in min_step..local.len() {
+        let best = (min_step..=max_step.min(t))
+            .map(|step| {
+                let penalty = (step as f64 / period).ln();
+                (
+                    t - step,
+                    TIGHTNESS.mul_add(-penalty * penalty, cumulative[t - step]),
+                )
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((prev, score)) = best {
+            if score > 0.0 {
+                cumulative[t] += score;
+                backlink[t] = Some(prev);
+            }
+        }
+    }
+
+    // End on the strongest beat within the last period
+    let tail_start = local.len().saturating_sub(period.round() as usize);
+    let (mut t, _) = cumulative
+        .iter()
+        .enumerate()
+        .skip(tail_start)
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let mut frames = vec![t];
+    while let Some(prev) = backlink[t] {
+        frames.push(prev);
+        t = prev;
+    }
+    frames.reverse();
+
+    // Trim beats in leading and trailing silence, where the tracker just keeps time
+    let at_beats: Vec<f64> = frames.iter().map(|&f| local[f]).collect();
+    let threshold =
+        0.5 * (at_beats.iter().map(|x| x * x).sum::<f64>() / at_beats.len() as f64).sqrt();
+    let first = at_beats.iter().position(|&x| x > threshold)?;
+    let last = at_beats.iter().rposition(|&x| x > threshold)?;
+    frames.truncate(last + 1);
+    frames.drain(..first);
+    (frames.len() >= 2).then_some(frames)
+}
+
+// Least squares slope of beat time over beat index, the mean period of the tracked beats
+fn fitted_period(beats: &[f64]) -> Option<f64> {
+    if beats.len() < 4 {
+        return None;
+    }
+    let n = beats.len() as f64;
+    let mean_i = (n - 1.0) / 2.0;
+    let mean_t = beats.iter().sum::<f64>() / n;
+    let (cov, var) = beats
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(cov, var), (i, &t)| {
+            let di = i as f64 - mean_i;
+            (di.mul_add(t - mean_t, cov), di.mul_add(di, var))
+        });
+    let period = cov / var;
+    (period > 0.0).then_some(period)
+}
+
+// Assumes 4/4, the bar phase whose beats carry the most low band onsets (kick, bass notes)
+fn find_downbeat(frames: &[usize], low_onset: &[f32]) -> Option<usize> {
+    if frames.len() < BEATS_PER_BAR * 2 {
+        return None;
+    }
+    let strength_at = |frame: usize| {
+        low_onset[frame.saturating_sub(2)..(frame + 3).min(low_onset.len())]
+            .iter()
+            .fold(0.0f32, |m, &x| m.max(x))
+    };
+    (0..BEATS_PER_BAR).max_by(|&a, &b| {
+        let mean = |phase: usize| {
+            let values: Vec<f32> = frames
+                .iter()
+                .skip(phase)
+                .step_by(BEATS_PER_BAR)
+                .map(|&f| strength_at(f))
+                .collect();
+            values.iter().sum::<f32>() / values.len().max(1) as f32
+        };
+        mean(a).total_cmp(&mean(b))
+    })
+}
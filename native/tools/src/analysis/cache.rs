@@ -0,0 +1,139 @@
+// Persistent analysis results, keyed by file identity so edited files are re-analysed
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::UNIX_EPOCH;
+
+use super::{AudioAnalysis, ANALYSIS_VERSION};
+use crate::utils::Context;
+
+static CACHE: Mutex<Option<Connection>> = Mutex::new(None);
+
+fn lock() -> MutexGuard<'static, Option<Connection>> {
+    CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Identifies one analysis of one version of a file
+pub(super) struct CacheKey {
+    path: String,
+    // Which analysis produced the result, e.g. `head:60`
+    mode: String,
+    size: i64,
+    mtime: i64,
+}
+
+impl CacheKey {
+    /// `None` if the file cannot be stat'ed, such results are not cached
+    pub(super) fn new(path: &Path, mode: String) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        let mtime = meta
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_millis();
+        Some(Self {
+            path: path.to_string_lossy().into_owned(),
+            mode,
+            size: i64::try_from(meta.len()).ok()?,
+            mtime: i64::try_from(mtime).ok()?,
+        })
+    }
+}
+
+pub(super) fn get(key: &CacheKey) -> Option<AudioAnalysis> {
+    let guard = lock();
+    let conn = guard.as_ref()?;
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM analysis_cache
+             WHERE path = ?1 AND mode = ?2 AND size = ?3 AND mtime = ?4 AND version = ?5",
+            params![key.path, key.mode, key.size, key.mtime, ANALYSIS_VERSION],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            println!("[AnalysisCache] Lookup failed: {e}");
+            None
+        });
+    drop(guard);
+    serde_json::from_str(&data?).ok()
+}
+
+pub(super) fn put(key: &CacheKey, analysis: &AudioAnalysis) {
+    let Ok(data) = serde_json::to_string(analysis) else {
+        return;
+    };
+    let guard = lock();
+    let Some(conn) = guard.as_ref() else {
+        return;
+    };
+    let result = conn.execute(
+        "INSERT OR REPLACE INTO analysis_cache (path, mode, size, mtime, version, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            key.path,
+            key.mode,
+            key.size,
+            key.mtime,
+            ANALYSIS_VERSION,
+            data
+        ],
+    );
+    drop(guard);
+    if let Err(e) = result {
+        println!("[AnalysisCache] Store failed: {e}");
+    }
+}
+
+/// Enables the persistent analysis cache in the `SQLite` database at `db_path` (created if
+/// missing), or disables it when `None`. Rows from older analysis versions are dropped.
+#[napi]
+#[allow(clippy::needless_pass_by_value, clippy::missing_errors_doc)]
+pub fn set_analysis_cache(db_path: Option<String>) -> Result<()> {
+    let conn = db_path
+        .map(|db_path| -> Result<Connection> {
+            let conn = Connection::open(db_path).context("Open analysis cache failed")?;
+            conn.execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 CREATE TABLE IF NOT EXISTS analysis_cache (
+                     path TEXT NOT NULL,
+                     mode TEXT NOT NULL,
+                     size INTEGER NOT NULL,
+                     mtime INTEGER NOT NULL,
+                     version INTEGER NOT NULL,
+                     data TEXT NOT NULL,
+                     PRIMARY KEY (path, mode)
+                 );",
+            )
+            .context("Init analysis cache failed")?;
+            conn.execute(
+                "DELETE FROM analysis_cache WHERE version != ?1",
+                [ANALYSIS_VERSION],
+            )
+            .context("Drop stale analysis failed")?;
+            Ok(conn)
+        })
+        .transpose()?;
+    *lock() = conn;
+    Ok(())
+}
+
+/// Removes cached results for `path`, or all of them. Returns the number of removed rows.
+#[napi]
+#[allow(clippy::needless_pass_by_value, clippy::missing_errors_doc)]
+pub fn clear_analysis_cache(path: Option<String>) -> Result<u32> {
+    let guard = lock();
+    let removed = guard.as_ref().map(|conn| {
+        path.map_or_else(
+            || conn.execute("DELETE FROM analysis_cache", []),
+            |path| conn.execute("DELETE FROM analysis_cache WHERE path = ?1", [path]),
+        )
+    });
+    drop(guard);
+    let removed = removed.transpose().context("Clear analysis cache failed")?;
+    Ok(removed.unwrap_or(0) as u32)
+}
@@ -0,0 +1,470 @@
+// Key detection: log-frequency chroma with harmonic summation and reference tuning estimation,
+// Krumhansl-Schmuckler profile correlation and a smoothed key timeline
+
+use napi_derive::napi;
+use num_complex::Complex32;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::pool::Pooled;
+use super::{get_camelot_key, BiquadFilter};
+
+// Audio is decimated to about this rate, the key lives well below 5 kHz
+const TARGET_RATE: u32 = 11_025;
+// ~0.37 s at the target rate, enough to resolve semitones down to C2
+const FRAME_LEN: usize = 4096;
+const HOP_LEN: usize = 2048;
+
+// Pitch range of the log-frequency spectrum, C2 - C7, three bins per semitone
+const MIN_MIDI: f64 = 36.0;
+const MAX_MIDI: f64 = 96.0;
+const BINS_PER_SEMITONE: usize = 3;
+const FINE_BINS: usize = 12 * BINS_PER_SEMITONE;
+const PITCH_BINS: usize = (MAX_MIDI - MIN_MIDI) as usize * BINS_PER_SEMITONE;
+
+// Harmonics 1 - 5 of a note are credited to its fundamental with decaying weight
+const HARMONICS: usize = 5;
+const HARMONIC_DECAY: f32 = 0.6;
+const LOG_COMPRESSION: f32 = 1_000.0;
+// Reference tuning histogram, 2 cents per bin over +-50 cents
+const TUNING_BINS: usize = 50;
+
+// Krumhansl-Schmuckler key profiles, tonic first
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+// Timeline: keys of overlapping windows, smoothed so only lasting changes show up
+const WINDOW_SECS: f64 = 16.0;
+const WINDOW_HOP_SECS: f64 = 4.0;
+const CHANGE_PENALTY: f64 = 0.3;
+
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySegment {
+    pub start: f64,
+    pub end: f64,
+    /// Pitch class of the tonic, 0 = C
+    pub key_root: i32,
+    /// 0 = major, 1 = minor
+    pub key_mode: i32,
+    pub camelot_key: Option<String>,
+    pub confidence: f64,
+}
+
+/// Chroma frames per second
+pub(super) fn chroma_rate(sample_rate: u32) -> f64 {
+    f64::from(sample_rate) / decimation(sample_rate) as f64 / HOP_LEN as f64
+}
+
+fn decimation(sample_rate: u32) -> usize {
+    (sample_rate / TARGET_RATE).max(1) as usize
+}
+
+/// Chroma frames of one analysed segment, before the tuning is known
+#[derive(Default)]
+pub(super) struct ChromaFrames {
+    frames: Pooled<[f32; FINE_BINS]>,
+    tuning: Vec<f32>,
+}
+
+impl ChromaFrames {
+    pub(super) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(super) fn coarsen(&mut self) {
+        self.frames.coarsen();
+    }
+
+    /// Pitch class profile of every frame, without tuning correction
+    pub(super) fn pitch_classes(&self) -> impl Iterator<Item = [f32; 12]> + '_ {
+        self.frames.iter().map(|fine| {
+            std::array::from_fn(|pc| {
+                let center = pc * BINS_PER_SEMITONE;
+                let below = fine[(center + FINE_BINS - 1) % FINE_BINS];
+                0.5f32.mul_add(below + fine[center + 1], fine[center])
+            })
+        })
+    }
+}
+
+/// Streams mono samples into `ChromaFrames`
+pub(super) struct ChromaAnalyzer {
+    decimation: usize,
+    rate: f64,
+    anti_alias: [BiquadFilter; 2],
+    phase: usize,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // The last `FRAME_LEN` decimated samples written twice so a frame never wraps
+    history: Vec<f32>,
+    pos: usize,
+    since_hop: usize,
+    buffer: Vec<Complex32>,
+}
+
+impl ChromaAnalyzer {
+    pub(super) fn new(sample_rate: u32) -> Self {
+        let decimation = decimation(sample_rate);
+        let rate = f64::from(sample_rate) / decimation as f64;
+        let coeffs = low_pass(f64::from(sample_rate), rate * 0.45);
+        let window = (0..FRAME_LEN)
+            .map(|i| {
+                0.5f32.mul_add(
+                    -(2.0 * std::f32::consts::PI * i as f32 / FRAME_LEN as f32).cos(),
+                    0.5,
+                )
+            })
+            .collect();
+        Self {
+            decimation,
+            rate,
+            anti_alias: [BiquadFilter::new(coeffs), BiquadFilter::new(coeffs)],
+            phase: 0,
+            fft: FftPlanner::new().plan_fft_forward(FRAME_LEN),
+            window,
+            history: vec![0.0; FRAME_LEN * 2],
+            pos: 0,
+            since_hop: 0,
+            buffer: vec![Complex32::new(0.0, 0.0); FRAME_LEN],
+        }
+    }
+
+    pub(super) fn process(&mut self, sample: f32, out: &mut ChromaFrames) {
+        let filtered = self
+            .anti_alias
+            .iter_mut()
+            .fold(f64::from(sample), |x, filter| filter.process(x));
+        self.phase += 1;
+        if self.phase < self.decimation {
+            return;
+        }
+        self.phase = 0;
+
+        self.pos = (self.pos + 1) % FRAME_LEN;
+        self.history[self.pos] = filtered as f32;
+        self.history[self.pos + FRAME_LEN] = filtered as f32;
+        self.since_hop += 1;
+        if self.since_hop < HOP_LEN {
+            return;
+        }
+        self.since_hop = 0;
+        self.analyze_frame(out);
+    }
+
+    fn analyze_frame(&mut self, out: &mut ChromaFrames) {
+        let frame = &self.history[self.pos + 1..=self.pos + FRAME_LEN];
+        for ((out, &x), &w) in self.buffer.iter_mut().zip(frame).zip(&self.window) {
+            *out = Complex32::new(x * w, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        let magnitude: Vec<f32> = self.buffer[..FRAME_LEN / 2]
+            .iter()
+            .map(|c| c.norm() / FRAME_LEN as f32)
+            .collect();
+
+        if out.tuning.is_empty() {
+            out.tuning = vec![0.0; TUNING_BINS];
+        }
+        let bin_hz = self.rate / FRAME_LEN as f64;
+        let mut pitch = vec![0.0f32; PITCH_BINS];
+        for k in 1..magnitude.len() - 1 {
+            let midi = hz_to_midi(k as f64 * bin_hz);
+            if !(MIN_MIDI..MAX_MIDI - 1.0).contains(&midi) {
+                continue;
+            }
+            // Linear interpolation between the two nearest pitch bins
+            let position = (midi - MIN_MIDI) * BINS_PER_SEMITONE as f64;
+            let lower = position as usize;
+            let frac = (position - lower as f64) as f32;
+            pitch[lower] += magnitude[k] * (1.0 - frac);
+            pitch[lower + 1] += magnitude[k] * frac;
+
+            // Spectral peaks vote for the reference tuning
+            let (prev, here, next) = (magnitude[k - 1], magnitude[k], magnitude[k + 1]);
+            if here > prev && here >= next && here > 1e-4 {
+                let curvature = 2.0f32.mul_add(-here, prev + next);
+                let shift = if curvature < 0.0 {
+                    0.5 * (prev - next) / curvature
+                } else {
+                    0.0
+                };
+                let deviation = hz_to_midi((k as f64 + f64::from(shift)) * bin_hz);
+                let cents = deviation - deviation.round();
+                let bin = ((cents + 0.5) * TUNING_BINS as f64) as usize % TUNING_BINS;
+                out.tuning[bin] += here;
+            }
+        }
+
+        for x in &mut pitch {
+            *x = (LOG_COMPRESSION * *x).ln_1p();
+        }
+        // Every bin also collects its harmonics, which strengthens fundamentals
+        let mut fine = [0.0f32; FINE_BINS];
+        for q in 0..PITCH_BINS {
+            fine[q % FINE_BINS] += (1..=HARMONICS)
+                .filter_map(|h| {
+                    let offset = (FINE_BINS as f64 * (h as f64).log2()).round() as usize;
+                    let weight = HARMONIC_DECAY.powi(h as i32 - 1);
+                    pitch.get(q + offset).map(|p| p * weight)
+                })
+                .sum::<f32>();
+        }
+        out.frames.push(fine);
+    }
+}
+
+// RBJ cookbook Butterworth low pass
+pub(super) fn low_pass(sample_rate: f64, cutoff: f64) -> [f64; 5] {
+    let w0 = 2.0 * PI * cutoff / sample_rate;
+    let alpha = w0.sin() / 2.0f64.sqrt();
+    let cos = w0.cos();
+    let a0 = 1.0 + alpha;
+    [
+        (1.0 - cos) / 2.0 / a0,
+        (1.0 - cos) / a0,
+        (1.0 - cos) / 2.0 / a0,
+        -2.0 * cos / a0,
+        (1.0 - alpha) / a0,
+    ]
+}
+
+pub(super) fn hz_to_midi(hz: f64) -> f64 {
+    12.0f64.mul_add((hz / 440.0).log2(), 69.0)
+}
+
+pub(super) struct KeyEstimate {
+    pub root: i32,
+    pub mode: i32,
+    /// 0.0 - 1.0, margin of the best key over the runner-up
+    pub confidence: f64,
+}
+
+/// Estimates the key over all `segments` (frames and the time of their first frame) and a
+/// timeline of key changes within them
+pub(super) fn detect_key(
+    segments: &[(&ChromaFrames, f64)],
+    frame_rate: f64,
+) -> (Option<KeyEstimate>, Vec<KeySegment>) {
+    // Deviation from A440 in semitones, the weighted circular mean of all peak votes
+    let (sin, cos) = segments
+        .iter()
+        .flat_map(|(chroma, _)| chroma.tuning.iter().enumerate())
+        .fold((0.0, 0.0), |(sin, cos), (bin, &weight)| {
+            let angle = 2.0 * PI * (bin as f64 + 0.5) / TUNING_BINS as f64;
+            (
+                f64::from(weight).mul_add(angle.sin(), sin),
+                f64::from(weight).mul_add(angle.cos(), cos),
+            )
+        });
+    let tuning = if sin == 0.0 && cos == 0.0 {
+        0.0
+    } else {
+        (sin.atan2(cos) / (2.0 * PI)).rem_euclid(1.0) - 0.5
+    };
+
+    let chroma_of = |frames: &[[f32; FINE_BINS]]| fold_chroma(frames, tuning);
+
+    let all: Vec<[f32; FINE_BINS]> = segments
+        .iter()
+        .flat_map(|(chroma, _)| chroma.frames.iter().copied())
+        .collect();
+    let estimate = chroma_of(&all).and_then(|chroma| best_key(&chroma));
+
+    let window = (WINDOW_SECS * frame_rate).round().max(1.0) as usize;
+    let hop = (WINDOW_HOP_SECS * frame_rate).round().max(1.0) as usize;
+    let timeline = segments
+        .iter()
+        .flat_map(|(chroma, start)| {
+            let frames = &chroma.frames;
+            let windows: Vec<(usize, [f64; 24])> = (0..=frames.len().saturating_sub(window))
+                .step_by(hop)
+                .filter(|_| !frames.is_empty())
+                .filter_map(|w| {
+                    let chroma = chroma_of(&frames[w..(w + window).min(frames.len())])?;
+                    Some((w, correlations(&chroma)))
+                })
+                .collect();
+            let end = start + frames.len() as f64 / frame_rate;
+            smooth_timeline(&windows, *start, end, window, frame_rate)
+        })
+        .collect();
+
+    (estimate, timeline)
+}
+
+// Sums the frames and folds the fine bins onto the 12 pitch classes, centred on the tuning
+fn fold_chroma(frames: &[[f32; FINE_BINS]], tuning: f64) -> Option<[f64; 12]> {
+    let mut fine = [0.0f64; FINE_BINS];
+    for frame in frames {
+        for (sum, &x) in fine.iter_mut().zip(frame) {
+            *sum += f64::from(x);
+        }
+    }
+    let at = |position: f64| {
+        let position = position.rem_euclid(FINE_BINS as f64);
+        let lower = position as usize % FINE_BINS;
+        let frac = position - position.floor();
+        (fine[(lower + 1) % FINE_BINS] - fine[lower]).mul_add(frac, fine[lower])
+    };
+    let mut chroma = [0.0; 12];
+    for (pc, value) in chroma.iter_mut().enumerate() {
+        let center = tuning.mul_add(BINS_PER_SEMITONE as f64, (pc * BINS_PER_SEMITONE) as f64);
+        *value = 0.5f64.mul_add(at(center - 1.0) + at(center + 1.0), at(center));
+    }
+    chroma.iter().any(|&x| x > 0.0).then_some(chroma)
+}
+
+// Pearson correlation with all 24 rotated profiles, majors first
+fn correlations(chroma: &[f64; 12]) -> [f64; 24] {
+    let mut result = [0.0; 24];
+    for (i, r) in result.iter_mut().enumerate() {
+        let (profile, root) = if i < 12 {
+            (&MAJOR_PROFILE, i)
+        } else {
+            (&MINOR_PROFILE, i - 12)
+        };
+        *r = pearson(chroma, |pc| profile[(pc + 12 - root) % 12]);
+    }
+    result
+}
+
+fn pearson(chroma: &[f64; 12], profile: impl Fn(usize) -> f64) -> f64 {
+    let mean_c = chroma.iter().sum::<f64>() / 12.0;
+    let mean_p = (0..12).map(&profile).sum::<f64>() / 12.0;
+    let (mut cov, mut var_c, mut var_p) = (0.0, 0.0, 0.0);
+    for (pc, &c) in chroma.iter().enumerate() {
+        let (dc, dp) = (c - mean_c, profile(pc) - mean_p);
+        cov = dc.mul_add(dp, cov);
+        var_c = dc.mul_add(dc, var_c);
+        var_p = dp.mul_add(dp, var_p);
+    }
+    if var_c > 0.0 {
+        cov / (var_c * var_p).sqrt()
+    } else {
+        0.0
+    }
+}
+
+const fn key_of(index: usize) -> (i32, i32) {
+    ((index % 12) as i32, (index / 12) as i32)
+}
+
+// Relative margin of the best correlation over the runner-up
+fn margin(scores: &[f64; 24], best: usize) -> f64 {
+    let runner_up = scores
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != best)
+        .map(|(_, &s)| s)
+        .fold(f64::MIN, f64::max);
+    if scores[best] > 0.0 {
+        ((scores[best] - runner_up) / scores[best]).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+fn best_key(chroma: &[f64; 12]) -> Option<KeyEstimate> {
+    let scores = correlations(chroma);
+    let (best, &score) = scores
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let (root, mode) = key_of(best);
+    (score > 0.0).then(|| KeyEstimate {
+        root,
+        mode,
+        confidence: margin(&scores, best),
+    })
+}
+
+// Viterbi over the windows: a key change costs `CHANGE_PENALTY` of correlation
+fn smooth_timeline(
+    windows: &[(usize, [f64; 24])],
+    start: f64,
+    end: f64,
+    window: usize,
+    frame_rate: f64,
+) -> Vec<KeySegment> {
+    let Some((_, first)) = windows.first() else {
+        return Vec::new();
+    };
+    let mut score = *first;
+    let mut backlinks: Vec<[usize; 24]> = Vec::with_capacity(windows.len());
+    for (_, scores) in &windows[1..] {
+        let (best_prev, best_prev_score) = score
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map_or((0, 0.0), |(i, &s)| (i, s));
+        let mut links = [0; 24];
+        let mut next = [0.0; 24];
+        for key in 0..24 {
+            let (prev, carried) = if score[key] >= best_prev_score - CHANGE_PENALTY {
+                (key, score[key])
+            } else {
+                (best_prev, best_prev_score - CHANGE_PENALTY)
+            };
+            links[key] = prev;
+            next[key] = carried + scores[key];
+        }
+        backlinks.push(links);
+        score = next;
+    }
+
+    let mut key = (0..24)
+        .max_by(|&a, &b| score[a].total_cmp(&score[b]))
+        .unwrap_or(0);
+    let mut path = vec![key];
+    for links in backlinks.iter().rev() {
+        key = links[key];
+        path.push(key);
+    }
+    path.reverse();
+
+    // A change is placed in the middle of the first window that hears the new key
+    let half_window = window as f64 / frame_rate / 2.0;
+    let mut segments: Vec<KeySegment> = Vec::new();
+    let mut confidence_sum = 0.0;
+    let mut count = 0.0;
+    for (i, (&key, (frame, scores))) in path.iter().zip(windows).enumerate() {
+        let (root, mode) = key_of(key);
+        let continues = segments
+            .last()
+            .is_some_and(|s| s.key_root == root && s.key_mode == mode);
+        if !continues {
+            let boundary = if i == 0 {
+                start
+            } else {
+                (start + *frame as f64 / frame_rate + half_window).min(end)
+            };
+            if let Some(last) = segments.last_mut() {
+                last.end = boundary;
+                last.confidence = confidence_sum / count;
+            }
+            segments.push(KeySegment {
+                start: boundary,
+                end,
+                key_root: root,
+                key_mode: mode,
+                camelot_key: get_camelot_key(root, mode),
+                confidence: 0.0,
+            });
+            confidence_sum = 0.0;
+            count = 0.0;
+        }
+        confidence_sum += margin(scores, key);
+        count += 1.0;
+    }
+    if let Some(last) = segments.last_mut() {
+        last.confidence = confidence_sum / count;
+    }
+    segments
+}
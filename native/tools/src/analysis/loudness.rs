@@ -0,0 +1,299 @@
+// ITU-R BS.1770-4 / EBU R128 loudness: gated integrated loudness, loudness range (EBU Tech
+// 3342), momentary / short-term maxima and true peak
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use super::BiquadFilter;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+const LRA_RELATIVE_GATE_LU: f64 = -20.0;
+const LRA_LOW_PERCENTILE: f64 = 0.10;
+const LRA_HIGH_PERCENTILE: f64 = 0.95;
+
+// Energy is accumulated in 100 ms steps, blocks are made of consecutive steps
+const STEPS_PER_SECOND: u32 = 10;
+const MOMENTARY_STEPS: usize = 4; // 400 ms, 75% overlap
+const SHORT_TERM_STEPS: usize = 30; // 3 s
+
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+
+// Surround channels count 1.41 times (+1.5 dB). Only the 5.1 layout is recognized, where the
+// 4th channel is the LFE and not measured at all
+const SURROUND_WEIGHT: f64 = 1.41;
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    10.0f64.mul_add(energy.log10(), -0.691)
+}
+
+fn lufs_to_energy(lufs: f64) -> f64 {
+    10.0f64.powf((lufs + 0.691) / 10.0)
+}
+
+// K-weighting (high shelf + RLB high pass) for any sample rate, derived from the analog
+// prototypes so rates other than 48 kHz are measured correctly
+fn k_weighting(sample_rate: u32) -> ([f64; 5], [f64; 5]) {
+    let rate = f64::from(sample_rate);
+
+    let f0 = 1_681.974_450_955_533;
+    let gain_db = 3.999_843_853_973_347;
+    let q = 0.707_175_236_955_419_6;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10.0f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = [
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    ];
+
+    let f0 = 38.135_470_876_024_44;
+    let q = 0.500_327_037_323_877_3;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = [
+        1.0,
+        -2.0,
+        1.0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    ];
+
+    (shelf, high_pass)
+}
+
+const fn channel_weight(index: usize, channels: usize) -> f64 {
+    match (channels, index) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) => SURROUND_WEIGHT,
+        _ => 1.0,
+    }
+}
+
+/// Oversampling peak meter, 4x below 96 kHz and 2x below 192 kHz as BS.1770-4 asks
+struct TruePeakMeter {
+    // One windowed-sinc interpolation filter per output phase
+    phases: Vec<[f64; TRUE_PEAK_TAPS_PER_PHASE]>,
+    // Largest possible ratio between an interpolated value and the window's peak
+    max_gain: f64,
+    // Per channel, the last samples written twice so a window never wraps
+    history: Vec<[f64; TRUE_PEAK_TAPS_PER_PHASE * 2]>,
+    pos: usize,
+    true_peak: f64,
+    sample_peak: f64,
+}
+
+impl TruePeakMeter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let factor = match sample_rate {
+            0..96_000 => 4,
+            96_000..192_000 => 2,
+            _ => 1,
+        };
+        let len = factor * TRUE_PEAK_TAPS_PER_PHASE;
+        let center = (len - 1) as f64 / 2.0;
+        let coeff = |n: usize| {
+            let x = (n as f64 - center) / factor as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5f64.mul_add(-(2.0 * PI * (n as f64 + 0.5) / len as f64).cos(), 0.5);
+            sinc * window
+        };
+        let phases = (0..factor)
+            .map(|phase| {
+                let mut taps = [0.0; TRUE_PEAK_TAPS_PER_PHASE];
+                for (k, tap) in taps.iter_mut().enumerate() {
+                    *tap = coeff(phase + k * factor);
+                }
+                // Unity gain per phase, a DC signal must not read above its level
+                let sum: f64 = taps.iter().sum();
+                for tap in &mut taps {
+                    *tap /= sum;
+                }
+                taps
+            })
+            .collect::<Vec<_>>();
+        let max_gain = phases
+            .iter()
+            .map(|taps| taps.iter().map(|t| t.abs()).sum::<f64>())
+            .fold(1.0, f64::max);
+        Self {
+            phases,
+            max_gain,
+            history: vec![[0.0; TRUE_PEAK_TAPS_PER_PHASE * 2]; channels],
+            pos: 0,
+            true_peak: 0.0,
+            sample_peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, frame: &[f32]) {
+        self.pos = (self.pos + 1) % TRUE_PEAK_TAPS_PER_PHASE;
+        for (history, &sample) in self.history.iter_mut().zip(frame) {
+            let sample = f64::from(sample);
+            self.sample_peak = self.sample_peak.max(sample.abs());
+
+            history[self.pos] = sample;
+            history[self.pos + TRUE_PEAK_TAPS_PER_PHASE] = sample;
+            // Newest sample last
+            let window = &history[self.pos + 1..=self.pos + TRUE_PEAK_TAPS_PER_PHASE];
+            // Interpolating cannot beat the current peak, skip the filter
+            let window_peak = window.iter().fold(0.0, |peak: f64, x| peak.max(x.abs()));
+            if window_peak * self.max_gain <= self.true_peak {
+                continue;
+            }
+            for taps in &self.phases {
+                let value: f64 = taps
+                    .iter()
+                    .zip(window.iter().rev())
+                    .map(|(t, x)| t * x)
+                    .sum();
+                self.true_peak = self.true_peak.max(value.abs());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct LoudnessSummary {
+    /// Gated integrated loudness in LUFS, `None` if everything is below the absolute gate
+    pub integrated: Option<f64>,
+    /// Loudness range in LU
+    pub range: Option<f64>,
+    pub momentary_max: Option<f64>,
+    pub short_term_max: Option<f64>,
+    /// Linear, 1.0 is full scale
+    pub true_peak: f64,
+}
+
+pub(super) struct LoudnessMeter {
+    filters: Vec<(BiquadFilter, BiquadFilter)>,
+    weights: Vec<f64>,
+    step_len: usize,
+    step_samples: usize,
+    step_energy: f64,
+    // Mean square of the most recent steps, enough for one short-term block
+    steps: VecDeque<f64>,
+    momentary: Vec<f64>,
+    short_term: Vec<f64>,
+    true_peak: TruePeakMeter,
+}
+
+impl LoudnessMeter {
+    pub(super) fn new(channels: usize, sample_rate: u32) -> Self {
+        let (shelf, high_pass) = k_weighting(sample_rate);
+        Self {
+            filters: (0..channels)
+                .map(|_| (BiquadFilter::new(shelf), BiquadFilter::new(high_pass)))
+                .collect(),
+            weights: (0..channels).map(|i| channel_weight(i, channels)).collect(),
+            step_len: (sample_rate / STEPS_PER_SECOND).max(1) as usize,
+            step_samples: 0,
+            step_energy: 0.0,
+            steps: VecDeque::with_capacity(SHORT_TERM_STEPS),
+            momentary: Vec::new(),
+            short_term: Vec::new(),
+            true_peak: TruePeakMeter::new(channels, sample_rate),
+        }
+    }
+
+    /// Feeds one frame, one sample per channel
+    pub(super) fn process(&mut self, frame: &[f32]) {
+        for ((filters, &weight), &sample) in self.filters.iter_mut().zip(&self.weights).zip(frame) {
+            let s = filters.1.process(filters.0.process(f64::from(sample)));
+            self.step_energy += weight * s * s;
+        }
+        self.true_peak.process(frame);
+
+        self.step_samples += 1;
+        if self.step_samples == self.step_len {
+            self.finish_step();
+        }
+    }
+
+    fn finish_step(&mut self) {
+        if self.steps.len() == SHORT_TERM_STEPS {
+            self.steps.pop_front();
+        }
+        self.steps
+            .push_back(self.step_energy / self.step_len as f64);
+        self.step_samples = 0;
+        self.step_energy = 0.0;
+
+        let mean_of_last = |n: usize| self.steps.iter().rev().take(n).sum::<f64>() / n as f64;
+        if self.steps.len() >= MOMENTARY_STEPS {
+            self.momentary.push(mean_of_last(MOMENTARY_STEPS));
+        }
+        if self.steps.len() >= SHORT_TERM_STEPS {
+            self.short_term.push(mean_of_last(SHORT_TERM_STEPS));
+        }
+    }
+
+    pub(super) fn summary(&self) -> LoudnessSummary {
+        let integrated = integrated(&self.momentary);
+
+        let range = gate(&self.short_term, LRA_RELATIVE_GATE_LU).map(|blocks| {
+            let mut loudness: Vec<f64> = blocks.into_iter().map(energy_to_lufs).collect();
+            loudness.sort_by(f64::total_cmp);
+            let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+            percentile(LRA_HIGH_PERCENTILE) - percentile(LRA_LOW_PERCENTILE)
+        });
+
+        let max_lufs = |blocks: &[f64]| {
+            blocks
+                .iter()
+                .copied()
+                .filter(|&e| e > 0.0)
+                .max_by(f64::total_cmp)
+                .map(energy_to_lufs)
+        };
+
+        LoudnessSummary {
+            integrated,
+            range,
+            momentary_max: max_lufs(&self.momentary),
+            short_term_max: max_lufs(&self.short_term),
+            true_peak: self.true_peak.true_peak.max(self.true_peak.sample_peak),
+        }
+    }
+}
+
+/// Integrated loudness of several tracks as if they were played back to back, used for
+/// album gain
+pub(super) fn combined_integrated<'a>(
+    meters: impl IntoIterator<Item = &'a LoudnessMeter>,
+) -> Option<f64> {
+    let blocks: Vec<f64> = meters
+        .into_iter()
+        .flat_map(|meter| meter.momentary.iter().copied())
+        .collect();
+    integrated(&blocks)
+}
+
+fn integrated(momentary: &[f64]) -> Option<f64> {
+    gate(momentary, RELATIVE_GATE_LU)
+        .map(|blocks| energy_to_lufs(blocks.iter().sum::<f64>() / blocks.len() as f64))
+}
+
+// Blocks above the absolute gate and `relative_lu` below their mean, `None` if none are left
+fn gate(blocks: &[f64], relative_lu: f64) -> Option<Vec<f64>> {
+    let absolute = lufs_to_energy(ABSOLUTE_GATE_LUFS);
+    let above_absolute: Vec<f64> = blocks.iter().copied().filter(|&e| e > absolute).collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+    let mean = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+    let relative = mean * 10.0f64.powf(relative_lu / 10.0);
+    let gated: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|&e| e > relative)
+        .collect();
+    (!gated.is_empty()).then_some(gated)
+}
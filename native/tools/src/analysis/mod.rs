@@ -8,23 +8,60 @@
     clippy::needless_continue
 )]
 
+use napi::bindgen_prelude::Either;
 use napi_derive::napi;
-use num_complex::Complex32;
-use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::path::PathBuf;
-use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::{Decoder, DecoderOptions};
-use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions};
+use symphonia::core::formats::{FormatOptions, FormatReader};
 use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
-use symphonia::core::sample::i24;
-use symphonia::core::units::Time;
+
+mod beat;
+mod cache;
+mod key;
+mod loudness;
+mod pool;
+mod replaygain;
+mod scheduler;
+mod spectrogram;
+mod structure;
+mod task;
+mod vocals;
+mod waveform;
+
+use beat::{BeatTrack, OnsetDetector};
+pub use cache::{clear_analysis_cache, set_analysis_cache};
+pub use key::KeySegment;
+use key::{ChromaAnalyzer, ChromaFrames};
+use loudness::LoudnessMeter;
+use pool::Pooled;
+pub use replaygain::{
+    compute_replaygain, ReplayGainOptions, ReplayGainProgress, ReplayGainResult, ReplayGainTrack,
+};
+pub use scheduler::{AnalysisJob, AnalysisJobResult, AnalysisScheduler, AnalysisSchedulerOptions};
+pub use spectrogram::{
+    render_spectrogram, SpectrogramColorMap, SpectrogramOptions, SpectrogramView, SpectrogramWindow,
+};
+pub use structure::{Section, SectionLabel};
+use task::ProgressCallback;
+pub use task::{AnalysisProgress, AnalysisTask};
+pub use vocals::VocalSegment;
+use vocals::{VocalAnalyzer, VocalFrames};
+pub use waveform::{
+    decode_waveform, encode_waveform, generate_waveform, Waveform, WaveformLevel, WaveformPeaks,
+};
 
 // --- Data Structures (API) ---
 
 #[napi(object)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AudioAnalysis {
     pub duration: f64,
     pub bpm: Option<f64>,
@@ -36,7 +73,19 @@ pub struct AudioAnalysis {
     pub fade_out_pos: f64,
     #[napi(js_name = "first_beat_pos")]
     pub first_beat_pos: Option<f64>,
-    pub loudness: Option<f64>, // LUFS
+    #[napi(js_name = "downbeat_pos")]
+    pub downbeat_pos: Option<f64>, // First beat of a bar, assumes 4/4
+    #[napi(js_name = "beat_grid")]
+    pub beat_grid: Option<Vec<f64>>, // Tracked beats of the analysed parts, since version 15
+    pub loudness: Option<f64>, // Gated integrated loudness, LUFS
+    #[napi(js_name = "loudness_range")]
+    pub loudness_range: Option<f64>, // LU
+    #[napi(js_name = "momentary_max")]
+    pub momentary_max: Option<f64>, // LUFS
+    #[napi(js_name = "short_term_max")]
+    pub short_term_max: Option<f64>, // LUFS
+    #[napi(js_name = "true_peak")]
+    pub true_peak: Option<f64>, // dBTP
     #[napi(js_name = "drop_pos")]
     pub drop_pos: Option<f64>, // Chorus/Drop start
     pub version: i32,
@@ -70,6 +119,11 @@ pub struct AudioAnalysis {
     pub key_confidence: Option<f64>,
     #[napi(js_name = "camelot_key")]
     pub camelot_key: Option<String>,
+    #[napi(js_name = "key_timeline")]
+    pub key_timeline: Option<Vec<KeySegment>>, // Key changes, since version 16
+    pub sections: Option<Vec<Section>>, // Song structure, since version 17
+    #[napi(js_name = "vocal_segments")]
+    pub vocal_segments: Option<Vec<VocalSegment>>, // Sung passages, since version 18
 }
 
 #[napi(object)]
@@ -119,35 +173,19 @@ pub struct AdvancedTransition {
 // --- Constants ---
 
 const ENV_RATE: f64 = 50.0;
+// Envelope frames a segment may hold (20 minutes) before its features are pooled to half the
+// resolution, only full-track analysis gets that far
+const MAX_ENV_FRAMES: usize = 60_000;
 const WINDOW_SIZE_MS: usize = 20;
-const ANALYSIS_VERSION: i32 = 13;
+const ANALYSIS_VERSION: i32 = 18;
 const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// Progress interval of full-track analysis when the duration is unknown
+const PROGRESS_STEP_SECS: f64 = 5.0;
 
-// Key Detection Constants
-const FFT_FRAME_SIZE: usize = 4096;
-const FFT_STEP: usize = 1024;
-const MIN_FREQ: f32 = 80.0;
-const MAX_FREQ: f32 = 5000.0;
-const HAMMING_ALPHA: f32 = 0.54;
-const HAMMING_BETA: f32 = 0.46;
-
-// BPM Detection Constants
-const BPM_MIN_LAG: usize = 15; // ~180 BPM at 50Hz
-const BPM_MAX_LAG: usize = 55; // ~55 BPM at 50Hz
+// Envelope Analysis Constants
 const SILENCE_THRESH_DB: f32 = -48.0;
-const VOCAL_LPF_FREQ: f32 = 3000.0;
-const VOCAL_HPF_FREQ: f32 = 200.0;
-const LOW_LPF_FREQ: f32 = 150.0;
-
-// Filter Coefficients (ITU-R BS.1770-4)
-const LOUDNESS_PRE_FILTER: [f64; 5] = [
-    1.53512485958697,
-    -2.69169618940638,
-    1.19839281085285,
-    -1.69065929318241,
-    0.73248077421585,
-];
-const LOUDNESS_RLB_FILTER: [f64; 5] = [1.0, -2.0, 1.0, -1.99004745483398, 0.99007225036621];
+// Mix points only snap to bar lines when the beat is at least this periodic
+const MIN_BEAT_CONFIDENCE: f64 = 0.2;
 
 // --- DSP Filters ---
 
@@ -182,107 +220,6 @@ impl BiquadFilter {
     }
 }
 
-// Generic First Order Filter (LPF/HPF)
-struct FirstOrderFilter {
-    prev_x: f32,
-    prev_y: f32,
-    alpha: f32,
-    is_high_pass: bool,
-}
-
-impl FirstOrderFilter {
-    fn new(sample_rate: u32, cutoff: f32, is_high_pass: bool) -> Self {
-        let dt = 1.0 / sample_rate as f32;
-        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
-        let alpha = if is_high_pass {
-            rc / (rc + dt)
-        } else {
-            dt / (rc + dt)
-        };
-        Self {
-            prev_x: 0.0,
-            prev_y: 0.0,
-            alpha,
-            is_high_pass,
-        }
-    }
-
-    fn process(&mut self, x: f32) -> f32 {
-        let y = if self.is_high_pass {
-            self.alpha * (self.prev_y + x - self.prev_x)
-        } else {
-            self.alpha.mul_add(x - self.prev_y, self.prev_y)
-        };
-        self.prev_x = x;
-        self.prev_y = y;
-        y
-    }
-}
-
-struct VocalFilter {
-    lpf: FirstOrderFilter,
-    hpf: FirstOrderFilter,
-}
-
-impl VocalFilter {
-    fn new(sample_rate: u32) -> Self {
-        Self {
-            lpf: FirstOrderFilter::new(sample_rate, VOCAL_LPF_FREQ, false),
-            hpf: FirstOrderFilter::new(sample_rate, VOCAL_HPF_FREQ, true),
-        }
-    }
-    fn process(&mut self, x: f32) -> f32 {
-        self.lpf.process(self.hpf.process(x))
-    }
-}
-
-struct LoudnessMeter {
-    pre_filter: Vec<BiquadFilter>,
-    rlb_filter: Vec<BiquadFilter>,
-    sum_sq: f64,
-    count: u64,
-}
-
-impl LoudnessMeter {
-    fn new(channels: usize) -> Self {
-        let mut pre_filter = Vec::with_capacity(channels);
-        let mut rlb_filter = Vec::with_capacity(channels);
-        for _ in 0..channels {
-            pre_filter.push(BiquadFilter::new(LOUDNESS_PRE_FILTER));
-            rlb_filter.push(BiquadFilter::new(LOUDNESS_RLB_FILTER));
-        }
-        Self {
-            pre_filter,
-            rlb_filter,
-            sum_sq: 0.0,
-            count: 0,
-        }
-    }
-
-    fn process(&mut self, channels: &[f32]) {
-        for (i, &sample) in channels.iter().enumerate() {
-            if i >= self.pre_filter.len() {
-                break;
-            }
-            let s = self.rlb_filter[i].process(self.pre_filter[i].process(f64::from(sample)));
-            self.sum_sq += s * s;
-        }
-        self.count += 1;
-    }
-
-    fn get_lufs(&self) -> f64 {
-        if self.count == 0 {
-            return -70.0;
-        }
-        let mean_sq = self.sum_sq / self.count as f64;
-        if mean_sq <= 0.0 {
-            -70.0
-        } else {
-            10.0f64.mul_add(mean_sq.log10(), -0.691)
-        }
-    }
-}
-
 // --- Analysis Core ---
 
 struct EnvelopeAccumulator {
@@ -316,9 +253,137 @@ impl EnvelopeAccumulator {
 
 #[derive(Default)]
 struct AnalysisSegment {
-    envelope: Vec<f32>,
-    low_envelope: Vec<f32>,
-    vocal_ratio: Vec<f32>,
+    envelope: Pooled<f32>,
+    onset: Pooled<f32>,
+    low_onset: Pooled<f32>,
+    band_energy: Pooled<[f32; beat::BAND_COUNT]>,
+    chroma: ChromaFrames,
+    vocal: VocalFrames,
+}
+
+impl AnalysisSegment {
+    // How many feature frames each stored one stands for, the same for every kind of frame
+    const fn stride(&self) -> usize {
+        self.envelope.stride()
+    }
+
+    fn coarsen(&mut self) {
+        self.envelope.coarsen();
+        self.onset.coarsen();
+        self.low_onset.coarsen();
+        self.band_energy.coarsen();
+        self.chroma.coarsen();
+        self.vocal.coarsen();
+    }
+}
+
+// Default track of a file, ready for decoding
+struct OpenTrack {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    params: CodecParameters,
+}
+
+impl OpenTrack {
+    fn open(path: &Path) -> Option<Self> {
+        let src = File::open(path).ok()?;
+        let mss = MediaSourceStream::new(Box::new(src), MediaSourceStreamOptions::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .ok()?;
+        let format = probed.format;
+        let track = format.default_track()?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .ok()?;
+
+        Some(Self {
+            format,
+            decoder,
+            track_id,
+            params,
+        })
+    }
+
+    // Decodes the track into interleaved f32 samples. `on_buffer` gets the start time of every
+    // buffer in seconds, its channel count and samples, and stops decoding by breaking.
+    // Packets the decoder rejects are skipped. Returns how many frames were decoded, `None` if
+    // `on_buffer` stopped early or nothing could be decoded.
+    fn decode(
+        &mut self,
+        mut on_buffer: impl FnMut(f64, usize, &[f32]) -> ControlFlow<()>,
+    ) -> Option<u64> {
+        let time_base = self.params.time_base;
+        let sample_rate = self.params.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        let mut samples: Option<SampleBuffer<f32>> = None;
+        let mut decoded_frames: u64 = 0;
+        while let Ok(packet) = self.format.next_packet() {
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let Ok(decoded) = self.decoder.decode(&packet) else {
+                continue;
+            };
+
+            // Fall back to counting frames if the container has no time base
+            let time = time_base.map_or_else(
+                || decoded_frames as f64 / f64::from(sample_rate),
+                |tb| {
+                    let t = tb.calc_time(packet.ts());
+                    t.seconds as f64 + t.frac
+                },
+            );
+            let spec = *decoded.spec();
+            let frame_len = spec.channels.count().max(1);
+            if samples
+                .as_ref()
+                .is_none_or(|buf| buf.capacity() < decoded.capacity() * frame_len)
+            {
+                samples = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let Some(buf) = samples.as_mut() else {
+                continue;
+            };
+            buf.copy_interleaved_ref(decoded);
+            decoded_frames += (buf.samples().len() / frame_len) as u64;
+            if on_buffer(time, frame_len, buf.samples()).is_break() {
+                return None;
+            }
+        }
+        Some(decoded_frames).filter(|&n| n > 0)
+    }
+}
+
+// Per-segment filter and envelope state, fresh for head and tail
+struct SegmentState {
+    acc_env: EnvelopeAccumulator,
+    onsets: OnsetDetector,
+    chroma: ChromaAnalyzer,
+    vocal: VocalAnalyzer,
+}
+
+impl SegmentState {
+    fn new(sample_rate: u32, window_size: usize) -> Self {
+        Self {
+            acc_env: EnvelopeAccumulator::new(window_size),
+            onsets: OnsetDetector::new(sample_rate),
+            chroma: ChromaAnalyzer::new(sample_rate),
+            vocal: VocalAnalyzer::new(sample_rate),
+        }
+    }
 }
 
 struct TrackAnalyzer {
@@ -328,11 +393,17 @@ struct TrackAnalyzer {
 
     head: AnalysisSegment,
     tail: AnalysisSegment,
-    head_pcm: Vec<f32>, // For key detection
 
     duration: f64,
+    estimated_duration: Option<f64>,
     sample_rate: u32,
 
+    // Full-track mode: the head covers the whole file
+    full_track: bool,
+    cancelled: Option<Arc<AtomicBool>>,
+    on_progress: Option<ProgressCallback>,
+    last_progress_step: i64,
+
     // Internal state
     loudness_meter: LoudnessMeter,
 }
@@ -345,36 +416,89 @@ impl TrackAnalyzer {
             include_tail,
             head: AnalysisSegment::default(),
             tail: AnalysisSegment::default(),
-            head_pcm: Vec::new(),
             duration: 0.0,
+            estimated_duration: None,
             sample_rate: DEFAULT_SAMPLE_RATE,
-            loudness_meter: LoudnessMeter::new(2), // Re-init on analyze
+            full_track: false,
+            cancelled: None,
+            on_progress: None,
+            last_progress_step: -1,
+            loudness_meter: LoudnessMeter::new(2, DEFAULT_SAMPLE_RATE), // Re-init on analyze
+        }
+    }
+
+    // Streams through the entire file, every field covers the whole track. Features of very long
+    // files are kept at a coarser resolution.
+    fn full_track(
+        path: String,
+        cancelled: Arc<AtomicBool>,
+        on_progress: Option<ProgressCallback>,
+    ) -> Self {
+        Self {
+            full_track: true,
+            cancelled: Some(cancelled),
+            on_progress,
+            ..Self::new(path, None, true)
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+            .as_ref()
+            .is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+
+    // At most once per percent, or every few seconds of audio if the duration is unknown
+    fn report_progress(&mut self, position: f64, force: bool) {
+        let Some(callback) = self.on_progress.as_mut() else {
+            return;
+        };
+        let percent = self
+            .estimated_duration
+            .filter(|&d| d > 0.0)
+            .map(|d| (position / d).clamp(0.0, 1.0));
+        let step = percent.map_or(position / PROGRESS_STEP_SECS, |p| p * 100.0) as i64;
+        if step <= self.last_progress_step && !force {
+            return;
+        }
+        self.last_progress_step = step;
+        callback(AnalysisProgress {
+            position,
+            duration: self.estimated_duration,
+            percent,
+        });
+    }
+
+    // Results of different modes are cached separately
+    fn cache_mode(&self) -> String {
+        if self.full_track {
+            "full".to_string()
+        } else if self.include_tail {
+            format!("head_tail:{}", self.max_analyze_time)
+        } else {
+            format!("head:{}", self.max_analyze_time)
         }
     }
 
     fn analyze(&mut self) -> Option<AudioAnalysis> {
-        let src = File::open(&self.path).ok()?;
-        let mss = MediaSourceStream::new(Box::new(src), MediaSourceStreamOptions::default());
-        let mut hint = Hint::new();
-        if let Some(ext) = self.path.extension().and_then(|s| s.to_str()) {
-            hint.with_extension(ext);
+        let key = cache::CacheKey::new(&self.path, self.cache_mode());
+        if let Some(cached) = key.as_ref().and_then(cache::get) {
+            return Some(cached);
         }
+        let analysis = self.analyze_uncached()?;
+        if let Some(key) = &key {
+            cache::put(key, &analysis);
+        }
+        Some(analysis)
+    }
 
-        let probed = symphonia::default::get_probe()
-            .format(
-                &hint,
-                mss,
-                &FormatOptions::default(),
-                &MetadataOptions::default(),
-            )
-            .ok()?;
-        let mut format = probed.format;
-        let track = format.default_track()?;
-        let track_id = track.id;
-        let params = &track.codec_params;
+    fn analyze_uncached(&mut self) -> Option<AudioAnalysis> {
+        let mut track = OpenTrack::open(&self.path)?;
+        let params = &track.params;
 
         self.sample_rate = params.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
-        self.loudness_meter = LoudnessMeter::new(2); // Assume stereo max for now
+        let channels = params.channels.map_or(2, Channels::count).clamp(1, 8);
+        self.loudness_meter = LoudnessMeter::new(channels, self.sample_rate);
 
         // Duration estimation
         let time_base = params.time_base;
@@ -386,239 +510,194 @@ impl TrackAnalyzer {
             }
             _ => None,
         };
+        self.estimated_duration = estimated_duration;
 
-        let mut decoder = symphonia::default::get_codecs()
-            .make(params, &DecoderOptions::default())
-            .ok()?;
+        // The tail is only analysed separately if it does not overlap the head
+        let tail_start = estimated_duration
+            .filter(|&tot| {
+                !self.full_track && self.include_tail && tot > self.max_analyze_time * 2.0
+            })
+            .map(|tot| tot - self.max_analyze_time);
 
-        // Phase 1: Head
-        self.process_segment(&mut format, &mut decoder, track_id, true, time_base)?;
-
-        // Phase 2: Tail
-        if self.include_tail {
-            if let Some(tot) = estimated_duration {
-                // If total duration is significantly longer than what we analyzed + max_analyze_time
-                // We only jump if there is unanalyzed gap.
-                // Actually, logic is: analyze tail if song is long enough.
-                if tot > self.max_analyze_time * 2.0 {
-                    let seek_target = tot - self.max_analyze_time;
-                    let seek_time = Time::from(seek_target);
-                    if format
-                        .seek(
-                            SeekMode::Coarse,
-                            SeekTo::Time {
-                                time: seek_time,
-                                track_id: Some(track_id),
-                            },
-                        )
-                        .is_ok()
-                    {
-                        // Reset duration to correct time after seek?
-                        // Actually duration tracking inside process_segment handles packets.
-                        // But we need to ensure we don't overwrite self.duration with wrong values if packets are weird.
-                        // Wait, process_segment updates self.duration from packet timestamp.
-                        // That is correct.
-                        self.process_segment(
-                            &mut format,
-                            &mut decoder,
-                            track_id,
-                            false,
-                            time_base,
-                        )?;
-                    }
-                }
-            }
-        }
+        self.process_stream(&mut track, tail_start)?;
+        self.report_progress(self.duration, true);
 
         Some(self.finalize_analysis())
     }
 
-    fn process_segment(
-        &mut self,
-        format: &mut Box<dyn FormatReader>,
-        decoder: &mut Box<dyn Decoder>,
-        track_id: u32,
-        is_head: bool,
-        time_base: Option<symphonia::core::units::TimeBase>,
-    ) -> Option<()> {
+    // Decodes the whole file. Head and tail get the full analysis, everything in between only
+    // feeds the loudness meter so loudness covers the entire track.
+    fn process_stream(&mut self, track: &mut OpenTrack, tail_start: Option<f64>) -> Option<()> {
         let window_size = (self.sample_rate as usize * WINDOW_SIZE_MS) / 1000;
         if window_size == 0 {
             return None;
         }
 
-        let mut acc_env = EnvelopeAccumulator::new(window_size);
-        let mut acc_low = EnvelopeAccumulator::new(window_size);
-        let mut acc_vocal = EnvelopeAccumulator::new(window_size);
-
-        let mut vocal_filter = VocalFilter::new(self.sample_rate);
-        let mut lpf = FirstOrderFilter::new(self.sample_rate, LOW_LPF_FREQ, false);
-
-        let key_max_samples =
-            (f64::from(self.sample_rate) * self.max_analyze_time.min(30.0)) as usize;
-        let mut processed_duration_local = 0.0; // For fallback if time_base missing
-
-        let segment = if is_head {
-            &mut self.head
-        } else {
-            &mut self.tail
-        };
-        let loudness_meter = &mut self.loudness_meter;
-        let head_pcm = &mut self.head_pcm;
-
-        // Use a small buffer to hold channel data to avoid allocation per sample
-        // Max 8 channels supported
-        let mut frame_buf = [0.0f32; 8];
-
-        while let Ok(packet) = format.next_packet() {
-            if packet.track_id() != track_id {
-                continue;
-            }
-
-            // Timestamp handling
-            let packet_time = if let Some(tb) = time_base {
-                let t = tb.calc_time(packet.ts());
-                t.seconds as f64 + t.frac
-            } else {
-                // If we sought, this local duration is wrong for absolute time,
-                // but if time_base is missing, seeking is likely impossible/unreliable anyway.
-                if !is_head {
-                    // If no time_base, we can't really do tail analysis properly via seek.
-                    // We just continue.
-                }
-                self.duration + processed_duration_local
-            };
+        let mut state = SegmentState::new(self.sample_rate, window_size);
+        let mut in_tail = false;
 
+        track.decode(|packet_time, frame_len, samples| {
             self.duration = packet_time;
 
-            // Stop condition
-            if is_head && packet_time > self.max_analyze_time {
-                break;
-            }
-
-            // Decode
-            let Ok(decoded) = decoder.decode(&packet) else {
-                continue;
-            };
-
-            let spec = *decoded.spec();
-            let frames = decoded.frames();
-            let channels = spec.channels.count().min(8);
-
-            if time_base.is_none() {
-                processed_duration_local += frames as f64 / f64::from(self.sample_rate);
+            if self.is_cancelled() {
+                return ControlFlow::Break(());
             }
+            self.report_progress(packet_time, false);
 
-            let capture_pcm = is_head && head_pcm.len() < key_max_samples;
-
-            // Process Frames
-            macro_rules! process_buffer {
-                ($buf:expr, $convert:expr) => {
-                    for i in 0..frames {
-                        let mut sum = 0.0;
-                        for c in 0..channels {
-                            let s = $convert($buf.chan(c)[i]);
-                            frame_buf[c] = s;
-                            sum += s;
-                        }
-                        Self::process_sample_static(
-                            loudness_meter,
-                            head_pcm,
-                            sum,
-                            channels,
-                            &frame_buf[..channels],
-                            &mut acc_env,
-                            &mut acc_low,
-                            &mut acc_vocal,
-                            &mut vocal_filter,
-                            &mut lpf,
-                            segment,
-                            capture_pcm,
-                        );
-                    }
-                };
+            let is_head = self.full_track || packet_time <= self.max_analyze_time;
+            let is_tail = !is_head && tail_start.is_some_and(|start| packet_time >= start);
+            if is_tail && !in_tail {
+                in_tail = true;
+                state = SegmentState::new(self.sample_rate, window_size);
             }
+            let mut segment = if is_head {
+                Some(&mut self.head)
+            } else if is_tail {
+                Some(&mut self.tail)
+            } else {
+                None
+            };
 
-            match decoded {
-                AudioBufferRef::F32(buf) => process_buffer!(buf, |s: f32| s),
-                AudioBufferRef::U8(buf) => {
-                    process_buffer!(buf, |s: u8| (f32::from(s) - 128.0) / 128.0)
+            // Max 8 channels supported
+            for frame in samples.chunks_exact(frame_len) {
+                let frame = &frame[..frame_len.min(8)];
+                self.loudness_meter.process(frame);
+                if let Some(segment) = segment.as_deref_mut() {
+                    Self::process_sample_static(
+                        frame.iter().sum(),
+                        frame.len(),
+                        &mut state,
+                        segment,
+                    );
                 }
-                AudioBufferRef::S16(buf) => process_buffer!(buf, |s: i16| f32::from(s) / 32768.0),
-                AudioBufferRef::S24(buf) => process_buffer!(buf, |s: i24| s.0 as f32 / 8388608.0),
-                AudioBufferRef::S32(buf) => process_buffer!(buf, |s: i32| s as f32 / 2147483648.0),
-                _ => continue,
             }
-        }
+            if self.head.envelope.len() >= MAX_ENV_FRAMES {
+                self.head.coarsen();
+            }
+            ControlFlow::Continue(())
+        })?;
         Some(())
     }
 
     // Static helper to avoid double borrow of self
     #[inline(always)]
     fn process_sample_static(
-        loudness_meter: &mut LoudnessMeter,
-        head_pcm: &mut Vec<f32>,
         sum: f32,
         channels: usize,
-        frame_buf: &[f32],
-        acc_env: &mut EnvelopeAccumulator,
-        acc_low: &mut EnvelopeAccumulator,
-        acc_vocal: &mut EnvelopeAccumulator,
-        vocal_filter: &mut VocalFilter,
-        lpf: &mut FirstOrderFilter,
+        state: &mut SegmentState,
         segment: &mut AnalysisSegment,
-        capture_pcm: bool,
     ) {
-        loudness_meter.process(frame_buf);
-
         let val = sum / channels as f32;
-        let vocal = vocal_filter.process(val);
-        let low = lpf.process(val);
-
-        if capture_pcm {
-            head_pcm.push(val);
-        }
+        state.chroma.process(val, &mut segment.chroma);
+        state.vocal.process(val, &mut segment.vocal);
 
-        if let Some(rms) = acc_env.process(val) {
+        if let Some(rms) = state.acc_env.process(val) {
             segment.envelope.push(rms);
         }
-        if let Some(rms_low) = acc_low.process(low) {
-            segment.low_envelope.push(rms_low);
-        }
-        if let Some(rms_vocal) = acc_vocal.process(vocal) {
-            let base = *segment.envelope.last().unwrap_or(&1.0);
-            segment
-                .vocal_ratio
-                .push(if base > 0.0001 { rms_vocal / base } else { 0.0 });
+        if let Some((onset, low_onset)) = state.onsets.process(val) {
+            segment.onset.push(onset);
+            segment.low_onset.push(low_onset);
+            segment.band_energy.push(state.onsets.band_energy());
         }
     }
 
+    #[allow(clippy::too_many_lines)]
     fn finalize_analysis(&self) -> AudioAnalysis {
+        // Only a full-track head is ever pooled, and then there is no tail
+        let stride = self.head.stride() as f64;
+        let env_rate = ENV_RATE / stride;
         let (fade_in, fade_out) = detect_silence(
             &self.head.envelope,
             &self.tail.envelope,
             self.duration,
-            ENV_RATE,
+            env_rate,
             SILENCE_THRESH_DB,
         );
-        let (bpm, bpm_conf, first_beat) =
-            detect_bpm(&self.head.envelope, &self.head.low_envelope, ENV_RATE);
-        let (key_root, key_mode, key_conf) = detect_key(&self.head_pcm, self.sample_rate);
-        let drop_pos = detect_drop(&self.head.envelope, ENV_RATE);
-
-        let (vocal_in, vocal_out, vocal_last_in) = detect_vocals(
-            &self.head.envelope,
-            &self.head.vocal_ratio,
-            &self.tail.envelope,
-            &self.tail.vocal_ratio,
+        let onset_rate = beat::onset_rate(self.sample_rate) / stride;
+        let head_beats = beat::track_beats(
+            &self.head.onset,
+            &self.head.low_onset,
+            onset_rate,
+            0.0,
+            None,
+        );
+        // The tail keeps the head's tempo unless it clearly changed
+        let tail_beats = beat::track_beats(
+            &self.tail.onset,
+            &self.tail.low_onset,
+            onset_rate,
+            (self.duration - self.tail.onset.len() as f64 / onset_rate).max(0.0),
+            head_beats.as_ref().map(|b| b.bpm),
+        );
+        let bpm = head_beats.as_ref().map(|b| b.bpm);
+        let bpm_conf = head_beats.as_ref().map(|b| b.confidence);
+        let beat_grid: Vec<f64> = head_beats
+            .iter()
+            .chain(&tail_beats)
+            .flat_map(|b| b.beats.iter().copied())
+            .collect();
+        let bar_lines: Vec<f64> = head_beats
+            .iter()
+            .chain(&tail_beats)
+            .flat_map(BeatTrack::bars)
+            .collect();
+        let chroma_rate = key::chroma_rate(self.sample_rate) / stride;
+        let (key, key_timeline) = key::detect_key(
+            &[
+                (&self.head.chroma, 0.0),
+                (
+                    &self.tail.chroma,
+                    (self.duration - self.tail.chroma.len() as f64 / chroma_rate).max(0.0),
+                ),
+            ],
+            chroma_rate,
+        );
+        let key_root = key.as_ref().map(|k| k.root);
+        let key_mode = key.as_ref().map(|k| k.mode);
+        let sections = structure::detect_sections(
+            &[
+                (&self.head, 0.0),
+                (
+                    &self.tail,
+                    (self.duration - self.tail.envelope.len() as f64 / env_rate).max(0.0),
+                ),
+            ],
+            env_rate,
+            chroma_rate,
+            onset_rate,
+            bpm.filter(|_| bpm_conf.unwrap_or(0.0) > MIN_BEAT_CONFIDENCE)
+                .map(|b| (b, bar_lines.as_slice())),
             self.duration,
-            ENV_RATE,
-            fade_in,
-            fade_out,
         );
+        // The first chorus if the head has one, otherwise the biggest jump in level
+        let head_end = self.head.envelope.len() as f64 / env_rate;
+        let drop_pos = sections
+            .iter()
+            .find(|s| s.label == SectionLabel::Chorus)
+            .map(|s| s.start)
+            .filter(|&start| start < head_end)
+            .or_else(|| detect_drop(&self.head.envelope, env_rate));
+        let loudness = self.loudness_meter.summary();
+
+        let vocal_rate = vocals::vocal_rate(self.sample_rate) / stride;
+        let vocal_segments = vocals::detect_vocals(
+            &[
+                (&self.head.vocal, 0.0),
+                (
+                    &self.tail.vocal,
+                    (self.duration - self.tail.vocal.len() as f64 / vocal_rate).max(0.0),
+                ),
+            ],
+            vocal_rate,
+            (fade_in, fade_out),
+        );
+        let vocal_in = vocal_segments.first().map(|s| s.start);
+        let vocal_out = vocal_segments.last().map(|s| s.end);
+        let vocal_last_in = vocal_segments.last().map(|s| s.start);
 
         let smart_cut_out = calculate_smart_cut_out(
             bpm,
-            first_beat,
+            &bar_lines,
             bpm_conf,
             vocal_out,
             fade_in,
@@ -627,7 +706,7 @@ impl TrackAnalyzer {
         );
 
         let smart_cut_in =
-            calculate_smart_cut_in(bpm, first_beat, bpm_conf, vocal_in.or(drop_pos), fade_in);
+            calculate_smart_cut_in(bpm, &bar_lines, bpm_conf, vocal_in.or(drop_pos), fade_in);
 
         let mix_center = smart_cut_out.min(self.duration);
         let mix_duration = bpm.map_or(20.0, |b| (240.0 / b * 8.0).clamp(15.0, 30.0));
@@ -642,16 +721,16 @@ impl TrackAnalyzer {
             &mut energy_profile,
             &self.head.envelope,
             0.0,
-            ENV_RATE,
+            env_rate,
             profile_rate,
         );
         if !self.tail.envelope.is_empty() {
-            let tail_start = (self.duration - self.tail.envelope.len() as f64 / ENV_RATE).max(0.0);
+            let tail_start = (self.duration - self.tail.envelope.len() as f64 / env_rate).max(0.0);
             fill_energy_profile(
                 &mut energy_profile,
                 &self.tail.envelope,
                 tail_start,
-                ENV_RATE,
+                env_rate,
                 profile_rate,
             );
         }
@@ -666,11 +745,21 @@ impl TrackAnalyzer {
             } else {
                 self.duration
             },
-            first_beat_pos: first_beat,
-            loudness: Some(self.loudness_meter.get_lufs()),
+            first_beat_pos: beat_grid.first().copied(),
+            downbeat_pos: bar_lines.first().copied(),
+            beat_grid: Some(beat_grid),
+            loudness: loudness.integrated,
+            loudness_range: loudness.range,
+            momentary_max: loudness.momentary_max,
+            short_term_max: loudness.short_term_max,
+            true_peak: (loudness.true_peak > 0.0).then(|| 20.0 * loudness.true_peak.log10()),
             drop_pos,
             version: ANALYSIS_VERSION,
-            analyze_window: self.max_analyze_time,
+            analyze_window: if self.full_track {
+                self.duration
+            } else {
+                self.max_analyze_time
+            },
             cut_in_pos: Some(smart_cut_in),
             cut_out_pos: if self.include_tail {
                 Some(smart_cut_out)
@@ -688,15 +777,23 @@ impl TrackAnalyzer {
             } else {
                 None
             },
-            outro_energy_level: calculate_outro_energy(&self.tail.envelope, ENV_RATE),
+            outro_energy_level: calculate_outro_energy(
+                if self.full_track {
+                    &self.head.envelope
+                } else {
+                    &self.tail.envelope
+                },
+                env_rate,
+            ),
             key_root,
             key_mode,
-            key_confidence: key_conf,
-            camelot_key: if let (Some(r), Some(m)) = (key_root, key_mode) {
-                get_camelot_key(r, m)
-            } else {
-                None
-            },
+            key_confidence: key.as_ref().map(|k| k.confidence),
+            camelot_key: key_root
+                .zip(key_mode)
+                .and_then(|(r, m)| get_camelot_key(r, m)),
+            key_timeline: Some(key_timeline),
+            sections: Some(sections),
+            vocal_segments: Some(vocal_segments),
         }
     }
 }
@@ -799,52 +896,6 @@ fn calculate_outro_energy(tail: &[f32], rate: f64) -> Option<f64> {
     }
 }
 
-fn detect_vocals(
-    head_env: &[f32],
-    head_ratio: &[f32],
-    tail_env: &[f32],
-    tail_ratio: &[f32],
-    duration: f64,
-    rate: f64,
-    fade_in: f64,
-    fade_out: f64,
-) -> (Option<f64>, Option<f64>, Option<f64>) {
-    let is_vocal = |ratio: f32, env: f32| ratio > 0.4 && env > 0.02;
-
-    let vocal_in = head_ratio
-        .iter()
-        .zip(head_env.iter())
-        .enumerate()
-        .skip((fade_in * rate) as usize)
-        .find(|(_, (&r, &e))| is_vocal(r, e))
-        .map(|(i, _)| i as f64 / rate);
-
-    let (scan_env, scan_ratio, base_time) = if tail_env.is_empty() {
-        (head_env, head_ratio, 0.0)
-    } else {
-        (
-            tail_env,
-            tail_ratio,
-            (duration - tail_env.len() as f64 / rate).max(0.0),
-        )
-    };
-
-    let end_limit_idx = ((fade_out - base_time) * rate).round() as usize;
-    let limit = end_limit_idx.min(scan_env.len());
-
-    let vocal_out = scan_ratio
-        .iter()
-        .zip(scan_env.iter())
-        .take(limit)
-        .enumerate()
-        .rfind(|(_, (&r, &e))| is_vocal(r, e))
-        .map(|(i, _)| base_time + i as f64 / rate);
-
-    let vocal_last_in = vocal_out.map(|vo| (vo - 5.0).max(fade_in));
-
-    (vocal_in, vocal_out, vocal_last_in)
-}
-
 fn snap_time(time: f64, bpm: f64, first_beat: f64, grid: f64) -> f64 {
     if bpm <= 0.0 {
         return time;
@@ -864,9 +915,18 @@ fn snap_time(time: f64, bpm: f64, first_beat: f64, grid: f64) -> f64 {
     }
 }
 
+// Nearest bar line, extrapolated at `bpm` from the closest tracked one
+fn snap_to_bar(time: f64, bpm: f64, bar_lines: &[f64]) -> f64 {
+    let i = bar_lines.partition_point(|&bar| bar < time);
+    bar_lines[i.saturating_sub(1)..(i + 1).min(bar_lines.len())]
+        .iter()
+        .min_by(|a, b| (*a - time).abs().total_cmp(&(*b - time).abs()))
+        .map_or(time, |&bar| snap_time(time, bpm, bar, 4.0))
+}
+
 fn calculate_smart_cut_out(
     bpm: Option<f64>,
-    first_beat: Option<f64>,
+    bar_lines: &[f64],
     conf: Option<f64>,
     vocal_out: Option<f64>,
     _fade_in: f64,
@@ -875,12 +935,12 @@ fn calculate_smart_cut_out(
 ) -> f64 {
     let search_end = vocal_out.map_or(fade_out, |vo| (vo + 40.0).min(fade_out));
 
-    if let (Some(b), Some(fb)) = (bpm, first_beat) {
-        if conf.unwrap_or(0.0) > 0.4 {
-            let snapped = snap_time(search_end, b, fb, 4.0);
+    if let Some(b) = bpm.filter(|_| !bar_lines.is_empty()) {
+        if conf.unwrap_or(0.0) > MIN_BEAT_CONFIDENCE {
+            let snapped = snap_to_bar(search_end, b, bar_lines);
             if let Some(vo) = vocal_out {
                 if snapped < vo + 2.0 {
-                    return snap_time(vo + 4.0, b, fb, 4.0).min(duration);
+                    return snap_to_bar(vo + 4.0, b, bar_lines).min(duration);
                 }
             }
             return snapped.min(duration);
@@ -891,19 +951,19 @@ fn calculate_smart_cut_out(
 
 fn calculate_smart_cut_in(
     bpm: Option<f64>,
-    first_beat: Option<f64>,
+    bar_lines: &[f64],
     conf: Option<f64>,
     anchor: Option<f64>,
     fade_in: f64,
 ) -> f64 {
     let anchor = anchor.unwrap_or(fade_in);
-    if let (Some(b), Some(fb)) = (bpm, first_beat) {
-        if conf.unwrap_or(0.0) > 0.4 {
+    if let Some(b) = bpm.filter(|_| !bar_lines.is_empty()) {
+        if conf.unwrap_or(0.0) > MIN_BEAT_CONFIDENCE {
             let sec_bar = 240.0 / b;
             for bars in [32.0_f64, 16.0, 8.0] {
                 let t = bars.mul_add(-sec_bar, anchor);
                 if t > fade_in {
-                    return snap_time(t, b, fb, 4.0);
+                    return snap_to_bar(t, b, bar_lines);
                 }
             }
         }
@@ -911,147 +971,6 @@ fn calculate_smart_cut_in(
     fade_in
 }
 
-// --- BPM & Key Detection Wrappers ---
-
-fn detect_bpm(env: &[f32], _low_env: &[f32], rate: f64) -> (Option<f64>, Option<f64>, Option<f64>) {
-    if env.len() < 100 {
-        return (None, None, None);
-    }
-
-    // Simple Flux
-    let flux: Vec<f32> = env.windows(2).map(|w| (w[1] - w[0]).max(0.0)).collect();
-    if flux.len() < 110 {
-        return (None, None, None);
-    }
-
-    // Autocorrelation (60-180 BPM -> 0.33-1.0s -> 16-50 samples)
-    let mut best_corr = 0.0;
-    let mut best_lag = 0;
-
-    for lag in BPM_MIN_LAG..BPM_MAX_LAG {
-        let mut sum = 0.0;
-        for i in 0..(flux.len() - lag) {
-            sum += flux[i] * flux[i + lag];
-        }
-        if sum > best_corr {
-            best_corr = sum;
-            best_lag = lag;
-        }
-    }
-
-    if best_corr <= 0.001 {
-        return (None, None, None);
-    }
-
-    let bpm = 60.0 / (best_lag as f64 / rate);
-    // Refine phase
-    let first_beat = (0..best_lag)
-        .max_by_key(|&phase| {
-            let mut e = 0.0;
-            let mut idx = phase;
-            while idx < flux.len() {
-                e += flux[idx];
-                idx += best_lag;
-            }
-            (e * 1000.0) as i32
-        })
-        .map(|p| p as f64 / rate);
-
-    (Some(bpm), Some(0.8), first_beat)
-}
-
-fn detect_key(pcm: &[f32], sr: u32) -> (Option<i32>, Option<i32>, Option<f64>) {
-    if pcm.len() < FFT_FRAME_SIZE {
-        return (None, None, None);
-    }
-
-    let frame_size = FFT_FRAME_SIZE;
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(frame_size);
-    let mut buffer = vec![Complex32::new(0.0, 0.0); frame_size];
-    let mut chroma = [0.0f32; 12];
-
-    // Simple Hamming window
-    let window: Vec<f32> = (0..frame_size)
-        .map(|i| {
-            HAMMING_BETA.mul_add(
-                -(2.0 * std::f32::consts::PI * i as f32 / (frame_size - 1) as f32).cos(),
-                HAMMING_ALPHA,
-            )
-        })
-        .collect();
-
-    // Processing chunks
-    for chunk in pcm.chunks(frame_size).step_by(FFT_STEP) {
-        if chunk.len() < frame_size {
-            break;
-        }
-        for i in 0..frame_size {
-            buffer[i] = Complex32::new(chunk[i] * window[i], 0.0);
-        }
-        fft.process(&mut buffer);
-
-        // Map bins to chroma
-        for (i, item) in buffer.iter().enumerate().take(frame_size / 2).skip(1) {
-            let hz = i as f32 * sr as f32 / frame_size as f32;
-            if !(MIN_FREQ..=MAX_FREQ).contains(&hz) {
-                continue;
-            }
-            let midi = 69.0 + 12.0 * (hz / 440.0).ln() / std::f32::consts::LN_2;
-            let pc = (midi.round() as usize) % 12;
-            let mag = item.norm_sqr();
-            chroma[pc] += mag;
-        }
-    }
-
-    // Correlation with Major/Minor profiles (Krumhansl-Schmuckler)
-    let major = [
-        6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
-    ];
-    let minor = [
-        6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
-    ];
-
-    let sum_sq: f32 = chroma.iter().map(|x| x * x).sum();
-    if sum_sq == 0.0 {
-        return (None, None, None);
-    }
-    let norm = sum_sq.sqrt();
-    for x in &mut chroma {
-        *x /= norm;
-    }
-
-    let mut best_score = -1.0;
-    let mut best_root = 0;
-    let mut best_mode = 0; // 0=Maj, 1=Min
-
-    for root in 0..12 {
-        let mut s_maj = 0.0;
-        let mut s_min = 0.0;
-        for (i, &c) in chroma.iter().enumerate() {
-            let idx = (i + 12 - root) % 12;
-            s_maj += c * major[idx] as f32;
-            s_min += c * minor[idx] as f32;
-        }
-        if s_maj > best_score {
-            best_score = s_maj;
-            best_root = root;
-            best_mode = 0;
-        }
-        if s_min > best_score {
-            best_score = s_min;
-            best_root = root;
-            best_mode = 1;
-        }
-    }
-
-    if best_score > 0.0 {
-        (Some(best_root as i32), Some(best_mode), Some(0.8))
-    } else {
-        (None, None, None)
-    }
-}
-
 // --- Transition Logic ---
 
 #[derive(Debug)]
@@ -1081,6 +1000,9 @@ impl MixStrategy {
     }
 }
 
+// Strategies whose overlap has the vocals of both tracks for longer than this are skipped
+const MAX_VOCAL_CLASH_SECS: f64 = 1.0;
+
 const STRATEGIES: &[MixStrategy] = &[
     MixStrategy::new(
         "Harmonic Deep Blend",
@@ -1096,11 +1018,62 @@ const STRATEGIES: &[MixStrategy] = &[
     MixStrategy::new("Quick Blend", "Quick Fade", 4.0, true, false),
 ];
 
+// Seconds of a mix over `dur` in which both tracks sing, `cur` playing from `cur_start` and
+// `next` from `next_start`
+fn vocal_clash(
+    cur: &[VocalSegment],
+    cur_start: f64,
+    next: &[VocalSegment],
+    next_start: f64,
+    dur: f64,
+) -> f64 {
+    let within = |segments: &'_ [VocalSegment], from: f64| -> Vec<(f64, f64)> {
+        segments
+            .iter()
+            .map(|s| ((s.start - from).max(0.0), (s.end - from).min(dur)))
+            .filter(|(start, end)| end > start)
+            .collect()
+    };
+    let next = within(next, next_start);
+    within(cur, cur_start)
+        .iter()
+        .flat_map(|a| {
+            next.iter()
+                .map(move |b| (a.1.min(b.1) - a.0.max(b.0)).max(0.0))
+        })
+        .sum()
+}
+
+// A track to plan a transition with, either an analysis from earlier or a path to analyse
+fn resolve_track(
+    track: Either<AudioAnalysis, String>,
+    max_analyze_time: Option<f64>,
+    include_tail: bool,
+) -> Option<AudioAnalysis> {
+    match track {
+        Either::A(analysis) => Some(analysis),
+        Either::B(path) => TrackAnalyzer::new(path, max_analyze_time, include_tail).analyze(),
+    }
+}
+
 #[napi]
 pub fn suggest_transition(current_path: String, next_path: String) -> Option<TransitionProposal> {
-    let cur = TrackAnalyzer::new(current_path, None, true).analyze()?;
-    let next = TrackAnalyzer::new(next_path, Some(120.0), false).analyze()?;
+    suggest_transition_from(Either::B(current_path), Either::B(next_path))
+}
 
+/// Same as `suggest_transition`, but each track can be given as the `AudioAnalysis` computed
+/// earlier (needs the tail, i.e. `analyzeAudioFile`, for the current track) so nothing is decoded
+#[napi]
+pub fn suggest_transition_from(
+    current: Either<AudioAnalysis, String>,
+    next: Either<AudioAnalysis, String>,
+) -> Option<TransitionProposal> {
+    let cur = resolve_track(current, None, true)?;
+    let next = resolve_track(next, Some(120.0), false)?;
+    Some(plan_transition(&cur, &next))
+}
+
+fn plan_transition(cur: &AudioAnalysis, next: &AudioAnalysis) -> TransitionProposal {
     let bpm_a = cur.bpm.unwrap_or(128.0);
     let bpm_b = next.bpm.unwrap_or(128.0);
     let bpm_compatible = (bpm_a - bpm_b).abs() / bpm_a < 0.06;
@@ -1109,7 +1082,6 @@ pub fn suggest_transition(current_path: String, next_path: String) -> Option<Tra
 
     let cur_out = cur.cut_out_pos.unwrap_or(cur.fade_out_pos);
     let next_in = next.first_beat_pos.unwrap_or(0.0);
-    let next_intro_len = next.vocal_in_pos.unwrap_or(30.0) - next_in;
 
     let sec_per_bar = 240.0 / bpm_a;
 
@@ -1123,9 +1095,6 @@ pub fn suggest_transition(current_path: String, next_path: String) -> Option<Tra
         }
 
         let dur = s.bars * sec_per_bar;
-        if next_intro_len < dur {
-            continue;
-        } // Next track intro too short
 
         // Check if Current track has space
         let start = cur_out - dur; // Simple backward calculation
@@ -1133,8 +1102,19 @@ pub fn suggest_transition(current_path: String, next_path: String) -> Option<Tra
             continue;
         } // Too far back?
 
+        let clash = vocal_clash(
+            cur.vocal_segments.as_deref().unwrap_or_default(),
+            start,
+            next.vocal_segments.as_deref().unwrap_or_default(),
+            next_in,
+            dur,
+        );
+        if clash > MAX_VOCAL_CLASH_SECS {
+            continue;
+        } // Both tracks sing at once
+
         // Success
-        return Some(TransitionProposal {
+        return TransitionProposal {
             duration: dur,
             current_track_mix_out: start,
             next_track_mix_in: next_in,
@@ -1143,14 +1123,14 @@ pub fn suggest_transition(current_path: String, next_path: String) -> Option<Tra
             compatibility_score: 0.9,
             key_compatible,
             bpm_compatible,
-        });
+        };
     }
 
     // 2. Fallback: Aggressive Bass Swap
     if bpm_compatible {
         let dur = 16.0 * sec_per_bar;
         if cur.duration - cur_out > dur {
-            return Some(TransitionProposal {
+            return TransitionProposal {
                 duration: dur,
                 current_track_mix_out: cur_out - dur,
                 next_track_mix_in: next_in,
@@ -1159,12 +1139,12 @@ pub fn suggest_transition(current_path: String, next_path: String) -> Option<Tra
                 compatibility_score: 0.7,
                 key_compatible,
                 bpm_compatible,
-            });
+            };
         }
     }
 
     // 3. Fallback: Echo Out
-    Some(TransitionProposal {
+    TransitionProposal {
         duration: sec_per_bar * 4.0,
         current_track_mix_out: cur_out,
         next_track_mix_in: next_in,
@@ -1173,14 +1153,26 @@ pub fn suggest_transition(current_path: String, next_path: String) -> Option<Tra
         compatibility_score: 0.5,
         key_compatible,
         bpm_compatible,
-    })
+    }
 }
 
 #[napi]
 pub fn suggest_long_mix(current_path: String, next_path: String) -> Option<AdvancedTransition> {
-    let cur = TrackAnalyzer::new(current_path, None, true).analyze()?;
-    let next = TrackAnalyzer::new(next_path, Some(180.0), false).analyze()?;
+    suggest_long_mix_from(Either::B(current_path), Either::B(next_path))
+}
 
+/// Same as `suggest_long_mix`, taking analyses computed earlier like `suggest_transition_from`
+#[napi]
+pub fn suggest_long_mix_from(
+    current: Either<AudioAnalysis, String>,
+    next: Either<AudioAnalysis, String>,
+) -> Option<AdvancedTransition> {
+    let cur = resolve_track(current, None, true)?;
+    let next = resolve_track(next, Some(180.0), false)?;
+    Some(plan_long_mix(&cur, &next))
+}
+
+fn plan_long_mix(cur: &AudioAnalysis, next: &AudioAnalysis) -> AdvancedTransition {
     let bpm_a = cur.bpm.unwrap_or(128.0);
     let bpm_b = next.bpm.unwrap_or(128.0);
     let playback_rate = bpm_a / bpm_b;
@@ -1199,7 +1191,7 @@ pub fn suggest_long_mix(current_path: String, next_path: String) -> Option<Advan
     // Automation
     let (auto_a, auto_b) = generate_bass_swap_automation(duration);
 
-    Some(AdvancedTransition {
+    AdvancedTransition {
         start_time_current: (cur_end - duration).max(0.0),
         start_time_next: (next_start - duration / playback_rate).max(0.0),
         duration,
@@ -1208,7 +1200,7 @@ pub fn suggest_long_mix(current_path: String, next_path: String) -> Option<Advan
         automation_current: auto_a,
         automation_next: auto_b,
         strategy: "Long Bass Swap".to_string(),
-    })
+    }
 }
 
 // --- Utils ---
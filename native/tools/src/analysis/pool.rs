@@ -0,0 +1,115 @@
+// Feature frames that get coarser as a track gets longer, so full-track analysis keeps a bounded
+// number of them however long the file is
+
+use std::ops::Deref;
+
+/// A feature frame that can be averaged with others
+pub(super) trait Poolable: Copy {
+    fn add(&mut self, other: &Self);
+    fn scale(&mut self, factor: f32);
+}
+
+impl Poolable for f32 {
+    fn add(&mut self, other: &Self) {
+        *self += other;
+    }
+
+    fn scale(&mut self, factor: f32) {
+        *self *= factor;
+    }
+}
+
+impl<const N: usize> Poolable for [f32; N] {
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.iter_mut().zip(other) {
+            *a += b;
+        }
+    }
+
+    fn scale(&mut self, factor: f32) {
+        for a in self {
+            *a *= factor;
+        }
+    }
+}
+
+/// Frames stored as the mean of every `stride` incoming ones. Derefs to the stored frames.
+pub(super) struct Pooled<T> {
+    frames: Vec<T>,
+    stride: usize,
+    // Sum and count of the incoming frames not stored yet
+    pending: Option<(T, usize)>,
+}
+
+impl<T> Default for Pooled<T> {
+    fn default() -> Self {
+        Self {
+            frames: Vec::new(),
+            stride: 1,
+            pending: None,
+        }
+    }
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.frames
+    }
+}
+
+impl<T> Pooled<T> {
+    /// How many incoming frames each stored one stands for
+    pub(super) const fn stride(&self) -> usize {
+        self.stride
+    }
+}
+
+impl<T: Poolable> Pooled<T> {
+    pub(super) fn push(&mut self, frame: T) {
+        if self.stride == 1 {
+            self.frames.push(frame);
+            return;
+        }
+        match &mut self.pending {
+            Some((sum, count)) => {
+                sum.add(&frame);
+                *count += 1;
+            }
+            None => self.pending = Some((frame, 1)),
+        }
+        if let Some((mut sum, count)) = self.pending.filter(|&(_, count)| count >= self.stride) {
+            self.pending = None;
+            sum.scale(1.0 / count as f32);
+            self.frames.push(sum);
+        }
+    }
+
+    /// Halves the resolution: neighbouring frames are merged and twice as many incoming frames
+    /// go into each stored one from now on
+    pub(super) fn coarsen(&mut self) {
+        let pairs = self.frames.len() / 2;
+        for i in 0..pairs {
+            let mut merged = self.frames[2 * i];
+            merged.add(&self.frames[2 * i + 1]);
+            merged.scale(0.5);
+            self.frames[i] = merged;
+        }
+        // An odd frame out is only half of a new one, it waits with the pending frames
+        if self.frames.len() % 2 == 1 {
+            let mut odd = self.frames[self.frames.len() - 1];
+            odd.scale(self.stride as f32);
+            let count = match self.pending.take() {
+                Some((sum, count)) => {
+                    odd.add(&sum);
+                    self.stride + count
+                }
+                None => self.stride,
+            };
+            self.pending = Some((odd, count));
+        }
+        self.frames.truncate(pairs);
+        self.stride *= 2;
+    }
+}
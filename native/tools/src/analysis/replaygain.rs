@@ -0,0 +1,184 @@
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use symphonia::core::audio::Channels;
+
+use super::loudness::{combined_integrated, LoudnessMeter, LoudnessSummary};
+use super::{OpenTrack, DEFAULT_SAMPLE_RATE};
+use crate::metadata::{write_replaygain, SongMetadata};
+use crate::utils::Context;
+
+// ReplayGain 2.0 reference level
+const REFERENCE_LUFS: f64 = -18.0;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct ReplayGainOptions {
+    /// Write `REPLAYGAIN_*` tags to the files, defaults to `false`
+    pub write_tags: Option<bool>,
+    /// Use the same crash-safe write as `WriteTagOptions.safeWrite`
+    pub safe_write: Option<bool>,
+}
+
+#[napi(object)]
+pub struct ReplayGainProgress {
+    pub current: u32,
+    pub total: u32,
+    pub path: String,
+    /// Set if this file could not be measured
+    pub error: Option<String>,
+}
+
+#[napi(object)]
+pub struct ReplayGainTrack {
+    pub path: String,
+    /// Gated integrated loudness in LUFS
+    pub loudness: Option<f64>,
+    /// Gain in dB that brings the track to -18 LUFS
+    pub gain: Option<f64>,
+    /// Linear true peak, 1.0 is full scale
+    pub peak: Option<f64>,
+    /// Set if measuring or tagging this file failed
+    pub error: Option<String>,
+}
+
+#[napi(object)]
+pub struct ReplayGainResult {
+    pub tracks: Vec<ReplayGainTrack>,
+    /// Only in album mode, computed over all tracks as if played back to back
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+fn gain_for(loudness: f64) -> f64 {
+    REFERENCE_LUFS - loudness
+}
+
+fn measure(path: &str) -> std::result::Result<(LoudnessMeter, LoudnessSummary), String> {
+    let mut track =
+        OpenTrack::open(Path::new(path)).ok_or("Unsupported or unreadable audio file")?;
+    let sample_rate = track.params.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let channels = track.params.channels.map_or(2, Channels::count).clamp(1, 8);
+    let mut meter = LoudnessMeter::new(channels, sample_rate);
+
+    track
+        .decode(|_, frame_len, samples| {
+            for frame in samples.chunks_exact(frame_len) {
+                meter.process(&frame[..frame_len.min(8)]);
+            }
+            ControlFlow::Continue(())
+        })
+        .ok_or("No audio could be decoded")?;
+    let summary = meter.summary();
+    Ok((meter, summary))
+}
+
+/// Measures `ReplayGain` 2.0 track (and optionally album) gain and peak with the EBU R128
+/// loudness meter, and can write the results as `REPLAYGAIN_*` tags
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn compute_replaygain(
+    paths: Vec<String>,
+    album_mode: bool,
+    on_progress: Option<ThreadsafeFunction<ReplayGainProgress>>,
+    options: Option<ReplayGainOptions>,
+) -> Result<ReplayGainResult> {
+    let options = options.unwrap_or_default();
+
+    tokio::task::spawn_blocking(move || {
+        // The same file twice would be tagged by two writers at once and counted twice in the
+        // album loudness
+        let mut seen = HashSet::new();
+        let paths: Vec<String> = paths
+            .into_iter()
+            .filter(|path| seen.insert(path.clone()))
+            .collect();
+        let total = paths.len() as u32;
+        let current = AtomicU32::new(0);
+
+        let measured: Vec<(String, std::result::Result<_, String>)> = paths
+            .into_par_iter()
+            .map(|path| {
+                let result = measure(&path);
+                if let Some(callback) = &on_progress {
+                    callback.call(
+                        Ok(ReplayGainProgress {
+                            current: current.fetch_add(1, Ordering::Relaxed) + 1,
+                            total,
+                            path: path.clone(),
+                            error: result.as_ref().err().cloned(),
+                        }),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+                (path, result)
+            })
+            .collect();
+
+        let measured_ok = || measured.iter().filter_map(|(_, r)| r.as_ref().ok());
+        let (album_gain, album_peak) = if album_mode {
+            (
+                combined_integrated(measured_ok().map(|(meter, _)| meter)).map(gain_for),
+                measured_ok()
+                    .map(|(_, summary)| summary.true_peak)
+                    .reduce(f64::max),
+            )
+        } else {
+            (None, None)
+        };
+
+        let mut tracks: Vec<ReplayGainTrack> = measured
+            .iter()
+            .map(|(path, result)| match result {
+                Ok((_, summary)) => ReplayGainTrack {
+                    path: path.clone(),
+                    loudness: summary.integrated,
+                    gain: summary.integrated.map(gain_for),
+                    peak: Some(summary.true_peak),
+                    error: None,
+                },
+                Err(e) => ReplayGainTrack {
+                    path: path.clone(),
+                    loudness: None,
+                    gain: None,
+                    peak: None,
+                    error: Some(e.clone()),
+                },
+            })
+            .collect();
+        drop(measured);
+
+        if options.write_tags.unwrap_or(false) {
+            let safe_write = options.safe_write.unwrap_or(false);
+            tracks
+                .par_iter_mut()
+                .filter(|track| track.gain.is_some())
+                .for_each(|track| {
+                    let meta = SongMetadata {
+                        replaygain_track_gain: track.gain,
+                        replaygain_track_peak: track.peak,
+                        replaygain_album_gain: album_gain,
+                        replaygain_album_peak: album_peak,
+                        ..Default::default()
+                    };
+                    if let Err(e) = write_replaygain(Path::new(&track.path), &meta, safe_write) {
+                        eprintln!("写入 ReplayGain 失败 {}: {e}", track.path);
+                        track.error = Some(e.reason.clone());
+                    }
+                });
+        }
+
+        Ok(ReplayGainResult {
+            tracks,
+            album_gain,
+            album_peak,
+        })
+    })
+    .await
+    .context("ReplayGain task panicked or cancelled")?
+}
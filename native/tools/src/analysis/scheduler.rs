@@ -0,0 +1,321 @@
+// Library-wide batch analysis: a priority queue of files drained by a fixed number of workers on
+// a rayon pool, with results streamed to JS as they finish
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+use super::{AudioAnalysis, TrackAnalyzer};
+use crate::utils::Context;
+
+#[napi(object)]
+pub struct AnalysisJob {
+    pub path: String,
+    /// Higher runs first, e.g. the upcoming queue above the rest of the library. Defaults to 0
+    pub priority: Option<i32>,
+}
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct AnalysisSchedulerOptions {
+    /// Worker threads, defaults to all cores but one
+    pub threads: Option<u32>,
+    /// Same as for `analyze_audio_file`
+    pub max_analyze_time: Option<f64>,
+    /// Analyse the tail as well (`analyze_audio_file`) or only the head (`analyze_audio_file_head`),
+    /// defaults to true
+    pub include_tail: Option<bool>,
+    /// Analyse whole tracks like `AnalysisTask`, ignores the two options above
+    pub full_track: Option<bool>,
+}
+
+#[napi(object)]
+pub struct AnalysisJobResult {
+    pub path: String,
+    /// `None` if the file could not be decoded
+    pub analysis: Option<AudioAnalysis>,
+    /// Jobs finished since the scheduler was created, this one included
+    pub completed: u32,
+    /// Jobs still queued
+    pub pending: u32,
+}
+
+// Ordered by priority, then by when it was queued
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct QueuedJob {
+    priority: i32,
+    order: Reverse<u64>,
+    path: String,
+}
+
+#[derive(Default)]
+struct JobQueue {
+    heap: BinaryHeap<QueuedJob>,
+    // Priority and order of every queued path, heap entries that disagree are stale
+    pending: HashMap<String, (i32, u64)>,
+    next_order: u64,
+    // Jobs being analysed right now
+    active: usize,
+    paused: bool,
+}
+
+impl JobQueue {
+    // Queues `path` or moves it to `priority` if it is queued already
+    fn push(&mut self, path: String, priority: i32) {
+        if self.pending.get(&path).is_some_and(|&(p, _)| p == priority) {
+            return;
+        }
+        let order = self.next_order;
+        self.next_order += 1;
+        self.pending.insert(path.clone(), (priority, order));
+        self.heap.push(QueuedJob {
+            priority,
+            order: Reverse(order),
+            path,
+        });
+        // Re-prioritising leaves stale entries behind, drop them before they pile up
+        if self.heap.len() > 2 * self.pending.len() + 64 {
+            let pending = &self.pending;
+            self.heap
+                .retain(|job| pending.get(&job.path) == Some(&(job.priority, job.order.0)));
+        }
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        while let Some(job) = self.heap.pop() {
+            if self.pending.get(&job.path) == Some(&(job.priority, job.order.0)) {
+                self.pending.remove(&job.path);
+                return Some(job.path);
+            }
+        }
+        None
+    }
+}
+
+struct Shared {
+    queue: Mutex<JobQueue>,
+    // Signalled whenever workers may have something to do or should stop
+    wake: Condvar,
+    cancelled: Arc<AtomicBool>,
+    running: AtomicBool,
+    completed: AtomicU32,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, JobQueue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Analyses many files in parallel, highest priority first. Jobs can be added, re-prioritised
+/// and removed while it runs.
+#[napi]
+pub struct AnalysisScheduler {
+    shared: Arc<Shared>,
+    options: AnalysisSchedulerOptions,
+}
+
+#[napi]
+impl AnalysisScheduler {
+    #[napi(constructor)]
+    pub fn new(options: Option<AnalysisSchedulerOptions>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(JobQueue::default()),
+                wake: Condvar::new(),
+                cancelled: Arc::new(AtomicBool::new(false)),
+                running: AtomicBool::new(false),
+                completed: AtomicU32::new(0),
+            }),
+            options: options.unwrap_or_default(),
+        }
+    }
+
+    /// Queues `jobs`. A path that is queued already moves to its new priority.
+    #[napi]
+    pub fn enqueue(&self, jobs: Vec<AnalysisJob>) {
+        let mut queue = self.shared.lock();
+        for job in jobs {
+            queue.push(job.path, job.priority.unwrap_or(0));
+        }
+        drop(queue);
+        self.shared.wake.notify_all();
+    }
+
+    /// Moves the queued ones of `paths` to `priority` and returns how many there were
+    #[napi]
+    pub fn set_priority(&self, paths: Vec<String>, priority: i32) -> u32 {
+        let mut queue = self.shared.lock();
+        let mut moved = 0;
+        for path in paths {
+            if queue.pending.contains_key(&path) {
+                queue.push(path, priority);
+                moved += 1;
+            }
+        }
+        drop(queue);
+        moved
+    }
+
+    /// Drops the queued ones of `paths` and returns how many there were. Jobs already being
+    /// analysed still finish.
+    #[napi]
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn remove(&self, paths: Vec<String>) -> u32 {
+        let mut queue = self.shared.lock();
+        paths
+            .iter()
+            .filter(|path| queue.pending.remove(*path).is_some())
+            .count() as u32
+    }
+
+    /// Jobs still queued
+    #[napi]
+    pub fn pending(&self) -> u32 {
+        self.shared.lock().pending.len() as u32
+    }
+
+    /// Lets running jobs finish but starts no new ones until `resume`
+    #[napi]
+    pub fn pause(&self) {
+        self.shared.lock().paused = true;
+    }
+
+    #[napi]
+    pub fn resume(&self) {
+        self.shared.lock().paused = false;
+        self.shared.wake.notify_all();
+    }
+
+    /// Stops running jobs and clears the queue. The scheduler cannot be used afterwards.
+    #[napi]
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Relaxed);
+        let mut queue = self.shared.lock();
+        queue.heap.clear();
+        queue.pending.clear();
+        drop(queue);
+        self.shared.wake.notify_all();
+    }
+
+    /// Works through the queue on `threads` workers, passing every result to `on_result` as it
+    /// finishes, and resolves to the number of files analysed once the queue is empty. Files that
+    /// could not be decoded are reported but not counted. Jobs queued meanwhile are picked up,
+    /// while paused it waits for `resume`. Rejects with `Cancelled` after `cancel`.
+    #[napi]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn run(&self, on_result: ThreadsafeFunction<AnalysisJobResult>) -> Result<u32> {
+        if self.shared.running.swap(true, Ordering::AcqRel) {
+            return Err(Error::from_reason("Analysis scheduler is already running"));
+        }
+        let threads = self.options.threads.map_or_else(
+            || {
+                std::thread::available_parallelism()
+                    .map_or(1, |n| n.get().saturating_sub(1))
+                    .max(1)
+            },
+            |n| n.max(1) as usize,
+        );
+        let shared = self.shared.clone();
+        let options = self.options.clone();
+
+        let result = tokio::task::spawn_blocking(move || -> Result<u32> {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("analysis-{i}"))
+                .build()
+                .context("Failed to start analysis threads")?;
+            let analysed = AtomicU32::new(0);
+            pool.scope(|scope| {
+                for _ in 0..threads {
+                    scope.spawn(|_| work(&shared, &options, &on_result, &analysed));
+                }
+            });
+            Ok(analysed.into_inner())
+        })
+        .await
+        .context("Analysis scheduler panicked or cancelled");
+        self.shared.running.store(false, Ordering::Release);
+
+        let analysed = result??;
+        if self.shared.cancelled.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                Status::Cancelled,
+                "Analysis cancelled".to_string(),
+            ));
+        }
+        Ok(analysed)
+    }
+}
+
+// One worker: takes the best queued job until nothing is queued or being analysed. Idle workers
+// stay while others run, those jobs' results may prompt JS to queue more.
+fn work(
+    shared: &Shared,
+    options: &AnalysisSchedulerOptions,
+    on_result: &ThreadsafeFunction<AnalysisJobResult>,
+    analysed: &AtomicU32,
+) {
+    loop {
+        let mut queue = shared.lock();
+        let path = loop {
+            if shared.cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            if !queue.paused {
+                if let Some(path) = queue.pop() {
+                    break path;
+                }
+                if queue.active == 0 {
+                    return;
+                }
+            }
+            queue = shared
+                .wake
+                .wait(queue)
+                .unwrap_or_else(PoisonError::into_inner);
+        };
+        queue.active += 1;
+        drop(queue);
+
+        let analysis = if options.full_track.unwrap_or(false) {
+            TrackAnalyzer::full_track(path.clone(), shared.cancelled.clone(), None).analyze()
+        } else {
+            TrackAnalyzer {
+                cancelled: Some(shared.cancelled.clone()),
+                ..TrackAnalyzer::new(
+                    path.clone(),
+                    options.max_analyze_time,
+                    options.include_tail.unwrap_or(true),
+                )
+            }
+            .analyze()
+        };
+
+        let mut queue = shared.lock();
+        queue.active -= 1;
+        let pending = queue.pending.len() as u32;
+        drop(queue);
+        shared.wake.notify_all();
+
+        if shared.cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        if analysis.is_some() {
+            analysed.fetch_add(1, Ordering::Relaxed);
+        }
+        on_result.call(
+            Ok(AnalysisJobResult {
+                path,
+                analysis,
+                completed: shared.completed.fetch_add(1, Ordering::Relaxed) + 1,
+                pending,
+            }),
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+    }
+}
@@ -0,0 +1,482 @@
+// Spectrum images for inspecting files, e.g. the low-pass shelf left behind by lossy encoders
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
+use symphonia::core::audio::Channels;
+use symphonia::core::formats::{SeekMode, SeekTo};
+use symphonia::core::units::Time;
+
+use super::{OpenTrack, DEFAULT_SAMPLE_RATE};
+use crate::utils::Context;
+
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 512;
+const MAX_DIMENSION: u32 = 8192;
+const DEFAULT_FFT_SIZE: u32 = 4096;
+const DEFAULT_OVERLAP: f64 = 0.5;
+const MAX_OVERLAP: f64 = 0.95;
+const DEFAULT_MIN_DB: f64 = -120.0;
+// Lowest frequency of the log axis, raised to the first bin for small FFTs
+const LOG_MIN_FREQ: f64 = 20.0;
+const POWER_FLOOR: f64 = 1e-20;
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectrogramWindow {
+    Hann,
+    Hamming,
+    Blackman,
+    /// Lowest side lobes, best for spotting faint content
+    BlackmanHarris,
+    Rectangular,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectrogramColorMap {
+    Magma,
+    Inferno,
+    Viridis,
+    Grayscale,
+}
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectrogramView {
+    /// All channels mixed to mono
+    Mix,
+    /// One band per channel, the first channel on top
+    Channels,
+    /// Mid on top and side below, mono files fall back to `Mix`
+    MidSide,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct SpectrogramOptions {
+    /// Image size in pixels, defaults to 1024 x 512
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Power of two between 64 and 65536, defaults to 4096
+    pub fft_size: Option<u32>,
+    /// Fraction of each FFT frame shared with the next, 0.0 - 0.95, defaults to 0.5
+    pub overlap: Option<f64>,
+    /// Defaults to `Hann`
+    pub window: Option<SpectrogramWindow>,
+    /// Logarithmic frequency axis from 20 Hz, defaults to linear
+    pub log_frequency: Option<bool>,
+    /// Defaults to `Magma`
+    pub color_map: Option<SpectrogramColorMap>,
+    /// Defaults to `Mix`
+    pub view: Option<SpectrogramView>,
+    /// Time range in seconds, defaults to the whole track
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    /// Level drawn as the darkest colour in dBFS, defaults to -120
+    pub min_db: Option<f64>,
+}
+
+struct Settings {
+    width: usize,
+    height: usize,
+    fft_size: usize,
+    hop: usize,
+    window: SpectrogramWindow,
+    log_frequency: bool,
+    color_map: SpectrogramColorMap,
+    view: SpectrogramView,
+    start: f64,
+    end: f64,
+    min_db: f64,
+}
+
+impl Settings {
+    fn new(options: &SpectrogramOptions) -> Result<Self> {
+        let invalid = |reason: &str| Err(Error::from_reason(format!("Invalid options: {reason}")));
+        let width = options.width.unwrap_or(DEFAULT_WIDTH);
+        let height = options.height.unwrap_or(DEFAULT_HEIGHT);
+        if !(1..=MAX_DIMENSION).contains(&width) || !(1..=MAX_DIMENSION).contains(&height) {
+            return invalid("width and height must be 1 - 8192");
+        }
+        let fft_size = options.fft_size.unwrap_or(DEFAULT_FFT_SIZE);
+        if !fft_size.is_power_of_two() || !(64..=65536).contains(&fft_size) {
+            return invalid("fftSize must be a power of two between 64 and 65536");
+        }
+        let overlap = options.overlap.unwrap_or(DEFAULT_OVERLAP);
+        if !(0.0..=MAX_OVERLAP).contains(&overlap) {
+            return invalid("overlap must be 0.0 - 0.95");
+        }
+        let start = options.start.unwrap_or(0.0).max(0.0);
+        let end = options.end.unwrap_or(f64::INFINITY);
+        if end <= start {
+            return invalid("end must be after start");
+        }
+        let min_db = options.min_db.unwrap_or(DEFAULT_MIN_DB);
+        if min_db.is_nan() || min_db >= 0.0 {
+            return invalid("minDb must be negative");
+        }
+
+        Ok(Self {
+            width: width as usize,
+            height: height as usize,
+            fft_size: fft_size as usize,
+            hop: ((f64::from(fft_size) * (1.0 - overlap)).round() as usize).max(1),
+            window: options.window.unwrap_or(SpectrogramWindow::Hann),
+            log_frequency: options.log_frequency.unwrap_or(false),
+            color_map: options.color_map.unwrap_or(SpectrogramColorMap::Magma),
+            view: options.view.unwrap_or(SpectrogramView::Mix),
+            start,
+            end,
+            min_db,
+        })
+    }
+}
+
+// Generalised cosine windows, a0 - a1 cos(x) + a2 cos(2x) - a3 cos(3x)
+fn window_coefficients(window: SpectrogramWindow, len: usize) -> Vec<f32> {
+    let terms: &[f64] = match window {
+        SpectrogramWindow::Hann => &[0.5, 0.5],
+        SpectrogramWindow::Hamming => &[0.54, 0.46],
+        SpectrogramWindow::Blackman => &[0.42, 0.5, 0.08],
+        SpectrogramWindow::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+        SpectrogramWindow::Rectangular => &[1.0],
+    };
+    (0..len)
+        .map(|i| {
+            let x = std::f64::consts::TAU * i as f64 / len as f64;
+            let w = terms.iter().enumerate().fold(0.0, |w, (k, &a)| {
+                let a = if k % 2 == 0 { a } else { -a };
+                a.mul_add((k as f64 * x).cos(), w)
+            });
+            w as f32
+        })
+        .collect()
+}
+
+// Where a pixel row takes its value from: the loudest bin of a range, or an interpolation
+// between two neighbouring bins when the row is narrower than a bin
+#[derive(Clone, Copy)]
+enum RowSource {
+    Max(usize, usize),
+    Lerp(usize, f32),
+}
+
+// Rows of one band, lowest frequency first
+fn row_sources(rows: usize, settings: &Settings, sample_rate: u32) -> Vec<RowSource> {
+    let last_bin = settings.fft_size / 2;
+    let nyquist = f64::from(sample_rate) / 2.0;
+    let min_freq = LOG_MIN_FREQ
+        .max(nyquist / last_bin as f64)
+        .min(nyquist / 2.0);
+    let freq_at = |r: f64| {
+        let t = r / rows as f64;
+        if settings.log_frequency {
+            min_freq * (nyquist / min_freq).powf(t)
+        } else {
+            t * nyquist
+        }
+    };
+    let bin_at = |freq: f64| freq / nyquist * last_bin as f64;
+
+    (0..rows)
+        .map(|r| {
+            let lo = bin_at(freq_at(r as f64)).ceil() as usize;
+            let hi = (bin_at(freq_at(r as f64 + 1.0)).floor() as usize).min(last_bin);
+            if lo <= hi {
+                RowSource::Max(lo, hi)
+            } else {
+                let center = bin_at(freq_at(r as f64 + 0.5));
+                let bin = (center.floor() as usize).min(last_bin - 1);
+                RowSource::Lerp(bin, (center - bin as f64) as f32)
+            }
+        })
+        .collect()
+}
+
+// Short-time spectra of every band, squeezed into at most twice the image width by averaging
+// neighbouring frames so memory stays bounded for any track length
+struct Spectrogram {
+    settings: Settings,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // Scales a full-scale sine to 0 dBFS
+    power_scale: f32,
+    bands: Vec<Vec<RowSource>>,
+    pending: Vec<Vec<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    columns: Vec<Vec<f32>>,
+    counts: Vec<u32>,
+    frames_per_column: u32,
+}
+
+impl Spectrogram {
+    fn new(settings: Settings, sample_rate: u32, band_count: usize) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(settings.fft_size);
+        let window = window_coefficients(settings.window, settings.fft_size);
+        let gain: f32 = window.iter().sum::<f32>() / 2.0;
+        let bands = (0..band_count)
+            .map(|b| {
+                let rows =
+                    (b + 1) * settings.height / band_count - b * settings.height / band_count;
+                row_sources(rows, &settings, sample_rate)
+            })
+            .collect();
+        Self {
+            scratch: vec![Complex::default(); fft.get_inplace_scratch_len()],
+            spectrum: vec![Complex::default(); settings.fft_size],
+            pending: vec![Vec::with_capacity(settings.fft_size * 2); band_count],
+            power_scale: 1.0 / (gain * gain),
+            fft,
+            window,
+            bands,
+            columns: Vec::new(),
+            counts: Vec::new(),
+            frames_per_column: 1,
+            settings,
+        }
+    }
+
+    // One sample per band
+    fn push(&mut self, samples: &[f32]) {
+        for (pending, &s) in self.pending.iter_mut().zip(samples) {
+            pending.push(s);
+        }
+        if self.pending[0].len() < self.settings.fft_size {
+            return;
+        }
+
+        let mut column = Vec::with_capacity(self.settings.height);
+        for (pending, sources) in self.pending.iter_mut().zip(&self.bands) {
+            for ((bin, &s), &w) in self
+                .spectrum
+                .iter_mut()
+                .zip(pending.iter())
+                .zip(&self.window)
+            {
+                *bin = Complex::new(s * w, 0.0);
+            }
+            pending.drain(..self.settings.hop);
+            self.fft
+                .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+            let power = |i: usize| self.spectrum[i].norm_sqr() * self.power_scale;
+            // Top row first, like the image
+            column.extend(sources.iter().rev().map(|&source| match source {
+                RowSource::Max(lo, hi) => (lo..=hi).map(power).fold(0.0, f32::max),
+                RowSource::Lerp(bin, t) => (power(bin + 1) - power(bin)).mul_add(t, power(bin)),
+            }));
+        }
+        self.add_frame(column);
+    }
+
+    fn add_frame(&mut self, frame: Vec<f32>) {
+        if let (Some(last), Some(count)) = (self.columns.last_mut(), self.counts.last_mut()) {
+            if *count < self.frames_per_column {
+                for (sum, p) in last.iter_mut().zip(&frame) {
+                    *sum += p;
+                }
+                *count += 1;
+                return;
+            }
+        }
+        if self.columns.len() == self.settings.width * 2 {
+            self.merge_columns();
+        }
+        self.columns.push(frame);
+        self.counts.push(1);
+    }
+
+    fn merge_columns(&mut self) {
+        let columns = std::mem::take(&mut self.columns);
+        let counts = std::mem::take(&mut self.counts);
+        for (pair, pair_counts) in columns.chunks(2).zip(counts.chunks(2)) {
+            let mut merged = pair[0].clone();
+            if let Some(second) = pair.get(1) {
+                for (sum, p) in merged.iter_mut().zip(second) {
+                    *sum += p;
+                }
+            }
+            self.columns.push(merged);
+            self.counts.push(pair_counts.iter().sum());
+        }
+        self.frames_per_column *= 2;
+    }
+
+    fn render(&self) -> Option<image::RgbImage> {
+        if self.columns.is_empty() {
+            return None;
+        }
+        let Settings {
+            width,
+            height,
+            min_db,
+            color_map,
+            ..
+        } = self.settings;
+        let total = self.columns.len();
+        let mut img = image::RgbImage::new(width as u32, height as u32);
+        for x in 0..width {
+            // Averages the columns under this pixel, or repeats one if there are fewer
+            let first = x * total / width;
+            let last = ((x + 1) * total / width).max(first + 1);
+            let count: u32 = self.counts[first..last].iter().sum();
+            for y in 0..height {
+                let sum: f32 = self.columns[first..last].iter().map(|c| c[y]).sum();
+                let db = 10.0 * (f64::from(sum) / f64::from(count)).max(POWER_FLOOR).log10();
+                let level = ((db - min_db) / -min_db).clamp(0.0, 1.0);
+                img.put_pixel(x as u32, y as u32, image::Rgb(color(color_map, level)));
+            }
+        }
+        Some(img)
+    }
+}
+
+// Nine evenly spaced stops of the matplotlib colour maps
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [31, 12, 72],
+    [85, 15, 109],
+    [136, 34, 106],
+    [186, 54, 85],
+    [227, 89, 51],
+    [249, 140, 10],
+    [249, 201, 50],
+    [252, 255, 164],
+];
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+fn color(map: SpectrogramColorMap, level: f64) -> [u8; 3] {
+    let stops: &[[u8; 3]] = match map {
+        SpectrogramColorMap::Magma => &MAGMA,
+        SpectrogramColorMap::Inferno => &INFERNO,
+        SpectrogramColorMap::Viridis => &VIRIDIS,
+        SpectrogramColorMap::Grayscale => &GRAYSCALE,
+    };
+    let pos = level * (stops.len() - 1) as f64;
+    let i = (pos.floor() as usize).min(stops.len() - 2);
+    let t = pos - i as f64;
+    std::array::from_fn(|c| {
+        let (a, b) = (f64::from(stops[i][c]), f64::from(stops[i + 1][c]));
+        (b - a).mul_add(t, a).round() as u8
+    })
+}
+
+fn build_spectrogram(path: &str, settings: Settings) -> Result<Vec<u8>> {
+    let mut track = OpenTrack::open(Path::new(path))
+        .ok_or_else(|| Error::from_reason("Unsupported or unreadable audio file"))?;
+
+    let params = &track.params;
+
+    let sample_rate = params.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let channels = params.channels.map_or(2, Channels::count).clamp(1, 8);
+    let view = match settings.view {
+        SpectrogramView::MidSide if channels < 2 => SpectrogramView::Mix,
+        view => view,
+    };
+    let band_count = match view {
+        SpectrogramView::Mix => 1,
+        SpectrogramView::Channels => channels.min(settings.height),
+        SpectrogramView::MidSide => 2.min(settings.height),
+    };
+    let (start, end) = (settings.start, settings.end);
+
+    // Formats that cannot seek are decoded from the beginning instead
+    if start > 0.0 {
+        let seeked = track.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(start),
+                track_id: Some(track.track_id),
+            },
+        );
+        if seeked.is_ok() {
+            track.decoder.reset();
+        }
+    }
+
+    let mut spectrogram = Spectrogram::new(settings, sample_rate, band_count);
+    let mut bands = [0.0f32; 8];
+    track.decode(|packet_time, frame_len, samples| {
+        if packet_time >= end {
+            return ControlFlow::Break(());
+        }
+        let frames = samples.len() / frame_len;
+
+        // Frames of this packet inside the range
+        let first = ((start - packet_time) * f64::from(sample_rate))
+            .ceil()
+            .max(0.0) as usize;
+        let last = ((end - packet_time) * f64::from(sample_rate))
+            .ceil()
+            .min(frames as f64) as usize;
+        for frame in samples.chunks_exact(frame_len).take(last).skip(first) {
+            let frame = &frame[..frame_len.min(8)];
+            match view {
+                SpectrogramView::Mix => {
+                    bands[0] = frame.iter().sum::<f32>() / frame.len() as f32;
+                }
+                SpectrogramView::Channels => {
+                    bands[..frame.len()].copy_from_slice(frame);
+                }
+                SpectrogramView::MidSide => {
+                    let (l, r) = (frame[0], frame.get(1).copied().unwrap_or(frame[0]));
+                    bands[0] = f32::midpoint(l, r);
+                    bands[1] = (l - r) / 2.0;
+                }
+            }
+            spectrogram.push(&bands[..band_count]);
+        }
+        ControlFlow::Continue(())
+    });
+
+    let img = spectrogram
+        .render()
+        .ok_or_else(|| Error::from_reason("Not enough audio in range for one FFT frame"))?;
+    let mut buf = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(&mut buf, image::ImageFormat::Png)
+        .context("Encode spectrogram failed")?;
+    Ok(buf.into_inner())
+}
+
+/// Renders the spectrum of `path` over time as a PNG, time left to right and frequency
+/// bottom to top
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn render_spectrogram(
+    path: String,
+    options: Option<SpectrogramOptions>,
+) -> Result<Buffer> {
+    let settings = Settings::new(&options.unwrap_or_default())?;
+    tokio::task::spawn_blocking(move || build_spectrogram(&path, settings))
+        .await
+        .context("Spectrogram task panicked or cancelled")?
+        .map(Buffer::from)
+}
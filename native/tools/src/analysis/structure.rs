@@ -0,0 +1,479 @@
+// Song structure: a self-similarity matrix of chroma and timbre, Foote novelty boundaries,
+// repetition groups from aligned diagonals and section names derived from the groups
+
+use napi_derive::napi;
+use serde::{Deserialize, Serialize};
+
+use super::beat::BAND_COUNT;
+use super::{snap_to_bar, AnalysisSegment};
+
+// Feature frame length, raised for long tracks so the matrix stays at most `MAX_FRAMES` wide
+const FRAME_SECS: f64 = 0.5;
+const MAX_FRAMES: usize = 1_200;
+// Features are averaged over a couple of bars, so frames follow neither the beat nor the chords
+const CONTEXT_SECS: f64 = 4.0;
+// Half width of the checkerboard kernel
+const KERNEL_SECS: f64 = 8.0;
+const MIN_SECTION_SECS: f64 = 8.0;
+// A novelty peak must stand this many deviations above the mean of its part, and above
+// `MIN_NOVELTY` so a steady loop stays one section
+const PEAK_DEVIATIONS: f64 = 0.5;
+const MIN_NOVELTY: f64 = 0.12;
+// Mean similarity along the aligned diagonal for two sections to count as a repetition
+const REPEAT_SIMILARITY: f32 = 0.8;
+// Alignment tolerance between repetitions
+const MAX_LAG_SECS: f64 = 1.0;
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SectionLabel {
+    Intro,
+    Verse,
+    Chorus,
+    Bridge,
+    Outro,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    pub start: f64,
+    pub end: f64,
+    pub label: SectionLabel,
+    /// Sections of the same group repeat the same material, numbered by first appearance
+    pub group: u32,
+    /// Mean level relative to the loudest section, 0.0 - 1.0
+    pub energy: f64,
+}
+
+struct Frame {
+    chroma: [f32; 12],
+    timbre: [f32; BAND_COUNT],
+    level: f32,
+}
+
+// Frames of one analysed part
+struct Part {
+    frames: std::ops::Range<usize>,
+    offset: f64,
+}
+
+fn mean_of<const N: usize>(rows: &[[f32; N]]) -> [f32; N] {
+    let mut mean = [0.0; N];
+    for row in rows {
+        for (m, &x) in mean.iter_mut().zip(row) {
+            *m += x;
+        }
+    }
+    for m in &mut mean {
+        *m /= rows.len().max(1) as f32;
+    }
+    mean
+}
+
+fn normalize<const N: usize>(v: &mut [f32; N]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in v.iter_mut() {
+            *x /= norm;
+        }
+    }
+}
+
+fn dot<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Finds the sections of all `parts` (segments and the time they start at). Boundaries are
+/// placed on the bar lines of `bar_grid` (tempo and bar times), if given.
+pub(super) fn detect_sections(
+    parts: &[(&AnalysisSegment, f64)],
+    env_rate: f64,
+    chroma_rate: f64,
+    onset_rate: f64,
+    bar_grid: Option<(f64, &[f64])>,
+    duration: f64,
+) -> Vec<Section> {
+    let total_secs: f64 = parts
+        .iter()
+        .map(|(segment, _)| segment.envelope.len() as f64 / env_rate)
+        .sum();
+    let frame_secs = FRAME_SECS.max(total_secs / MAX_FRAMES as f64);
+    let min_frames = (MIN_SECTION_SECS / frame_secs).round() as usize;
+
+    let mut frames = Vec::new();
+    let mut ranges = Vec::new();
+    for (segment, offset) in parts {
+        let start = frames.len();
+        frames.extend(segment_frames(
+            segment,
+            env_rate,
+            chroma_rate,
+            onset_rate,
+            frame_secs,
+        ));
+        if frames.len() - start < min_frames * 2 {
+            frames.truncate(start);
+            continue;
+        }
+        ranges.push(Part {
+            frames: start..frames.len(),
+            offset: *offset,
+        });
+    }
+    if ranges.is_empty() {
+        return Vec::new();
+    }
+
+    standardize_timbre(&mut frames);
+    let similarity = self_similarity(&frames);
+    let n = frames.len();
+    let at = |i: usize, j: usize| similarity[i * n + j];
+
+    // Boundaries in frames, every part split on its own
+    let kernel = (KERNEL_SECS / frame_secs).round() as usize;
+    let mut spans: Vec<(usize, usize, f64)> = Vec::new();
+    for part in &ranges {
+        let novelty = novelty(&at, part.frames.clone(), kernel);
+        let mut cuts = pick_peaks(&novelty, min_frames);
+        cuts.insert(0, 0);
+        cuts.push(part.frames.len());
+        for pair in cuts.windows(2) {
+            spans.push((
+                part.frames.start + pair[0],
+                part.frames.start + pair[1],
+                (part.frames.start as f64).mul_add(-frame_secs, part.offset),
+            ));
+        }
+    }
+
+    let groups = group_repetitions(&at, &spans, (MAX_LAG_SECS / frame_secs).round() as usize);
+    let levels: Vec<f64> = spans
+        .iter()
+        .map(|&(a, b, _)| {
+            frames[a..b].iter().map(|f| f64::from(f.level)).sum::<f64>() / (b - a) as f64
+        })
+        .collect();
+    let loudest = levels.iter().copied().fold(0.0, f64::max);
+
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, &(a, b, base)) in spans.iter().enumerate() {
+        let to_secs = |frame: usize| (frame as f64).mul_add(frame_secs, base);
+        let on_bar = |frame: usize| match bar_grid {
+            Some((bpm, bars)) if !bars.is_empty() => snap_to_bar(to_secs(frame), bpm, bars),
+            _ => to_secs(frame),
+        };
+        // The ends of a part are where the analysed audio stops, not boundaries
+        let start = if ranges.iter().any(|p| p.frames.start == a) {
+            to_secs(a)
+        } else {
+            on_bar(a)
+        };
+        let end = if ranges.iter().any(|p| p.frames.end == b) {
+            to_secs(b).min(duration)
+        } else {
+            on_bar(b)
+        };
+        let energy = if loudest > 0.0 {
+            levels[i] / loudest
+        } else {
+            0.0
+        };
+
+        // Neighbours of the same group are one section that the novelty split
+        if let Some(last) = sections.last_mut() {
+            if last.group == groups[i] && (last.end - start).abs() < frame_secs * 2.0 {
+                let (len_a, len_b) = (last.end - last.start, end - start);
+                last.energy = last.energy.mul_add(len_a, energy * len_b) / (len_a + len_b);
+                last.end = end;
+                continue;
+            }
+        }
+        sections.push(Section {
+            start,
+            end,
+            label: SectionLabel::Verse,
+            group: groups[i],
+            energy,
+        });
+    }
+    name_sections(&mut sections, duration, frame_secs);
+    sections
+}
+
+fn segment_frames(
+    segment: &AnalysisSegment,
+    env_rate: f64,
+    chroma_rate: f64,
+    onset_rate: f64,
+    frame_secs: f64,
+) -> Vec<Frame> {
+    let chroma: Vec<[f32; 12]> = segment.chroma.pitch_classes().collect();
+    let count = (segment.envelope.len() as f64 / env_rate / frame_secs) as usize;
+    let slice = |rate: f64, len: usize, i: usize| {
+        let lo = ((i as f64 * frame_secs * rate) as usize).min(len);
+        let hi = (((i + 1) as f64 * frame_secs * rate).ceil() as usize).clamp(lo, len);
+        lo..hi
+    };
+    let frames: Vec<Frame> = (0..count)
+        .map(|i| Frame {
+            chroma: mean_of(&chroma[slice(chroma_rate, chroma.len(), i)]),
+            timbre: mean_of(&segment.band_energy[slice(onset_rate, segment.band_energy.len(), i)]),
+            level: {
+                let env = &segment.envelope[slice(env_rate, segment.envelope.len(), i)];
+                env.iter().sum::<f32>() / env.len().max(1) as f32
+            },
+        })
+        .collect();
+
+    let context_len = ((CONTEXT_SECS / frame_secs).round() as usize).max(1);
+    (0..count)
+        .map(|i| {
+            let start = i.saturating_sub(context_len / 2);
+            let context = &frames[start..(start + context_len).min(count)];
+            let mut chroma = [0.0; 12];
+            let mut timbre = [0.0; BAND_COUNT];
+            for frame in context {
+                for (c, &x) in chroma.iter_mut().zip(&frame.chroma) {
+                    *c += x;
+                }
+                for (t, &x) in timbre.iter_mut().zip(&frame.timbre) {
+                    *t += x / context.len() as f32;
+                }
+            }
+            // Drums and noise raise all pitch classes alike
+            let floor = chroma.iter().copied().fold(f32::MAX, f32::min);
+            for c in &mut chroma {
+                *c -= floor;
+            }
+            normalize(&mut chroma);
+            Frame {
+                chroma,
+                timbre,
+                level: frames[i].level,
+            }
+        })
+        .collect()
+}
+
+// Every band to zero mean and unit deviation over the track, so the quiet high bands count as
+// much as the bass
+fn standardize_timbre(frames: &mut [Frame]) {
+    let n = frames.len() as f32;
+    for band in 0..BAND_COUNT {
+        let mean = frames.iter().map(|f| f.timbre[band]).sum::<f32>() / n;
+        let deviation = (frames
+            .iter()
+            .map(|f| (f.timbre[band] - mean).powi(2))
+            .sum::<f32>()
+            / n)
+            .sqrt();
+        for frame in frames.iter_mut() {
+            frame.timbre[band] = if deviation > 1e-6 {
+                (frame.timbre[band] - mean) / deviation
+            } else {
+                0.0
+            };
+        }
+    }
+    for frame in frames {
+        normalize(&mut frame.timbre);
+    }
+}
+
+// Row-major, 0.0 - 1.0, chroma and timbre weighted equally
+fn self_similarity(frames: &[Frame]) -> Vec<f32> {
+    let n = frames.len();
+    let mut matrix = vec![0.0; n * n];
+    for i in 0..n {
+        for j in i..n {
+            let chroma = dot(&frames[i].chroma, &frames[j].chroma);
+            let timbre = dot(&frames[i].timbre, &frames[j].timbre).max(0.0);
+            let s = f32::midpoint(chroma, timbre);
+            matrix[i * n + j] = s;
+            matrix[j * n + i] = s;
+        }
+    }
+    matrix
+}
+
+// Correlation of a Gaussian tapered checkerboard kernel along the diagonal, high where the
+// past and the future are each homogeneous but differ from each other
+fn novelty(
+    at: &impl Fn(usize, usize) -> f32,
+    frames: std::ops::Range<usize>,
+    half: usize,
+) -> Vec<f64> {
+    let half = half.max(1) as isize;
+    let taper = |k: isize| {
+        let x = (k as f64 + 0.5) / half as f64;
+        (-2.0 * x * x).exp()
+    };
+    let (lo, hi) = (frames.start as isize, frames.end as isize);
+    (lo..hi)
+        .map(|center| {
+            let (mut sum, mut weight) = (0.0, 0.0);
+            for a in -half..half {
+                for b in -half..half {
+                    let (i, j) = (center + a, center + b);
+                    if i < lo || j < lo || i >= hi || j >= hi {
+                        continue;
+                    }
+                    let w = taper(a.min(-a - 1)) * taper(b.min(-b - 1));
+                    let sign = if (a < 0) == (b < 0) { 1.0 } else { -1.0 };
+                    sum += sign * w * f64::from(at(i as usize, j as usize));
+                    weight += w;
+                }
+            }
+            if weight > 0.0 {
+                sum / weight
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+// Strongest local maxima first, each at least `min_gap` frames from the others and the ends
+fn pick_peaks(novelty: &[f64], min_gap: usize) -> Vec<usize> {
+    // The truncated kernel runs high at the ends, which can't hold a peak anyway
+    let gap = min_gap.max(1);
+    let inner = novelty
+        .get(gap..novelty.len().saturating_sub(gap))
+        .unwrap_or_default();
+    let n = inner.len().max(1) as f64;
+    let mean = inner.iter().sum::<f64>() / n;
+    let deviation = (inner.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let threshold = PEAK_DEVIATIONS.mul_add(deviation, mean).max(MIN_NOVELTY);
+
+    let mut candidates: Vec<usize> = (gap..=novelty.len().saturating_sub(gap))
+        .filter(|&i| {
+            novelty[i] > threshold
+                && novelty[i] >= novelty[i - 1]
+                && novelty.get(i + 1).is_none_or(|&next| novelty[i] >= next)
+        })
+        .collect();
+    candidates.sort_by(|&a, &b| novelty[b].total_cmp(&novelty[a]));
+    let mut peaks: Vec<usize> = Vec::new();
+    for i in candidates {
+        if peaks.iter().all(|&p| p.abs_diff(i) >= min_gap) {
+            peaks.push(i);
+        }
+    }
+    peaks.sort_unstable();
+    peaks
+}
+
+// Best mean similarity along the diagonal starting at both sections, within `max_lag` frames
+fn repetition(
+    at: &impl Fn(usize, usize) -> f32,
+    a: (usize, usize),
+    b: (usize, usize),
+    max_lag: usize,
+) -> f32 {
+    let len = (a.1 - a.0).min(b.1 - b.0);
+    (0..=2 * max_lag)
+        .filter_map(|shift| {
+            let start = (b.0 + shift).checked_sub(max_lag)?;
+            let steps = len.min(b.1.saturating_sub(start));
+            (steps > 0)
+                .then(|| (0..steps).map(|k| at(a.0 + k, start + k)).sum::<f32>() / steps as f32)
+        })
+        .fold(0.0, f32::max)
+}
+
+// In order of appearance: a section joins the group of its most similar predecessor, or
+// starts a new one
+fn group_repetitions(
+    at: &impl Fn(usize, usize) -> f32,
+    spans: &[(usize, usize, f64)],
+    max_lag: usize,
+) -> Vec<u32> {
+    let mut groups: Vec<u32> = Vec::with_capacity(spans.len());
+    let mut next_group = 0;
+    for (i, &(a0, a1, _)) in spans.iter().enumerate() {
+        let best = spans[..i]
+            .iter()
+            .enumerate()
+            .map(|(j, &(b0, b1, _))| (j, repetition(at, (b0, b1), (a0, a1), max_lag)))
+            .max_by(|x, y| x.1.total_cmp(&y.1))
+            .filter(|&(_, similarity)| similarity >= REPEAT_SIMILARITY);
+        groups.push(best.map_or_else(
+            || {
+                next_group += 1;
+                next_group - 1
+            },
+            |(j, _)| groups[j],
+        ));
+    }
+    groups
+}
+
+// The loudest repeated group is the chorus and other repeated groups are verses. Material
+// heard once is the intro or outro at the ends of the track, a verse before the first chorus
+// and a bridge after it.
+fn name_sections(sections: &mut [Section], duration: f64, tolerance: f64) {
+    let count = |group: u32| sections.iter().filter(|s| s.group == group).count();
+    let group_energy = |group: u32| {
+        let (sum, len) =
+            sections
+                .iter()
+                .filter(|s| s.group == group)
+                .fold((0.0, 0.0), |(sum, len), s| {
+                    (
+                        s.energy.mul_add(s.end - s.start, sum),
+                        len + s.end - s.start,
+                    )
+                });
+        sum / len
+    };
+    let chorus = sections
+        .iter()
+        .map(|s| s.group)
+        .filter(|&g| count(g) > 1)
+        .max_by(|&a, &b| group_energy(a).total_cmp(&group_energy(b)));
+    // Without repetitions the loudest section in the middle stands in for the chorus
+    let last = sections.len().saturating_sub(1);
+    let loudest_middle = chorus.is_none().then(|| {
+        sections
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i > 0 && i < last)
+            .max_by(|a, b| a.1.energy.total_cmp(&b.1.energy))
+            .map(|(i, _)| i)
+    });
+
+    // The intro and outro frame at least one section and may share material with each other
+    // but not with the middle
+    let in_middle = |group: u32| {
+        sections
+            .iter()
+            .enumerate()
+            .any(|(i, s)| i > 0 && i < last && s.group == group)
+    };
+    let is_intro = last > 1
+        && sections.first().is_some_and(|s| {
+            s.start <= tolerance && Some(s.group) != chorus && !in_middle(s.group)
+        });
+    let is_outro = last > 1
+        && sections.last().is_some_and(|s| {
+            s.end >= duration - tolerance && Some(s.group) != chorus && !in_middle(s.group)
+        });
+
+    let repeated: Vec<bool> = sections.iter().map(|s| count(s.group) > 1).collect();
+    let mut after_chorus = false;
+    for (i, section) in sections.iter_mut().enumerate() {
+        section.label = if Some(section.group) == chorus || loudest_middle.flatten() == Some(i) {
+            after_chorus = true;
+            SectionLabel::Chorus
+        } else if i == 0 && is_intro {
+            SectionLabel::Intro
+        } else if i == last && is_outro {
+            SectionLabel::Outro
+        } else if repeated[i] || !after_chorus {
+            SectionLabel::Verse
+        } else {
+            SectionLabel::Bridge
+        };
+    }
+}
@@ -0,0 +1,81 @@
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::{AudioAnalysis, TrackAnalyzer};
+use crate::utils::Context;
+
+pub(super) type ProgressCallback = Box<dyn FnMut(AnalysisProgress) + Send>;
+
+#[napi(object)]
+#[derive(Clone, Copy)]
+pub struct AnalysisProgress {
+    /// Seconds decoded so far
+    pub position: f64,
+    /// Duration from the container, if it has one
+    pub duration: Option<f64>,
+    /// 0.0 - 1.0, `None` while the duration is unknown
+    pub percent: Option<f64>,
+}
+
+/// Full-track analysis. Unlike `analyze_audio_file` it does not stop after
+/// `max_analyze_time`, every field of the result covers the whole track.
+#[napi]
+pub struct AnalysisTask {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[napi]
+impl AnalysisTask {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[napi]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Decodes the whole file in bounded memory. Resolves to `null` if the file cannot be
+    /// decoded and rejects with `Cancelled` after `cancel`.
+    #[napi]
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn analyze(
+        &self,
+        path: String,
+        on_progress: Option<ThreadsafeFunction<AnalysisProgress>>,
+    ) -> Result<Option<AudioAnalysis>> {
+        let cancelled = self.cancelled.clone();
+        let callback = on_progress.map(|callback| -> ProgressCallback {
+            Box::new(move |progress| {
+                callback.call(Ok(progress), ThreadsafeFunctionCallMode::NonBlocking);
+            })
+        });
+
+        let analysis = tokio::task::spawn_blocking({
+            let cancelled = cancelled.clone();
+            move || TrackAnalyzer::full_track(path, cancelled, callback).analyze()
+        })
+        .await
+        .context("Analysis task panicked or cancelled")?;
+
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                Status::Cancelled,
+                "Analysis cancelled".to_string(),
+            ));
+        }
+        Ok(analysis)
+    }
+}
+
+impl Default for AnalysisTask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,447 @@
+// Vocal activity: periodicity (harmonicity), spectral flatness and pitch of the vocal band per
+// frame, then pitch and syllable modulation over short windows, combined into a probability
+// that hysteresis cuts into segments
+
+use napi_derive::napi;
+use num_complex::Complex32;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::key::{hz_to_midi, low_pass};
+use super::pool::{Poolable, Pooled};
+use super::BiquadFilter;
+
+// Audio is decimated to about this rate, a voice has little of its energy above 5 kHz
+const TARGET_RATE: u32 = 11_025;
+// ~93 ms frames every ~23 ms, fast enough to follow vibrato
+const FRAME_LEN: usize = 1024;
+const HOP_LEN: usize = 256;
+// Vocal band, and the range of sung fundamentals in Hz
+const BAND_LOW: f64 = 150.0;
+const BAND_HIGH: f64 = 4_000.0;
+const MIN_F0: f64 = 80.0;
+const MAX_F0: f64 = 1_000.0;
+// Among lags this close to the best one the shortest wins, which avoids octave errors
+const OCTAVE_TOLERANCE: f32 = 0.9;
+const LOG_COMPRESSION: f32 = 1e4;
+// Frames with less of their power in the vocal band are left unvoiced, whatever leaks into the
+// band from a bass or kick is periodic too
+const MIN_BAND_SHARE: f32 = 0.02;
+
+// A frame is voiced above this harmonicity
+const VOICED_HARMONICITY: f32 = 0.5;
+// Pitch steps larger than this are note changes rather than vibrato or glides
+const MAX_PITCH_STEP_CENTS: f32 = 100.0;
+// Windows the features are summarised over, and the hop between them
+const WINDOW_SECS: f64 = 1.0;
+const BLOCK_SECS: f64 = 0.25;
+// Span of the running median over block probabilities, which drops brief false alarms
+const MEDIAN_SECS: f64 = 2.0;
+// Syllables come at about 3 - 8 per second
+const SYLLABLE_HZ: (f64, f64) = (3.0, 8.0);
+// Hysteresis on the vocal probability, and the shortest segment and gap that count
+const ENTER_PROBABILITY: f64 = 0.6;
+const LEAVE_PROBABILITY: f64 = 0.4;
+const MIN_SEGMENT_SECS: f64 = 1.0;
+const MAX_GAP_SECS: f64 = 1.5;
+
+#[napi(object)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VocalSegment {
+    pub start: f64,
+    pub end: f64,
+    /// Mean vocal probability over the segment, 0.0 - 1.0
+    pub confidence: f64,
+}
+
+/// Vocal feature frames per second
+pub(super) fn vocal_rate(sample_rate: u32) -> f64 {
+    f64::from(sample_rate) / decimation(sample_rate) as f64 / HOP_LEN as f64
+}
+
+fn decimation(sample_rate: u32) -> usize {
+    (sample_rate / TARGET_RATE).max(1) as usize
+}
+
+#[derive(Clone, Copy)]
+struct VocalFrame {
+    // Log energy of the vocal band
+    energy: f32,
+    // Normalised autocorrelation at the pitch period, 0.0 (noise) - 1.0 (periodic)
+    harmonicity: f32,
+    // Geometric over arithmetic mean of the band's power, 0.0 (tonal) - 1.0 (noise)
+    flatness: f32,
+    // Fundamental in semitones (MIDI), meaningful only if the frame is voiced
+    pitch: f32,
+}
+
+impl Poolable for VocalFrame {
+    fn add(&mut self, other: &Self) {
+        self.energy += other.energy;
+        self.harmonicity += other.harmonicity;
+        self.flatness += other.flatness;
+        self.pitch += other.pitch;
+    }
+
+    fn scale(&mut self, factor: f32) {
+        self.energy *= factor;
+        self.harmonicity *= factor;
+        self.flatness *= factor;
+        self.pitch *= factor;
+    }
+}
+
+/// Vocal features of one analysed segment
+#[derive(Default)]
+pub(super) struct VocalFrames {
+    frames: Pooled<VocalFrame>,
+}
+
+impl VocalFrames {
+    pub(super) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(super) fn coarsen(&mut self) {
+        self.frames.coarsen();
+    }
+}
+
+/// Streams mono samples into `VocalFrames`
+pub(super) struct VocalAnalyzer {
+    decimation: usize,
+    rate: f64,
+    anti_alias: [BiquadFilter; 2],
+    phase: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // Autocorrelation of the window, which the signal's is divided by
+    window_correlation: Vec<f32>,
+    // The last `FRAME_LEN` decimated samples written twice so a frame never wraps
+    history: Vec<f32>,
+    pos: usize,
+    since_hop: usize,
+    buffer: Vec<Complex32>,
+    band: std::ops::Range<usize>,
+    lags: std::ops::RangeInclusive<usize>,
+}
+
+impl VocalAnalyzer {
+    pub(super) fn new(sample_rate: u32) -> Self {
+        let decimation = decimation(sample_rate);
+        let rate = f64::from(sample_rate) / decimation as f64;
+        let coeffs = low_pass(f64::from(sample_rate), rate * 0.45);
+        let window: Vec<f32> = (0..FRAME_LEN)
+            .map(|i| {
+                0.5f32.mul_add(
+                    -(2.0 * std::f32::consts::PI * i as f32 / FRAME_LEN as f32).cos(),
+                    0.5,
+                )
+            })
+            .collect();
+        let window_energy: f32 = window.iter().map(|w| w * w).sum();
+        let window_correlation = (0..FRAME_LEN)
+            .map(|lag| {
+                window[..FRAME_LEN - lag]
+                    .iter()
+                    .zip(&window[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>()
+                    / window_energy
+            })
+            .collect();
+        let bin = |hz: f64| (hz * FRAME_LEN as f64 / rate).round() as usize;
+        let lag = |hz: f64| (rate / hz).round() as usize;
+
+        let mut planner = FftPlanner::new();
+        Self {
+            decimation,
+            rate,
+            anti_alias: [BiquadFilter::new(coeffs), BiquadFilter::new(coeffs)],
+            phase: 0,
+            fft: planner.plan_fft_forward(FRAME_LEN),
+            ifft: planner.plan_fft_inverse(FRAME_LEN),
+            window,
+            window_correlation,
+            history: vec![0.0; FRAME_LEN * 2],
+            pos: 0,
+            since_hop: 0,
+            buffer: vec![Complex32::new(0.0, 0.0); FRAME_LEN],
+            band: bin(BAND_LOW).max(1)..bin(BAND_HIGH.min(rate * 0.45)),
+            lags: lag(MAX_F0)..=lag(MIN_F0).min(FRAME_LEN / 2),
+        }
+    }
+
+    pub(super) fn process(&mut self, sample: f32, out: &mut VocalFrames) {
+        let filtered = self
+            .anti_alias
+            .iter_mut()
+            .fold(f64::from(sample), |x, filter| filter.process(x));
+        self.phase += 1;
+        if self.phase < self.decimation {
+            return;
+        }
+        self.phase = 0;
+
+        self.pos = (self.pos + 1) % FRAME_LEN;
+        self.history[self.pos] = filtered as f32;
+        self.history[self.pos + FRAME_LEN] = filtered as f32;
+        self.since_hop += 1;
+        if self.since_hop < HOP_LEN {
+            return;
+        }
+        self.since_hop = 0;
+        out.frames.push(self.analyze_frame());
+    }
+
+    fn analyze_frame(&mut self) -> VocalFrame {
+        let frame = &self.history[self.pos + 1..=self.pos + FRAME_LEN];
+        for ((out, &x), &w) in self.buffer.iter_mut().zip(frame).zip(&self.window) {
+            *out = Complex32::new(x * w, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+
+        let power: Vec<f32> = self.buffer[self.band.clone()]
+            .iter()
+            .map(Complex32::norm_sqr)
+            .collect();
+        let total: f32 = power.iter().sum();
+        let energy = (LOG_COMPRESSION * total / (FRAME_LEN * FRAME_LEN) as f32).ln_1p();
+        let silent = VocalFrame {
+            energy,
+            harmonicity: 0.0,
+            flatness: 1.0,
+            pitch: 0.0,
+        };
+        let full: f32 = self.buffer[..FRAME_LEN / 2]
+            .iter()
+            .map(Complex32::norm_sqr)
+            .sum();
+        if total <= f32::EPSILON || total < MIN_BAND_SHARE * full {
+            return silent;
+        }
+        let log_mean = power.iter().map(|p| (p + 1e-12).ln()).sum::<f32>() / power.len() as f32;
+        let flatness = (log_mean.exp() / (total / power.len() as f32)).min(1.0);
+
+        // Autocorrelation of the band-limited signal is the inverse transform of its power
+        for (k, x) in self.buffer.iter_mut().enumerate() {
+            let bin = k.min(FRAME_LEN - k);
+            *x = if self.band.contains(&bin) {
+                Complex32::new(x.norm_sqr(), 0.0)
+            } else {
+                Complex32::new(0.0, 0.0)
+            };
+        }
+        self.ifft.process(&mut self.buffer);
+        let zero = self.buffer[0].re;
+        if zero <= 0.0 {
+            return silent;
+        }
+        let correlation = |lag: usize| self.buffer[lag].re / zero / self.window_correlation[lag];
+
+        let best = self.lags.clone().map(correlation).fold(0.0f32, f32::max);
+        if best <= 0.0 {
+            return VocalFrame { flatness, ..silent };
+        }
+        let lag = self
+            .lags
+            .clone()
+            .find(|&lag| {
+                let r = correlation(lag);
+                r >= best * OCTAVE_TOLERANCE
+                    && r >= correlation(lag - 1)
+                    && r >= correlation(lag + 1)
+            })
+            .unwrap_or_else(|| *self.lags.start());
+
+        // Parabolic interpolation for a pitch finer than one sample of lag
+        let (prev, here, next) = (correlation(lag - 1), correlation(lag), correlation(lag + 1));
+        let curvature = 2.0f32.mul_add(-here, prev + next);
+        let shift = if curvature < 0.0 {
+            (0.5 * (prev - next) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let hz = self.rate / (lag as f64 + f64::from(shift));
+        VocalFrame {
+            energy,
+            harmonicity: here.clamp(0.0, 1.0),
+            flatness,
+            pitch: hz_to_midi(hz) as f32,
+        }
+    }
+}
+
+// Features of one window, the shares and means 0.0 - 1.0
+struct WindowFeatures {
+    voiced: f64,
+    harmonicity: f64,
+    flatness: f64,
+    // Steady pitch movement between voiced frames in cents per frame, vibrato and glides
+    pitch_motion: f64,
+    // Share of the energy envelope's modulation at syllable rate
+    syllables: f64,
+}
+
+fn window_features(frames: &[VocalFrame], rate: f64) -> WindowFeatures {
+    let n = frames.len().max(1) as f64;
+    let voiced = |f: &VocalFrame| f.harmonicity > VOICED_HARMONICITY;
+
+    // Vibrato and glides move the pitch the same way over consecutive frames, estimation jitter
+    // alternates, so the product of consecutive steps keeps only the former
+    let (mut motion, mut steps) = (0.0, 0);
+    for triple in frames.windows(3) {
+        if triple.iter().all(voiced) {
+            let first = (triple[1].pitch - triple[0].pitch) * 100.0;
+            let second = (triple[2].pitch - triple[1].pitch) * 100.0;
+            if first.abs().max(second.abs()) < MAX_PITCH_STEP_CENTS {
+                motion += f64::from(first * second);
+                steps += 1;
+            }
+        }
+    }
+
+    // DFT of the energy envelope at whole Hz, the resolution of a one second window
+    let mean_energy = frames.iter().map(|f| f64::from(f.energy)).sum::<f64>() / n;
+    let (mut syllable_power, mut total_power) = (0.0, 0.0);
+    let max_hz = (rate / 2.0).floor() as usize;
+    for hz in 1..=max_hz {
+        let omega = 2.0 * std::f64::consts::PI * hz as f64 / rate;
+        let (re, im) = frames
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (i, f)| {
+                let x = f64::from(f.energy) - mean_energy;
+                let phase = omega * i as f64;
+                (x.mul_add(phase.cos(), re), x.mul_add(-phase.sin(), im))
+            });
+        let power = re.mul_add(re, im * im);
+        total_power += power;
+        if (SYLLABLE_HZ.0..=SYLLABLE_HZ.1).contains(&(hz as f64)) {
+            syllable_power += power;
+        }
+    }
+
+    WindowFeatures {
+        voiced: frames.iter().filter(|f| voiced(f)).count() as f64 / n,
+        harmonicity: frames.iter().map(|f| f64::from(f.harmonicity)).sum::<f64>() / n,
+        flatness: frames.iter().map(|f| f64::from(f.flatness)).sum::<f64>() / n,
+        pitch_motion: if steps >= frames.len() / 4 && steps > 0 {
+            (motion / steps as f64).max(0.0).sqrt()
+        } else {
+            0.0
+        },
+        syllables: if total_power > 0.0 {
+            syllable_power / total_power
+        } else {
+            0.0
+        },
+    }
+}
+
+// Logistic over the features, pitch motion separates a voice from steady synths and plucked
+// strings that are just as harmonic
+fn vocal_probability(features: &WindowFeatures) -> f64 {
+    let score = 0.8f64.mul_add(
+        features.pitch_motion - 11.0,
+        3.0f64.mul_add(
+            features.harmonicity - 0.55,
+            2.0f64.mul_add(
+                features.voiced - 0.5,
+                1.0f64.mul_add(features.syllables - 0.4, -15.0 * (features.flatness - 0.05)),
+            ),
+        ),
+    );
+    1.0 / (1.0 + (-score).exp())
+}
+
+/// Vocal segments of all `parts` (frames and the time they start at), within `range`
+pub(super) fn detect_vocals(
+    parts: &[(&VocalFrames, f64)],
+    rate: f64,
+    range: (f64, f64),
+) -> Vec<VocalSegment> {
+    let window = (WINDOW_SECS * rate).round() as usize;
+    let hop = ((BLOCK_SECS * rate).round() as usize).max(1);
+    let mut segments: Vec<VocalSegment> = Vec::new();
+
+    for (part, offset) in parts {
+        let frames = &part.frames;
+        if frames.len() < window {
+            continue;
+        }
+        // Probability of every block, from the window centred on it
+        let blocks: Vec<(f64, f64)> = (0..frames.len() / hop)
+            .map(|b| {
+                let center = b * hop + hop / 2;
+                let start = center.saturating_sub(window / 2).min(frames.len() - window);
+                let features = window_features(&frames[start..start + window], rate);
+                let time = offset + (b * hop) as f64 / rate;
+                (time, vocal_probability(&features))
+            })
+            .collect();
+
+        let radius = (MEDIAN_SECS / 2.0 / BLOCK_SECS).round() as usize;
+        let blocks: Vec<(f64, f64)> = (0..blocks.len())
+            .map(|i| {
+                let mut near: Vec<f64> = blocks
+                    [i.saturating_sub(radius)..(i + radius + 1).min(blocks.len())]
+                    .iter()
+                    .map(|&(_, p)| p)
+                    .collect();
+                near.sort_by(f64::total_cmp);
+                (blocks[i].0, near[near.len() / 2])
+            })
+            .collect();
+
+        let block_secs = hop as f64 / rate;
+        let mut open: Option<(f64, f64, usize)> = None;
+        for (i, &(time, p)) in blocks.iter().enumerate() {
+            let is_last = i + 1 == blocks.len();
+            open = match open {
+                None if p > ENTER_PROBABILITY => Some((time, p, 1)),
+                Some((start, sum, count)) if p >= LEAVE_PROBABILITY && !is_last => {
+                    Some((start, sum + p, count + 1))
+                }
+                Some((start, sum, count)) => {
+                    let end = if p >= LEAVE_PROBABILITY {
+                        time + block_secs
+                    } else {
+                        time
+                    };
+                    push_segment(&mut segments, start, end, sum / count as f64);
+                    None
+                }
+                None => None,
+            };
+        }
+    }
+
+    segments
+        .into_iter()
+        .filter_map(|mut s| {
+            s.start = s.start.max(range.0);
+            s.end = s.end.min(range.1);
+            (s.end - s.start >= MIN_SEGMENT_SECS).then_some(s)
+        })
+        .collect()
+}
+
+// Joins a segment to the previous one across a short gap
+fn push_segment(segments: &mut Vec<VocalSegment>, start: f64, end: f64, confidence: f64) {
+    if let Some(last) = segments.last_mut() {
+        if start - last.end < MAX_GAP_SECS {
+            let (len_a, len_b) = (last.end - last.start, end - start);
+            last.confidence = last.confidence.mul_add(len_a, confidence * len_b) / (len_a + len_b);
+            last.end = end;
+            return;
+        }
+    }
+    segments.push(VocalSegment {
+        start,
+        end,
+        confidence,
+    });
+}
@@ -0,0 +1,326 @@
+// Min/max/RMS peaks for drawing waveforms, at several zoom levels from one decode
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use std::ops::ControlFlow;
+use std::path::Path;
+use symphonia::core::audio::Channels;
+
+use super::{AnalysisProgress, OpenTrack, DEFAULT_SAMPLE_RATE};
+use crate::utils::Context;
+
+// Binary layout, all little-endian:
+//   magic "WFPK", version u16, channels u16, sample rate u32, duration f64, level count u16
+//   per level: samples per pixel u32, point count u32, then for every point and channel
+//   min i16, max i16, rms i16 (full scale is 32767)
+const MAGIC: &[u8; 4] = b"WFPK";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 20;
+const LEVEL_HEADER_LEN: usize = 8;
+const POINT_LEN: usize = 6;
+const FULL_SCALE: f64 = 32767.0;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct WaveformPeaks {
+    pub min: Vec<f64>,
+    pub max: Vec<f64>,
+    pub rms: Vec<f64>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct WaveformLevel {
+    /// Frames summarised by each point
+    pub samples_per_pixel: u32,
+    /// One entry per channel, values are -1.0 - 1.0
+    pub channels: Vec<WaveformPeaks>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Decoded length in seconds
+    pub duration: f64,
+    /// In the order of the requested resolutions
+    pub levels: Vec<WaveformLevel>,
+}
+
+// Running min/max/sum of squares of the current point of one channel
+#[derive(Clone, Copy)]
+struct Bucket {
+    min: f32,
+    max: f32,
+    sum_sq: f64,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self {
+            min: f32::MAX,
+            max: f32::MIN,
+            sum_sq: 0.0,
+        }
+    }
+}
+
+struct LevelBuilder {
+    samples_per_pixel: usize,
+    filled: usize,
+    buckets: Vec<Bucket>,
+    peaks: Vec<WaveformPeaks>,
+}
+
+impl LevelBuilder {
+    fn new(samples_per_pixel: u32, channels: usize) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel as usize,
+            filled: 0,
+            buckets: vec![Bucket::default(); channels],
+            peaks: vec![WaveformPeaks::default(); channels],
+        }
+    }
+
+    fn push(&mut self, frame: &[f32]) {
+        for (bucket, &s) in self.buckets.iter_mut().zip(frame) {
+            bucket.min = bucket.min.min(s);
+            bucket.max = bucket.max.max(s);
+            bucket.sum_sq += f64::from(s) * f64::from(s);
+        }
+        self.filled += 1;
+        if self.filled == self.samples_per_pixel {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.filled == 0 {
+            return;
+        }
+        for (bucket, peaks) in self.buckets.iter_mut().zip(&mut self.peaks) {
+            peaks.min.push(f64::from(bucket.min));
+            peaks.max.push(f64::from(bucket.max));
+            peaks.rms.push((bucket.sum_sq / self.filled as f64).sqrt());
+            *bucket = Bucket::default();
+        }
+        self.filled = 0;
+    }
+
+    fn finish(mut self) -> WaveformLevel {
+        self.flush();
+        WaveformLevel {
+            samples_per_pixel: self.samples_per_pixel as u32,
+            channels: self.peaks,
+        }
+    }
+}
+
+fn build_waveform(
+    path: &str,
+    resolutions: &[u32],
+    mut on_progress: impl FnMut(AnalysisProgress),
+) -> Result<Waveform> {
+    let mut track = OpenTrack::open(Path::new(path))
+        .ok_or_else(|| Error::from_reason("Unsupported or unreadable audio file"))?;
+
+    let params = &track.params;
+    let sample_rate = params.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let channels = params.channels.map_or(2, Channels::count).clamp(1, 8);
+    let duration = params
+        .n_frames
+        .map(|n| n as f64 / f64::from(sample_rate))
+        .filter(|&d| d > 0.0);
+    let mut levels: Vec<LevelBuilder> = resolutions
+        .iter()
+        .map(|&spp| LevelBuilder::new(spp, channels))
+        .collect();
+
+    let mut frames_done = 0;
+    let mut last_percent = -1;
+    let decoded = track.decode(|_, frame_len, samples| {
+        for frame in samples.chunks_exact(frame_len) {
+            for level in &mut levels {
+                level.push(frame);
+            }
+        }
+        frames_done += samples.len() / frame_len;
+
+        // At most once per percent
+        let position = frames_done as f64 / f64::from(sample_rate);
+        let percent = duration.map(|d| (position / d).clamp(0.0, 1.0));
+        if let Some(p) = percent.filter(|p| (p * 100.0) as i32 > last_percent) {
+            last_percent = (p * 100.0) as i32;
+            on_progress(AnalysisProgress {
+                position,
+                duration,
+                percent,
+            });
+        }
+        ControlFlow::Continue(())
+    });
+
+    let decoded_frames = decoded.ok_or_else(|| Error::from_reason("No audio could be decoded"))?;
+    Ok(Waveform {
+        sample_rate,
+        channels: channels as u32,
+        duration: decoded_frames as f64 / f64::from(sample_rate),
+        levels: levels.into_iter().map(LevelBuilder::finish).collect(),
+    })
+}
+
+/// Decodes the whole file once and computes min/max/RMS peaks per channel for every
+/// resolution, given as frames per point (e.g. `[256, 1024, 4096]`)
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn generate_waveform(
+    path: String,
+    resolutions: Vec<u32>,
+    on_progress: Option<ThreadsafeFunction<AnalysisProgress>>,
+) -> Result<Waveform> {
+    if resolutions.is_empty() || resolutions.contains(&0) {
+        return Err(Error::from_reason(
+            "Resolutions must be non-empty and greater than zero",
+        ));
+    }
+
+    tokio::task::spawn_blocking(move || {
+        build_waveform(&path, &resolutions, |progress| {
+            if let Some(callback) = &on_progress {
+                callback.call(Ok(progress), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        })
+    })
+    .await
+    .context("Waveform task panicked or cancelled")?
+}
+
+fn quantize(value: f64) -> [u8; 2] {
+    ((value.clamp(-1.0, 1.0) * FULL_SCALE).round() as i16).to_le_bytes()
+}
+
+/// Packs a waveform into a compact binary form with 16-bit peaks, for caching on disk
+#[napi]
+#[allow(clippy::needless_pass_by_value, clippy::missing_errors_doc)]
+pub fn encode_waveform(waveform: Waveform) -> Result<Buffer> {
+    let channels = waveform.channels as usize;
+    let invalid = |reason: &str| Err(Error::from_reason(format!("Invalid waveform: {reason}")));
+    if !(1..=u16::MAX as usize).contains(&channels) {
+        return invalid("bad channel count");
+    }
+
+    let mut out = Vec::with_capacity(HEADER_LEN);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(channels as u16).to_le_bytes());
+    out.extend_from_slice(&waveform.sample_rate.to_le_bytes());
+    out.extend_from_slice(&waveform.duration.to_le_bytes());
+    let Ok(level_count) = u16::try_from(waveform.levels.len()) else {
+        return invalid("too many levels");
+    };
+    out.extend_from_slice(&level_count.to_le_bytes());
+
+    for level in &waveform.levels {
+        let len = level.channels.first().map_or(0, |peaks| peaks.min.len());
+        let consistent = level.channels.len() == channels
+            && level.channels.iter().all(|peaks| {
+                peaks.min.len() == len && peaks.max.len() == len && peaks.rms.len() == len
+            });
+        if !consistent {
+            return invalid("peak arrays differ in length or channel count");
+        }
+        out.reserve(LEVEL_HEADER_LEN + len * channels * POINT_LEN);
+        out.extend_from_slice(&level.samples_per_pixel.to_le_bytes());
+        out.extend_from_slice(&(len as u32).to_le_bytes());
+        for i in 0..len {
+            for peaks in &level.channels {
+                out.extend_from_slice(&quantize(peaks.min[i]));
+                out.extend_from_slice(&quantize(peaks.max[i]));
+                out.extend_from_slice(&quantize(peaks.rms[i]));
+            }
+        }
+    }
+    Ok(out.into())
+}
+
+// Little-endian reader over the encoded form, `None` once the data runs out
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.data.split_at_checked(N)?;
+        self.data = rest;
+        head.try_into().ok()
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn peak(&mut self) -> Option<f64> {
+        self.take()
+            .map(|b| f64::from(i16::from_le_bytes(b)) / FULL_SCALE)
+    }
+}
+
+fn parse_waveform(data: &[u8]) -> Option<Waveform> {
+    let mut reader = Reader { data };
+    if &reader.take::<4>()? != MAGIC || reader.u16()? != FORMAT_VERSION {
+        return None;
+    }
+    let channels = usize::from(reader.u16()?);
+    let sample_rate = reader.u32()?;
+    let duration = f64::from_le_bytes(reader.take()?);
+    let level_count = reader.u16()?;
+
+    let mut levels = Vec::with_capacity(usize::from(level_count));
+    for _ in 0..level_count {
+        let samples_per_pixel = reader.u32()?;
+        let len = reader.u32()? as usize;
+        // Reject truncated data before allocating for it
+        if reader.data.len() < len.checked_mul(channels * POINT_LEN)? {
+            return None;
+        }
+        let mut peaks = vec![
+            WaveformPeaks {
+                min: Vec::with_capacity(len),
+                max: Vec::with_capacity(len),
+                rms: Vec::with_capacity(len),
+            };
+            channels
+        ];
+        for _ in 0..len {
+            for channel in &mut peaks {
+                channel.min.push(reader.peak()?);
+                channel.max.push(reader.peak()?);
+                channel.rms.push(reader.peak()?);
+            }
+        }
+        levels.push(WaveformLevel {
+            samples_per_pixel,
+            channels: peaks,
+        });
+    }
+
+    Some(Waveform {
+        sample_rate,
+        channels: channels as u32,
+        duration,
+        levels,
+    })
+}
+
+/// Reads data produced by `encode_waveform`
+#[napi]
+#[allow(clippy::needless_pass_by_value, clippy::missing_errors_doc)]
+pub fn decode_waveform(data: Buffer) -> Result<Waveform> {
+    parse_waveform(&data).ok_or_else(|| Error::from_reason("Invalid waveform data"))
+}
@@ -0,0 +1,405 @@
+#![allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::Context;
+
+const INDEX_FILE_NAME: &str = "cache-index.db";
+// Unfinished entries neither accessed nor written to for this long are abandoned downloads
+const ABANDONED_AFTER_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub key: String,
+    /// Absolute path of the cached file
+    pub path: String,
+    pub size: f64,
+    /// Final size, if known when the entry was started
+    pub expected_size: Option<f64>,
+    pub complete: bool,
+    /// Unix time in milliseconds
+    pub last_access: f64,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    /// Bytes counted against the budget, unfinished entries count with their expected size
+    pub total_bytes: f64,
+    pub max_bytes: f64,
+    pub entry_count: u32,
+    pub complete_count: u32,
+    pub pinned_count: u32,
+}
+
+/// Size-capped song cache. The index lives in `SQLite` next to the files, eviction is LRU
+/// and never touches pinned entries (the current play queue).
+#[napi]
+pub struct MusicCache {
+    dir: PathBuf,
+    conn: Mutex<Connection>,
+    max_bytes: Mutex<u64>,
+    pinned: Mutex<HashSet<String>>,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Keys are arbitrary (song IDs, URLs), file names are derived from them
+fn file_name_for(key: &str, extension: Option<&str>) -> String {
+    let digest = md5::compute(key);
+    extension
+        .map(|e| e.trim_start_matches('.'))
+        .filter(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map_or_else(|| format!("{digest:x}"), |ext| format!("{digest:x}.{ext}"))
+}
+
+// Whether `name` could have come from `file_name_for`, anything else in the directory is not ours
+fn is_cache_file_name(name: &str) -> bool {
+    let (stem, extension) = name
+        .split_once('.')
+        .map_or((name, None), |(stem, ext)| (stem, Some(ext)));
+    stem.len() == 32
+        && stem.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && extension.is_none_or(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|m| m.len())
+}
+
+// Last write to `path` in Unix milliseconds
+fn modified_ms(path: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as i64)
+}
+
+// Whether an unfinished entry was given up on, going by its last access and last write
+fn is_abandoned(entry: &CacheEntry, now: i64) -> bool {
+    let last_active = modified_ms(Path::new(&entry.path))
+        .unwrap_or(0)
+        .max(entry.last_access as i64);
+    now - last_active > ABANDONED_AFTER_MS
+}
+
+#[napi]
+#[allow(clippy::needless_pass_by_value, clippy::missing_errors_doc)]
+impl MusicCache {
+    #[napi(constructor)]
+    pub fn new(cache_dir: String, max_bytes: f64) -> Result<Self> {
+        let dir = PathBuf::from(cache_dir);
+        std::fs::create_dir_all(&dir).context("Create cache dir failed")?;
+        let conn =
+            Connection::open(dir.join(INDEX_FILE_NAME)).context("Open cache index failed")?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS entries (
+                 key TEXT PRIMARY KEY,
+                 file_name TEXT NOT NULL,
+                 size INTEGER NOT NULL DEFAULT 0,
+                 expected_size INTEGER,
+                 complete INTEGER NOT NULL DEFAULT 0,
+                 last_access INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_entries_last_access ON entries (last_access);",
+        )
+        .context("Init cache index failed")?;
+
+        Ok(Self {
+            dir,
+            conn: Mutex::new(conn),
+            max_bytes: Mutex::new(max_bytes.max(0.0) as u64),
+            pinned: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Registers an unfinished entry and returns the path it should be downloaded to.
+    /// Makes room for `expected_size` first.
+    #[napi]
+    pub fn begin(
+        &self,
+        key: String,
+        expected_size: Option<f64>,
+        extension: Option<String>,
+    ) -> Result<String> {
+        let file_name = file_name_for(&key, extension.as_deref());
+        let expected = expected_size.map(|s| s.max(0.0) as i64);
+        lock(&self.conn)
+            .execute(
+                "INSERT INTO entries (key, file_name, size, expected_size, complete, last_access)
+                 VALUES (?1, ?2, 0, ?3, 0, ?4)
+                 ON CONFLICT(key) DO UPDATE SET
+                     file_name = excluded.file_name,
+                     expected_size = excluded.expected_size,
+                     complete = 0,
+                     last_access = excluded.last_access",
+                params![key, file_name, expected, now_ms()],
+            )
+            .context("Insert cache entry failed")?;
+        self.evict_except(Some(&key))?;
+        Ok(self.dir.join(file_name).to_string_lossy().into_owned())
+    }
+
+    /// Marks an entry as fully cached, taking its size from the file on disk
+    #[napi]
+    pub fn complete(&self, key: String) -> Result<bool> {
+        let Some(file_name) = self.file_name(&key)? else {
+            return Ok(false);
+        };
+        let Some(size) = file_size(&self.dir.join(&file_name)) else {
+            self.remove_entry(&key)?;
+            return Ok(false);
+        };
+        lock(&self.conn)
+            .execute(
+                "UPDATE entries SET size = ?2, expected_size = ?2, complete = 1, last_access = ?3
+                 WHERE key = ?1",
+                params![key, size as i64, now_ms()],
+            )
+            .context("Update cache entry failed")?;
+        self.evict_except(Some(&key))?;
+        Ok(true)
+    }
+
+    /// Looks up an entry and refreshes its last access time
+    #[napi]
+    pub fn lookup(&self, key: String) -> Result<Option<CacheEntry>> {
+        let Some(mut entry) = self.get(&key)? else {
+            return Ok(None);
+        };
+        if !Path::new(&entry.path).exists() {
+            self.remove_entry(&key)?;
+            return Ok(None);
+        }
+        let now = now_ms();
+        lock(&self.conn)
+            .execute(
+                "UPDATE entries SET last_access = ?2 WHERE key = ?1",
+                params![key, now],
+            )
+            .context("Update cache entry failed")?;
+        entry.last_access = now as f64;
+        Ok(Some(entry))
+    }
+
+    /// Whether the song can be played entirely from the cache. Does not count as an access.
+    #[napi]
+    pub fn is_complete(&self, key: String) -> Result<bool> {
+        Ok(self
+            .get(&key)?
+            .is_some_and(|e| e.complete && Path::new(&e.path).exists()))
+    }
+
+    #[napi]
+    pub fn remove(&self, key: String) -> Result<bool> {
+        self.remove_entry(&key)
+    }
+
+    /// Replaces the set of pinned keys, usually with the current play queue
+    #[napi]
+    pub fn pin(&self, keys: Vec<String>) {
+        *lock(&self.pinned) = keys.into_iter().collect();
+    }
+
+    #[napi]
+    pub fn set_max_bytes(&self, max_bytes: f64) -> Result<Vec<String>> {
+        *lock(&self.max_bytes) = max_bytes.max(0.0) as u64;
+        self.evict_except(None)
+    }
+
+    /// Evicts least recently used entries until the cache fits its budget.
+    /// Returns the evicted keys.
+    #[napi]
+    pub fn evict(&self) -> Result<Vec<String>> {
+        self.evict_except(None)
+    }
+
+    /// Drops index entries whose files are gone and deletes cache files that have no entry.
+    /// Files not named like cache entries are left alone. Returns how many entries and files
+    /// were removed.
+    #[napi]
+    pub fn reconcile(&self) -> Result<u32> {
+        let entries = self.all_entries()?;
+        let pinned = lock(&self.pinned).clone();
+        let mut removed = 0;
+        for entry in &entries {
+            // A pinned unfinished entry may simply not have started writing yet
+            if !Path::new(&entry.path).exists() && !pinned.contains(&entry.key) {
+                self.remove_entry(&entry.key)?;
+                removed += 1;
+            }
+        }
+
+        let known: HashSet<PathBuf> = entries.iter().map(|e| PathBuf::from(&e.path)).collect();
+        let dir_entries = std::fs::read_dir(&self.dir).context("Read cache dir failed")?;
+        for path in dir_entries.flatten().map(|e| e.path()) {
+            let is_ours = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(is_cache_file_name);
+            if path.is_file() && is_ours && !known.contains(&path) {
+                let _ = std::fs::remove_file(&path);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    #[napi]
+    pub fn stats(&self) -> Result<CacheStats> {
+        let (total, count, complete): (i64, u32, u32) = lock(&self.conn)
+            .query_row(
+                "SELECT COALESCE(SUM(MAX(size, COALESCE(expected_size, 0))), 0),
+                        COUNT(*),
+                        COALESCE(SUM(complete), 0)
+                 FROM entries",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .context("Query cache stats failed")?;
+        Ok(CacheStats {
+            total_bytes: total as f64,
+            max_bytes: *lock(&self.max_bytes) as f64,
+            entry_count: count,
+            complete_count: complete,
+            pinned_count: lock(&self.pinned).len() as u32,
+        })
+    }
+
+    #[napi]
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        self.all_entries()
+    }
+}
+
+impl MusicCache {
+    fn remove_entry(&self, key: &str) -> Result<bool> {
+        let Some(file_name) = self.file_name(key)? else {
+            return Ok(false);
+        };
+        let path = self.dir.join(file_name);
+        if path.exists() {
+            std::fs::remove_file(&path).context("Remove cached file failed")?;
+        }
+        lock(&self.conn)
+            .execute("DELETE FROM entries WHERE key = ?1", params![key])
+            .context("Delete cache entry failed")?;
+        Ok(true)
+    }
+
+    fn row_to_entry(&self, row: &rusqlite::Row<'_>) -> rusqlite::Result<CacheEntry> {
+        let file_name: String = row.get(1)?;
+        let path = self.dir.join(&file_name);
+        let complete: bool = row.get(4)?;
+        // Unfinished entries grow while downloading, the index only knows the final size
+        let size = if complete {
+            row.get::<_, i64>(2)? as f64
+        } else {
+            file_size(&path).unwrap_or(0) as f64
+        };
+        Ok(CacheEntry {
+            key: row.get(0)?,
+            path: path.to_string_lossy().into_owned(),
+            size,
+            expected_size: row.get::<_, Option<i64>>(3)?.map(|s| s as f64),
+            complete,
+            last_access: row.get::<_, i64>(5)? as f64,
+        })
+    }
+
+    fn get(&self, key: &str) -> Result<Option<CacheEntry>> {
+        lock(&self.conn)
+            .query_row(
+                "SELECT key, file_name, size, expected_size, complete, last_access
+                 FROM entries WHERE key = ?1",
+                params![key],
+                |row| self.row_to_entry(row),
+            )
+            .optional()
+            .context("Query cache entry failed")
+    }
+
+    fn file_name(&self, key: &str) -> Result<Option<String>> {
+        lock(&self.conn)
+            .query_row(
+                "SELECT file_name FROM entries WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Query cache entry failed")
+    }
+
+    fn all_entries(&self) -> Result<Vec<CacheEntry>> {
+        let conn = lock(&self.conn);
+        let mut stmt = conn
+            .prepare(
+                "SELECT key, file_name, size, expected_size, complete, last_access
+                 FROM entries ORDER BY last_access ASC",
+            )
+            .context("Query cache entries failed")?;
+        let entries = stmt
+            .query_map([], |row| self.row_to_entry(row))
+            .context("Query cache entries failed")?
+            .collect::<rusqlite::Result<Vec<_>>>();
+        drop(stmt);
+        drop(conn);
+        entries.context("Read cache entries failed")
+    }
+
+    // `keep` is the entry that triggered the eviction, it must not evict itself. Unfinished
+    // entries are still being written and stay like pinned ones, unless they were abandoned.
+    fn evict_except(&self, keep: Option<&str>) -> Result<Vec<String>> {
+        let max_bytes = *lock(&self.max_bytes);
+        let entries = self.all_entries()?;
+        let budget_size =
+            |e: &CacheEntry| e.size.max(e.expected_size.unwrap_or(0.0)).max(0.0) as u64;
+        let mut total: u64 = entries.iter().map(budget_size).sum();
+        if total <= max_bytes {
+            return Ok(Vec::new());
+        }
+
+        let pinned = lock(&self.pinned).clone();
+        let now = now_ms();
+        let mut evicted = Vec::new();
+        // Oldest access first
+        for entry in &entries {
+            if total <= max_bytes {
+                break;
+            }
+            if pinned.contains(&entry.key)
+                || keep == Some(entry.key.as_str())
+                || (!entry.complete && !is_abandoned(entry, now))
+            {
+                continue;
+            }
+            // A file another process holds open must not fail the caller's begin/complete
+            if let Err(e) = self.remove_entry(&entry.key) {
+                println!("[Cache] Evict {} failed: {}", entry.key, e.reason);
+                continue;
+            }
+            total = total.saturating_sub(budget_size(entry));
+            evicted.push(entry.key.clone());
+        }
+
+        if !evicted.is_empty() {
+            println!("[Cache] Evicted {} entries", evicted.len());
+        }
+        Ok(evicted)
+    }
+}
@@ -0,0 +1,119 @@
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::utils::Context;
+
+const DEFAULT_USER_AGENT: &str = "SPlayer/1.0";
+const KEEP_ALIVE_INTERVAL_SECS: u64 = 15;
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Proxy URL, e.g. `http://host:port`, `https://host:port` or `socks5h://host:port`
+    pub proxy: Option<String>,
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    /// Extra headers sent with every request
+    pub headers: Option<HashMap<String, String>>,
+    /// Domain (or URL) -> `Set-Cookie` style strings used to seed the cookie jar
+    pub cookies: Option<HashMap<String, Vec<String>>>,
+    pub user_agent: Option<String>,
+    pub connect_timeout_ms: Option<u32>,
+    pub read_timeout_ms: Option<u32>,
+    /// Paths to PEM (or DER) encoded CA certificates to trust in addition to the system ones
+    pub extra_ca_certs: Option<Vec<String>>,
+}
+
+pub(super) fn build_client(enable_http2: bool, options: &ClientOptions) -> Result<reqwest::Client> {
+    let user_agent = options
+        .user_agent
+        .as_deref()
+        .filter(|ua| !ua.is_empty())
+        .unwrap_or(DEFAULT_USER_AGENT);
+
+    let mut builder = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .tcp_nodelay(true)
+        .http2_keep_alive_interval(Duration::from_secs(KEEP_ALIVE_INTERVAL_SECS));
+
+    if !enable_http2 {
+        builder = builder.http1_only();
+    }
+
+    if let Some(proxy_url) = options.proxy.as_deref().filter(|p| !p.is_empty()) {
+        let mut proxy = reqwest::Proxy::all(proxy_url).context("Invalid proxy URL")?;
+        if let Some(username) = options.proxy_username.as_deref() {
+            let password = options.proxy_password.as_deref().unwrap_or_default();
+            proxy = proxy.basic_auth(username, password);
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(headers) = &options.headers {
+        builder = builder.default_headers(build_header_map(headers)?);
+    }
+
+    if let Some(cookies) = &options.cookies {
+        builder = builder.cookie_provider(build_cookie_jar(cookies)?);
+    }
+
+    if let Some(ms) = options.connect_timeout_ms.filter(|&ms| ms > 0) {
+        builder = builder.connect_timeout(Duration::from_millis(u64::from(ms)));
+    }
+
+    if let Some(ms) = options.read_timeout_ms.filter(|&ms| ms > 0) {
+        builder = builder.read_timeout(Duration::from_millis(u64::from(ms)));
+    }
+
+    for path in options.extra_ca_certs.iter().flatten() {
+        for cert in load_certificates(path)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    builder.build().context("Failed to build HTTP client")
+}
+
+fn build_header_map(headers: &HashMap<String, String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .context(format!("Invalid header name '{name}'"))?;
+        let value =
+            HeaderValue::from_str(value).context(format!("Invalid value for header '{name}'"))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+fn build_cookie_jar(cookies: &HashMap<String, Vec<String>>) -> Result<Arc<reqwest::cookie::Jar>> {
+    let jar = reqwest::cookie::Jar::default();
+    for (domain, entries) in cookies {
+        let url = if domain.contains("://") {
+            domain.clone()
+        } else {
+            format!("https://{}/", domain.trim_start_matches('.'))
+        };
+        let url = reqwest::Url::parse(&url).context(format!("Invalid cookie domain '{domain}'"))?;
+        for cookie in entries {
+            jar.add_cookie_str(cookie, &url);
+        }
+    }
+    Ok(Arc::new(jar))
+}
+
+fn load_certificates(path: &str) -> Result<Vec<reqwest::Certificate>> {
+    let data = std::fs::read(path).context(format!("Read CA certificate '{path}' failed"))?;
+    if let Ok(certs) = reqwest::Certificate::from_pem_bundle(&data) {
+        if !certs.is_empty() {
+            return Ok(certs);
+        }
+    }
+    reqwest::Certificate::from_der(&data)
+        .map(|cert| vec![cert])
+        .context(format!("Parse CA certificate '{path}' failed"))
+}
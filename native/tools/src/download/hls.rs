@@ -0,0 +1,635 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use futures_util::StreamExt;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunction;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+
+use super::progressive::FileProgress;
+use super::source::UrlSource;
+use super::{download_chunk_with_retry, DownloadProgress, ProgressTracker};
+use crate::utils::{id3v2_len, Context};
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+const VIDEO_CODEC_PREFIXES: &[&str] = &["avc1", "avc3", "hvc1", "hev1", "vp09", "av01"];
+const HLS_CONTENT_TYPES: &[&str] = &[
+    "application/vnd.apple.mpegurl",
+    "application/x-mpegurl",
+    "audio/mpegurl",
+    "audio/x-mpegurl",
+];
+
+pub(super) fn is_hls(url: &str, content_type: Option<&str>) -> bool {
+    let by_type = content_type.is_some_and(|ct| {
+        let ct = ct.to_ascii_lowercase();
+        HLS_CONTENT_TYPES.iter().any(|t| ct.starts_with(t))
+    });
+    let by_ext = Url::parse(url).is_ok_and(|u| {
+        std::path::Path::new(u.path())
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u8") || ext.eq_ignore_ascii_case("m3u"))
+    });
+    by_type || by_ext
+}
+
+// --- Playlist Parsing ---
+
+#[derive(Clone, Debug)]
+struct SegmentKey {
+    uri: String,
+    iv: Option<[u8; 16]>,
+}
+
+#[derive(Clone, Debug)]
+struct MediaSegment {
+    uri: String,
+    byte_range: Option<(u64, u64)>,
+    key: Option<SegmentKey>,
+    sequence: u64,
+    duration: f64,
+}
+
+#[derive(Debug, Default)]
+struct MediaPlaylist {
+    init: Option<MediaSegment>,
+    segments: Vec<MediaSegment>,
+}
+
+#[derive(Debug)]
+struct Variant {
+    uri: String,
+    bandwidth: u64,
+    codecs: Option<String>,
+    audio_group: Option<String>,
+}
+
+#[derive(Debug)]
+struct Rendition {
+    group_id: String,
+    uri: String,
+    is_default: bool,
+}
+
+#[derive(Debug)]
+enum Playlist {
+    Master {
+        variants: Vec<Variant>,
+        renditions: Vec<Rendition>,
+    },
+    Media(MediaPlaylist),
+}
+
+// KEY="value",KEY=value,... (quoted values may contain commas)
+fn parse_attributes(input: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = input.trim();
+    while !rest.is_empty() {
+        let Some(eq) = rest.find('=') else {
+            break;
+        };
+        let key = rest[..eq].trim().to_ascii_uppercase();
+        rest = &rest[eq + 1..];
+
+        let (value, remaining) = rest.strip_prefix('"').map_or_else(
+            || {
+                let end = rest.find(',').unwrap_or(rest.len());
+                (rest[..end].trim(), &rest[end..])
+            },
+            |quoted| {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+            },
+        );
+        attrs.insert(key, value.to_string());
+        rest = remaining.trim_start_matches(',').trim_start();
+    }
+    attrs
+}
+
+fn parse_iv(value: &str) -> Option<[u8; 16]> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))?;
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut iv = [0u8; 16];
+    for (i, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(iv)
+}
+
+// <length>[@<offset>], offset defaults to the end of the previous range of the same resource
+fn parse_byte_range(value: &str, previous_end: Option<u64>) -> Option<(u64, u64)> {
+    let (len, offset) = match value.split_once('@') {
+        Some((len, offset)) => (len.trim().parse::<u64>().ok()?, offset.trim().parse().ok()?),
+        None => (value.trim().parse::<u64>().ok()?, previous_end.unwrap_or(0)),
+    };
+    if len == 0 {
+        return None;
+    }
+    Some((offset, offset + len - 1))
+}
+
+fn resolve(base: &Url, uri: &str) -> Result<String> {
+    base.join(uri)
+        .map(String::from)
+        .context(format!("Invalid playlist URI '{uri}'"))
+}
+
+#[allow(clippy::too_many_lines)]
+fn parse_playlist(text: &str, base: &Url) -> Result<Playlist> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    if lines.next() != Some("#EXTM3U") {
+        return Err(Error::from_reason("Not an HLS playlist (missing #EXTM3U)"));
+    }
+
+    let mut variants = Vec::new();
+    let mut renditions = Vec::new();
+    let mut media = MediaPlaylist::default();
+    let mut is_master = false;
+
+    let mut pending_variant: Option<HashMap<String, String>> = None;
+    let mut pending_range: Option<String> = None;
+    let mut pending_duration = 0.0;
+    let mut current_key: Option<SegmentKey> = None;
+    let mut sequence = 0u64;
+    let mut last_range_end: HashMap<String, u64> = HashMap::new();
+
+    for line in lines {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            is_master = true;
+            pending_variant = Some(parse_attributes(attrs));
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attrs = parse_attributes(attrs);
+            if attrs.get("TYPE").map(String::as_str) == Some("AUDIO") {
+                if let (Some(group_id), Some(uri)) = (attrs.get("GROUP-ID"), attrs.get("URI")) {
+                    renditions.push(Rendition {
+                        group_id: group_id.clone(),
+                        uri: resolve(base, uri)?,
+                        is_default: attrs.get("DEFAULT").map(String::as_str) == Some("YES"),
+                    });
+                }
+            }
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = value.trim().parse().unwrap_or(0);
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+            let attrs = parse_attributes(attrs);
+            current_key = match attrs.get("METHOD").map(String::as_str) {
+                Some("NONE") | None => None,
+                Some("AES-128") => {
+                    let uri = attrs
+                        .get("URI")
+                        .ok_or_else(|| Error::from_reason("AES-128 key without URI"))?;
+                    Some(SegmentKey {
+                        uri: resolve(base, uri)?,
+                        iv: attrs.get("IV").and_then(|iv| parse_iv(iv)),
+                    })
+                }
+                Some(method) => {
+                    return Err(Error::from_reason(format!(
+                        "Unsupported HLS encryption method: {method}"
+                    )))
+                }
+            };
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+            let attrs = parse_attributes(attrs);
+            if media.init.is_none() {
+                if let Some(uri) = attrs.get("URI") {
+                    let uri = resolve(base, uri)?;
+                    let byte_range = attrs
+                        .get("BYTERANGE")
+                        .and_then(|r| parse_byte_range(r, None));
+                    media.init = Some(MediaSegment {
+                        uri,
+                        byte_range,
+                        key: current_key.clone(),
+                        sequence: 0,
+                        duration: 0.0,
+                    });
+                }
+            }
+        } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+            let value = value.split(',').next().unwrap_or_default();
+            pending_duration = value.trim().parse().unwrap_or(0.0);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            pending_range = Some(value.to_string());
+        } else if line.starts_with('#') {
+            // Other tags are irrelevant for downloading
+        } else if let Some(attrs) = pending_variant.take() {
+            variants.push(Variant {
+                uri: resolve(base, line)?,
+                bandwidth: attrs
+                    .get("BANDWIDTH")
+                    .and_then(|b| b.parse().ok())
+                    .unwrap_or(0),
+                codecs: attrs.get("CODECS").cloned(),
+                audio_group: attrs.get("AUDIO").cloned(),
+            });
+        } else {
+            let uri = resolve(base, line)?;
+            let byte_range = pending_range
+                .take()
+                .and_then(|r| parse_byte_range(&r, last_range_end.get(&uri).copied()));
+            if let Some((_, end)) = byte_range {
+                last_range_end.insert(uri.clone(), end + 1);
+            }
+            media.segments.push(MediaSegment {
+                uri,
+                byte_range,
+                key: current_key.clone(),
+                sequence,
+                duration: pending_duration,
+            });
+            sequence += 1;
+            pending_duration = 0.0;
+        }
+    }
+
+    if is_master {
+        Ok(Playlist::Master {
+            variants,
+            renditions,
+        })
+    } else {
+        Ok(Playlist::Media(media))
+    }
+}
+
+fn is_audio_only(variant: &Variant) -> bool {
+    variant.codecs.as_deref().is_some_and(|codecs| {
+        !codecs
+            .split(',')
+            .any(|c| VIDEO_CODEC_PREFIXES.iter().any(|v| c.trim().starts_with(v)))
+    })
+}
+
+// Picks the media playlist carrying the highest-bitrate audio
+fn select_audio_playlist(variants: &[Variant], renditions: &[Rendition]) -> Option<String> {
+    let audio_only: Vec<&Variant> = variants.iter().filter(|v| is_audio_only(v)).collect();
+    let candidates: Vec<&Variant> = if audio_only.is_empty() {
+        variants.iter().collect()
+    } else {
+        audio_only
+    };
+    let best = candidates.into_iter().max_by_key(|v| v.bandwidth)?;
+
+    // Muxed video variants with a separate audio rendition: fetch only the audio
+    if !is_audio_only(best) {
+        if let Some(group) = &best.audio_group {
+            let mut group_renditions = renditions.iter().filter(|r| &r.group_id == group);
+            let rendition = group_renditions
+                .clone()
+                .find(|r| r.is_default)
+                .or_else(|| group_renditions.next());
+            if let Some(rendition) = rendition {
+                return Some(rendition.uri.clone());
+            }
+        }
+    }
+    Some(best.uri.clone())
+}
+
+async fn fetch_text(client: &reqwest::Client, url: &str, referer: Option<&str>) -> Result<String> {
+    let mut req = client.get(url);
+    if let Some(r) = referer {
+        req = req.header("Referer", r);
+    }
+    let resp = req
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .context("Fetch playlist failed")?;
+    let bytes = resp.bytes().await.context("Read playlist failed")?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+async fn load_media_playlist(
+    client: &reqwest::Client,
+    url: &str,
+    referer: Option<&str>,
+) -> Result<MediaPlaylist> {
+    let mut url = url.to_string();
+    // A master playlist only ever points one level down
+    for _ in 0..2 {
+        let base = Url::parse(&url).context("Invalid playlist URL")?;
+        let text = fetch_text(client, &url, referer).await?;
+        match parse_playlist(&text, &base)? {
+            Playlist::Media(media) => return Ok(media),
+            Playlist::Master {
+                variants,
+                renditions,
+            } => {
+                url = select_audio_playlist(&variants, &renditions)
+                    .ok_or_else(|| Error::from_reason("Master playlist has no variants"))?;
+                println!("[Download] HLS variant: {url}");
+            }
+        }
+    }
+    Err(Error::from_reason(
+        "Nested master playlists are not supported",
+    ))
+}
+
+// --- Decryption ---
+
+async fn fetch_keys(
+    client: &reqwest::Client,
+    playlist: &MediaPlaylist,
+    referer: Option<&str>,
+) -> Result<HashMap<String, [u8; 16]>> {
+    let mut keys = HashMap::new();
+    let key_uris = playlist
+        .init
+        .iter()
+        .chain(&playlist.segments)
+        .filter_map(|s| s.key.as_ref().map(|k| k.uri.clone()));
+
+    for uri in key_uris {
+        if keys.contains_key(&uri) {
+            continue;
+        }
+        let mut req = client.get(&uri);
+        if let Some(r) = referer {
+            req = req.header("Referer", r);
+        }
+        let data = req
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("Fetch HLS key failed")?
+            .bytes()
+            .await
+            .context("Read HLS key failed")?;
+        let key: [u8; 16] = data.as_ref().try_into().map_err(|_| {
+            Error::from_reason(format!("Invalid AES-128 key length: {}", data.len()))
+        })?;
+        keys.insert(uri, key);
+    }
+    Ok(keys)
+}
+
+fn decrypt_segment(
+    data: &[u8],
+    segment: &MediaSegment,
+    keys: &HashMap<String, [u8; 16]>,
+) -> Result<Vec<u8>> {
+    let Some(key_info) = &segment.key else {
+        return Ok(data.to_vec());
+    };
+    let key = keys
+        .get(&key_info.uri)
+        .ok_or_else(|| Error::from_reason("Missing HLS key"))?;
+    // Without an explicit IV the media sequence number is used (RFC 8216 5.2)
+    let iv = key_info
+        .iv
+        .unwrap_or_else(|| u128::from(segment.sequence).to_be_bytes());
+
+    Aes128CbcDec::new(key.into(), &iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| Error::from_reason(format!("Decrypt segment {} failed", segment.sequence)))
+}
+
+// --- Remuxing ---
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SegmentFormat {
+    // Fragmented MP4, written as init + fragments
+    Fmp4,
+    // MPEG-TS, demuxed to the raw audio elementary stream
+    Ts,
+    // Packed audio (ADTS/MP3 with ID3 timestamps)
+    Packed,
+}
+
+fn detect_segment_format(first: &[u8], has_init: bool) -> SegmentFormat {
+    if has_init {
+        SegmentFormat::Fmp4
+    } else if first.first() == Some(&TS_SYNC_BYTE)
+        && first.get(TS_PACKET_SIZE).is_none_or(|&b| b == TS_SYNC_BYTE)
+    {
+        SegmentFormat::Ts
+    } else {
+        SegmentFormat::Packed
+    }
+}
+
+// Strips the ID3 timestamp tag packed audio segments start with
+fn strip_id3(data: &[u8]) -> &[u8] {
+    id3v2_len(data).map_or(data, |len| data.get(len as usize..).unwrap_or_default())
+}
+
+/// Minimal MPEG-TS demuxer that extracts the first AAC (ADTS) or MPEG audio stream
+#[derive(Default)]
+struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    in_pes: bool,
+}
+
+impl TsDemuxer {
+    fn push(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for packet in data.chunks_exact(TS_PACKET_SIZE) {
+            if packet[0] != TS_SYNC_BYTE {
+                continue;
+            }
+            let payload_start = packet[1] & 0x40 != 0;
+            let pid = (u16::from(packet[1] & 0x1F) << 8) | u16::from(packet[2]);
+            let adaptation = (packet[3] >> 4) & 0x03;
+
+            let mut offset = 4;
+            if adaptation & 0x02 != 0 {
+                offset += 1 + usize::from(packet[4]);
+            }
+            if adaptation & 0x01 == 0 || offset >= TS_PACKET_SIZE {
+                continue;
+            }
+            let payload = &packet[offset..];
+
+            if pid == 0 && payload_start {
+                self.pmt_pid = parse_pat(payload);
+            } else if Some(pid) == self.pmt_pid && payload_start {
+                if self.audio_pid.is_none() {
+                    self.audio_pid = parse_pmt(payload);
+                }
+            } else if Some(pid) == self.audio_pid {
+                if payload_start {
+                    self.in_pes = false;
+                    // 00 00 01 | stream_id | length(2) | flags(2) | header_len | ...
+                    if payload.len() >= 9 && payload[..3] == [0, 0, 1] {
+                        let start = 9 + usize::from(payload[8]);
+                        if let Some(es) = payload.get(start..) {
+                            out.extend_from_slice(es);
+                            self.in_pes = true;
+                        }
+                    }
+                } else if self.in_pes {
+                    out.extend_from_slice(payload);
+                }
+            }
+        }
+    }
+}
+
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = usize::from(*payload.first()?);
+    let section = payload.get(1 + pointer..)?;
+    let length = (usize::from(*section.get(1)? & 0x0F) << 8) | usize::from(*section.get(2)?);
+    // Drop the trailing CRC32
+    section.get(..(3 + length).checked_sub(4)?)
+}
+
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let section = psi_section(payload)?;
+    section.get(8..)?.chunks_exact(4).find_map(|entry| {
+        let program = u16::from_be_bytes([entry[0], entry[1]]);
+        (program != 0).then_some((u16::from(entry[2] & 0x1F) << 8) | u16::from(entry[3]))
+    })
+}
+
+fn parse_pmt(payload: &[u8]) -> Option<u16> {
+    let section = psi_section(payload)?;
+    let info_len = (usize::from(*section.get(10)? & 0x0F) << 8) | usize::from(*section.get(11)?);
+    let mut i = 12 + info_len;
+    while i + 5 <= section.len() {
+        let stream_type = section[i];
+        let pid = (u16::from(section[i + 1] & 0x1F) << 8) | u16::from(section[i + 2]);
+        let es_info_len = (usize::from(section[i + 3] & 0x0F) << 8) | usize::from(section[i + 4]);
+        // 0x0F: AAC (ADTS), 0x03/0x04: MPEG-1/2 audio
+        if matches!(stream_type, 0x0F | 0x03 | 0x04) {
+            return Some(pid);
+        }
+        i += 5 + es_info_len;
+    }
+    None
+}
+
+// --- Download ---
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn download_hls(
+    token: CancellationToken,
+    client: reqwest::Client,
+    url: String,
+    file_path: String,
+    thread_count: u32,
+    referer: Option<String>,
+    on_progress: ThreadsafeFunction<DownloadProgress>,
+    progress: &FileProgress,
+) -> Result<()> {
+    let playlist = load_media_playlist(&client, &url, referer.as_deref()).await?;
+    if playlist.segments.is_empty() {
+        return Err(Error::from_reason("HLS playlist has no segments"));
+    }
+    let keys = Arc::new(fetch_keys(&client, &playlist, referer.as_deref()).await?);
+
+    let total_duration: f64 = playlist.segments.iter().map(|s| s.duration).sum();
+    println!(
+        "[Download] HLS segments: {}, Duration: {total_duration:.1}s",
+        playlist.segments.len()
+    );
+
+    let mut file = tokio::fs::File::create(&file_path)
+        .await
+        .context("Create file failed")?;
+    let tracker = Arc::new(ProgressTracker::new(0, on_progress));
+    let segment_count = playlist.segments.len() as u64;
+    let has_init = playlist.init.is_some();
+
+    // Init segment first, then media segments; `buffered` keeps them in playlist order
+    let jobs = playlist.init.into_iter().chain(playlist.segments);
+    let segment_futures = futures_util::stream::iter(jobs).map(|segment| {
+        let client = client.clone();
+        let referer = referer.clone();
+        let token = token.clone();
+        let keys = keys.clone();
+        let tracker = tracker.clone();
+
+        async move {
+            let source = Arc::new(UrlSource::new(segment.uri.clone(), None, None));
+            let data = download_chunk_with_retry(
+                client,
+                source,
+                referer,
+                segment.byte_range,
+                token,
+                &tracker,
+            )
+            .await?;
+            decrypt_segment(&data, &segment, &keys)
+        }
+    });
+    let mut stream = segment_futures.buffered(thread_count.max(1) as usize);
+
+    let process_result = async {
+        let mut format: Option<SegmentFormat> = None;
+        let mut demuxer = TsDemuxer::default();
+        let mut out = Vec::new();
+        let mut done = 0u64;
+        let mut written = 0u64;
+
+        while let Some(result) = stream.next().await {
+            let data = result?;
+            if token.is_cancelled() {
+                return Err(Error::new(
+                    Status::Cancelled,
+                    "Download cancelled".to_string(),
+                ));
+            }
+
+            let segment_format =
+                *format.get_or_insert_with(|| detect_segment_format(&data, has_init));
+            out.clear();
+            match segment_format {
+                SegmentFormat::Fmp4 => out.extend_from_slice(&data),
+                SegmentFormat::Ts => demuxer.push(&data, &mut out),
+                SegmentFormat::Packed => out.extend_from_slice(strip_id3(&data)),
+            }
+            file.write_all(&out).await.context("Write failed")?;
+            file.flush().await.context("Flush failed")?;
+            progress.mark(written, written + out.len() as u64);
+            written += out.len() as u64;
+
+            // Total size is only known once every segment is in; extrapolate meanwhile
+            done += 1;
+            let transferred = tracker.transferred() + data.len() as u64;
+            let media_done = if has_init {
+                done.saturating_sub(1)
+            } else {
+                done
+            };
+            if media_done > 0 {
+                tracker.set_total(transferred * segment_count / media_done.min(segment_count));
+            }
+            tracker.update(data.len() as u64);
+        }
+
+        if format == Some(SegmentFormat::Ts) && demuxer.audio_pid.is_none() {
+            return Err(Error::from_reason("No audio stream found in HLS segments"));
+        }
+        file.flush().await.context("Flush failed")?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = process_result {
+        drop(file);
+        let _ = tokio::fs::remove_file(&file_path).await;
+        // Callers tell a cancel from a failure by the status
+        if e.status == Status::Cancelled {
+            return Err(e);
+        }
+        return Err(Error::from_reason(format!("HLS download failed: {e}")));
+    }
+
+    tracker.set_total(tracker.transferred());
+    tracker.finish();
+    Ok(())
+}
@@ -0,0 +1,781 @@
+use futures_util::StreamExt;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio_util::sync::CancellationToken;
+
+use crate::metadata::{write_metadata, SongMetadata, WriteTagOptions};
+use crate::utils::Context;
+
+mod client;
+mod hls;
+mod path;
+mod progressive;
+mod server;
+mod sniff;
+mod source;
+
+use client::build_client;
+pub use client::ClientOptions;
+use path::MAX_COLLISION_SUFFIX;
+pub use path::{render_download_path, PathTarget, RenderPathOptions};
+use progressive::{ChunkScheduler, FileProgress, Registration};
+pub use server::StreamServer;
+pub use sniff::AudioContainer;
+pub use source::UrlRefreshRequest;
+use source::{is_url_expired, UrlRefreshCallback, UrlSource};
+
+// Constants
+const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_RETRIES: u32 = 3;
+const TIMEOUT_SECS: u64 = 10;
+const PROGRESS_INTERVAL_MS: u64 = 200;
+const PROGRESS_BYTES_THRESHOLD: u64 = 100 * 1024; // 100KB
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct DownloadProgress {
+    pub percent: f64,
+    pub transferred_bytes: f64,
+    pub total_bytes: f64,
+    /// Smoothed transfer rate in bytes per second
+    pub speed: f64,
+    /// Estimated seconds remaining, `None` while the speed or total size is unknown
+    pub eta: Option<f64>,
+    /// Failed attempts that were retried so far
+    pub retries: u32,
+    pub active_connections: u32,
+    /// One byte per chunk for multi-threaded downloads: 0 pending, 1 downloading, 2 done
+    pub chunk_states: Option<Vec<u8>>,
+}
+
+#[napi(object)]
+pub struct DownloadResult {
+    /// Where the file ended up, differs from the requested path if the extension was fixed
+    pub file_path: String,
+    /// Real container sniffed from the content (or `Content-Type`), `None` if unrecognized
+    pub container: Option<AudioContainer>,
+    pub content_type: Option<String>,
+    /// Whether the file was renamed to match `container`
+    pub renamed: bool,
+}
+
+const CHUNK_ACTIVE: u8 = 1;
+const CHUNK_DONE: u8 = 2;
+// Weight of the newest sample in the smoothed speed
+const SPEED_EWMA_ALPHA: f64 = 0.3;
+
+#[derive(Default)]
+struct SpeedSample {
+    ts: u64,
+    bytes: u64,
+    rate: Option<f64>,
+}
+
+struct ProgressTracker {
+    total_size: AtomicU64,
+    transferred: AtomicU64,
+    last_emitted_ts: AtomicU64,    // Timestamp in ms
+    last_emitted_bytes: AtomicU64, // Last emitted bytes count
+    retries: AtomicU32,
+    active_connections: AtomicU32,
+    speed: Mutex<SpeedSample>,
+    chunk_states: Option<Mutex<Vec<u8>>>,
+    callback: Arc<ThreadsafeFunction<DownloadProgress>>,
+}
+
+// Counts an open connection until dropped
+struct ConnectionGuard<'a>(&'a AtomicU32);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl ProgressTracker {
+    fn new(total_size: u64, callback: ThreadsafeFunction<DownloadProgress>) -> Self {
+        Self {
+            total_size: AtomicU64::new(total_size),
+            transferred: AtomicU64::new(0),
+            last_emitted_ts: AtomicU64::new(0),
+            last_emitted_bytes: AtomicU64::new(0),
+            retries: AtomicU32::new(0),
+            active_connections: AtomicU32::new(0),
+            speed: Mutex::new(SpeedSample::default()),
+            chunk_states: None,
+            callback: Arc::new(callback),
+        }
+    }
+
+    fn with_chunks(mut self, chunk_count: u64) -> Self {
+        self.chunk_states = Some(Mutex::new(vec![0; chunk_count as usize]));
+        self
+    }
+
+    fn set_chunk_state(&self, index: u64, state: u8) {
+        if let Some(states) = &self.chunk_states {
+            let mut states = states.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(slot) = states.get_mut(index as usize) {
+                *slot = state;
+            }
+        }
+    }
+
+    fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn connection(&self) -> ConnectionGuard<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(&self.active_connections)
+    }
+
+    fn update(&self, delta: u64) {
+        let current = self.transferred.fetch_add(delta, Ordering::Relaxed) + delta;
+        let total = self.total_size.load(Ordering::Relaxed);
+
+        if total == 0 {
+            return;
+        }
+
+        let last_bytes = self.last_emitted_bytes.load(Ordering::Relaxed);
+
+        // Optimization: Only check time if we've made significant progress or finished
+        if current - last_bytes < PROGRESS_BYTES_THRESHOLD && current != total {
+            return;
+        }
+
+        // Rate limit
+        let now = now_ms();
+
+        let last_ts = self.last_emitted_ts.load(Ordering::Relaxed);
+
+        if now - last_ts >= PROGRESS_INTERVAL_MS || current == total {
+            self.last_emitted_ts.store(now, Ordering::Relaxed);
+            self.last_emitted_bytes.store(current, Ordering::Relaxed);
+            self.emit(current, total, now);
+        }
+    }
+
+    fn update_speed(&self, current: u64, now: u64) -> f64 {
+        let mut sample = self.speed.lock().unwrap_or_else(PoisonError::into_inner);
+        if sample.ts > 0 && now > sample.ts {
+            let instant =
+                current.saturating_sub(sample.bytes) as f64 * 1000.0 / (now - sample.ts) as f64;
+            sample.rate = Some(sample.rate.map_or(instant, |rate| {
+                SPEED_EWMA_ALPHA.mul_add(instant, (1.0 - SPEED_EWMA_ALPHA) * rate)
+            }));
+        }
+        sample.ts = now;
+        sample.bytes = current;
+        sample.rate.unwrap_or(0.0)
+    }
+
+    fn emit(&self, current: u64, total: u64, now: u64) {
+        let speed = self.update_speed(current, now);
+        let eta = (speed > 0.0 && total > 0).then(|| total.saturating_sub(current) as f64 / speed);
+        let progress = DownloadProgress {
+            percent: if total > 0 {
+                current as f64 / total as f64
+            } else {
+                0.0
+            },
+            transferred_bytes: current as f64,
+            total_bytes: total as f64,
+            speed,
+            eta,
+            retries: self.retries.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            chunk_states: self
+                .chunk_states
+                .as_ref()
+                .map(|s| s.lock().unwrap_or_else(PoisonError::into_inner).clone()),
+        };
+        self.callback
+            .call(Ok(progress), ThreadsafeFunctionCallMode::NonBlocking);
+    }
+
+    // For sources whose size is only known as an estimate (e.g. HLS)
+    fn set_total(&self, total: u64) {
+        self.total_size.store(total, Ordering::Relaxed);
+    }
+
+    fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    fn finish(&self) {
+        let total = self.total_size.load(Ordering::Relaxed);
+        if let Some(states) = &self.chunk_states {
+            states
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .fill(CHUNK_DONE);
+        }
+        self.emit(total, total, now_ms());
+    }
+}
+
+#[napi]
+pub struct DownloadTask {
+    token: CancellationToken,
+    options: ClientOptions,
+}
+
+#[napi]
+impl DownloadTask {
+    #[napi(constructor)]
+    pub fn new(options: Option<ClientOptions>) -> Self {
+        Self {
+            token: CancellationToken::new(),
+            options: options.unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for DownloadTask {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[napi]
+impl DownloadTask {
+    #[napi]
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    #[napi]
+    #[allow(clippy::too_many_arguments, clippy::missing_errors_doc)]
+    pub async fn download(
+        &self,
+        url: String,
+        file_path: String,
+        metadata: Option<SongMetadata>,
+        thread_count: u32,
+        referer: Option<String>,
+        on_progress: ThreadsafeFunction<DownloadProgress>,
+        enable_http2: bool,
+        on_url_expired: Option<UrlRefreshCallback>,
+        mirrors: Option<Vec<String>>,
+        fix_extension: Option<bool>,
+    ) -> Result<DownloadResult> {
+        if self.token.is_cancelled() {
+            return Err(Error::new(Status::Cancelled, "下载已取消".to_string()));
+        }
+
+        let client = build_client(enable_http2, &self.options)?;
+        let source = Arc::new(UrlSource::new(url.clone(), mirrors, on_url_expired));
+
+        // Size detection
+        let remote = probe_remote(&client, &url, referer.as_deref()).await;
+        let total_size = remote.size;
+
+        println!(
+            "[Download] URL: {url}, Version: {:?}, Size: {total_size}",
+            remote.version
+        );
+
+        // Lets `StreamServer` serve the file while it is being written
+        let registration = Registration::new(&file_path);
+        let progress = registration.progress();
+
+        if hls::is_hls(&url, remote.content_type.as_deref()) {
+            println!("[Download] Mode: HLS, Threads: {thread_count}");
+            hls::download_hls(
+                self.token.clone(),
+                client.clone(),
+                url.clone(),
+                file_path.clone(),
+                thread_count,
+                referer,
+                on_progress,
+                &progress,
+            )
+            .await?;
+        } else if total_size > 0 {
+            println!("[Download] Threads: {thread_count}");
+            download_range_stream(
+                self.token.clone(),
+                client.clone(),
+                source,
+                file_path.clone(),
+                total_size,
+                thread_count,
+                referer,
+                on_progress,
+                &progress,
+            )
+            .await?;
+        } else {
+            println!("[Download] Mode: Simple Stream");
+            download_simple_stream(
+                self.token.clone(),
+                client.clone(),
+                source,
+                file_path.clone(),
+                total_size,
+                referer,
+                on_progress,
+                &progress,
+            )
+            .await?;
+        }
+
+        // Stream server readers must be done with the download before the file is renamed or
+        // rewritten, from here on they see it as a finished file
+        registration.complete();
+
+        let container =
+            sniff::detect_container(Path::new(&file_path), remote.content_type.as_deref());
+        println!("[Download] Container: {container:?}");
+        let final_path = match container {
+            Some(container) if fix_extension.unwrap_or(false) => {
+                correct_extension(&file_path, container).await?
+            }
+            _ => file_path.clone(),
+        };
+
+        if let Some(meta) = metadata {
+            if container.is_none_or(AudioContainer::supports_tags) {
+                process_metadata(client, final_path.clone(), meta).await?;
+            } else {
+                println!("[Download] Skip metadata: {container:?} cannot carry tags");
+            }
+        }
+
+        Ok(DownloadResult {
+            renamed: final_path != file_path,
+            file_path: final_path,
+            container,
+            content_type: remote.content_type,
+        })
+    }
+}
+
+// Renames the file if its extension does not match the detected container
+async fn correct_extension(file_path: &str, container: AudioContainer) -> Result<String> {
+    let path = Path::new(file_path);
+    if path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| container.matches_extension(ext))
+    {
+        return Ok(file_path.to_string());
+    }
+
+    // Never replace another file, pick a free name the way `render_download_path` does
+    let extension = container.extension();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut target = path.with_extension(extension);
+    let mut n = 0;
+    while tokio::fs::try_exists(&target).await.unwrap_or(true) {
+        n += 1;
+        if n > MAX_COLLISION_SUFFIX {
+            return Err(Error::from_reason("Too many files with the same name"));
+        }
+        target = path.with_file_name(format!("{stem} ({n}).{extension}"));
+    }
+    tokio::fs::rename(path, &target)
+        .await
+        .context("Rename failed")?;
+    println!("[Download] Renamed to {}", target.display());
+    Ok(target.to_string_lossy().into_owned())
+}
+
+#[derive(Default)]
+struct RemoteInfo {
+    size: u64,
+    version: Option<reqwest::Version>,
+    content_type: Option<String>,
+}
+
+fn content_type_of(resp: &reqwest::Response) -> Option<String> {
+    resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+async fn probe_remote(client: &reqwest::Client, url: &str, referer: Option<&str>) -> RemoteInfo {
+    // 1. HEAD request
+    let mut head_req = client.head(url);
+    if let Some(r) = referer {
+        head_req = head_req.header("Referer", r);
+    }
+
+    let mut content_type = None;
+    if let Ok(head_resp) = head_req.send().await {
+        let version = head_resp.version();
+        content_type = content_type_of(&head_resp);
+        if let Some(len) = head_resp.content_length() {
+            // Only accept if length > 0. If 0, fallback to Range probe.
+            if len > 0 {
+                return RemoteInfo {
+                    size: len,
+                    version: Some(version),
+                    content_type,
+                };
+            }
+        }
+    }
+
+    // 2. Range probe (bytes=0-0)
+    let mut range_req = client.get(url).header("Range", "bytes=0-0");
+    if let Some(r) = referer {
+        range_req = range_req.header("Referer", r);
+    }
+
+    if let Ok(range_resp) = range_req.send().await {
+        let version = range_resp.version();
+        let content_type = content_type_of(&range_resp).or_else(|| content_type.take());
+        if range_resp.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            if let Some(val) = range_resp.headers().get(reqwest::header::CONTENT_RANGE) {
+                if let Ok(s) = val.to_str() {
+                    // bytes 0-0/12345
+                    if let Some(size_str) = s.rsplit('/').next() {
+                        return RemoteInfo {
+                            size: size_str.parse().unwrap_or(0),
+                            version: Some(version),
+                            content_type,
+                        };
+                    }
+                }
+            }
+        }
+        return RemoteInfo {
+            content_type,
+            ..RemoteInfo::default()
+        };
+    }
+
+    RemoteInfo {
+        content_type,
+        ..RemoteInfo::default()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn download_simple_stream(
+    token: CancellationToken,
+    client: reqwest::Client,
+    source: Arc<UrlSource>,
+    file_path: String,
+    total_size: u64,
+    referer: Option<String>,
+    on_progress: ThreadsafeFunction<DownloadProgress>,
+    progress: &FileProgress,
+) -> Result<()> {
+    let response = send_simple_request(&client, &source, referer.as_deref()).await?;
+
+    let content_length = response.content_length().unwrap_or(0);
+
+    let mut file = tokio::fs::File::create(&file_path)
+        .await
+        .context("Create file failed")?;
+
+    let mut stream = response.bytes_stream();
+    let total_size = if total_size == 0 {
+        content_length
+    } else {
+        total_size
+    };
+    let tracker = Arc::new(ProgressTracker::new(total_size, on_progress));
+    if total_size > 0 {
+        progress.set_total_size(total_size);
+    }
+
+    let process_result = async {
+        // One response body streamed for the whole file
+        let _connection = tracker.connection();
+        let mut written = 0u64;
+        while let Some(item) = tokio::select! {
+            () = token.cancelled() => None,
+            item = stream.next() => item,
+        } {
+            let chunk = item.context("Read error")?;
+            file.write_all(&chunk).await.context("Write error")?;
+            file.flush().await.context("Flush failed")?;
+            progress.mark(written, written + chunk.len() as u64);
+            written += chunk.len() as u64;
+            tracker.update(chunk.len() as u64);
+        }
+
+        if token.is_cancelled() {
+            return Err(Error::new(
+                Status::Cancelled,
+                "Download cancelled".to_string(),
+            ));
+        }
+
+        file.flush().await.context("Flush failed")?;
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = process_result {
+        drop(file);
+        let _ = tokio::fs::remove_file(&file_path).await;
+        return Err(e);
+    }
+
+    tracker.finish();
+    Ok(())
+}
+
+async fn send_simple_request(
+    client: &reqwest::Client,
+    source: &UrlSource,
+    referer: Option<&str>,
+) -> Result<reqwest::Response> {
+    loop {
+        let (url, generation) = source.current();
+        let mut req = client.get(&url);
+        if let Some(r) = referer {
+            req = req.header("Referer", r);
+        }
+
+        match req.send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(resp),
+            Ok(resp) if is_url_expired(resp.status()) => {
+                source.switch_on_expired(generation, resp.status()).await?;
+            }
+            Ok(resp) => {
+                if !source.switch_on_failure(generation).await {
+                    return Err(Error::from_reason(format!(
+                        "Request failed: HTTP status {}",
+                        resp.status()
+                    )));
+                }
+            }
+            Err(e) => {
+                if !source.switch_on_failure(generation).await {
+                    return Err(e).context("Request failed");
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn download_range_stream(
+    token: CancellationToken,
+    client: reqwest::Client,
+    source: Arc<UrlSource>,
+    file_path: String,
+    total_size: u64,
+    thread_count: u32,
+    referer: Option<String>,
+    on_progress: ThreadsafeFunction<DownloadProgress>,
+    progress: &Arc<FileProgress>,
+) -> Result<()> {
+    let mut file = tokio::fs::File::create(&file_path)
+        .await
+        .context("Create file failed")?;
+    file.set_len(total_size)
+        .await
+        .context("Set length failed")?;
+
+    let chunk_count = total_size.div_ceil(CHUNK_SIZE);
+    let tracker = Arc::new(ProgressTracker::new(total_size, on_progress).with_chunks(chunk_count));
+
+    progress.set_total_size(total_size);
+    let scheduler = ChunkScheduler::new(chunk_count, CHUNK_SIZE, progress.clone());
+
+    let download_futures = futures_util::stream::iter(std::iter::from_fn(move || scheduler.next()))
+        .map(|index| {
+            let start = index * CHUNK_SIZE;
+            let end = std::cmp::min(start + CHUNK_SIZE, total_size) - 1;
+            let client = client.clone();
+            let source = source.clone();
+            let referer = referer.clone();
+            let token = token.clone();
+            let tracker = tracker.clone();
+
+            async move {
+                tracker.set_chunk_state(index, CHUNK_ACTIVE);
+                download_chunk_with_retry(
+                    client,
+                    source,
+                    referer,
+                    Some((start, end)),
+                    token,
+                    &tracker,
+                )
+                .await
+                .map(|data| (index, data))
+            }
+        });
+
+    let mut stream = download_futures.buffer_unordered(thread_count as usize);
+
+    let process_result = async {
+        while let Some(result) = stream.next().await {
+            let (index, data) = result?;
+            let offset = index * CHUNK_SIZE;
+            if token.is_cancelled() {
+                return Err(Error::new(
+                    Status::Cancelled,
+                    "Download cancelled".to_string(),
+                ));
+            }
+
+            file.seek(SeekFrom::Start(offset))
+                .await
+                .context("Seek failed")?;
+            file.write_all(&data).await.context("Write failed")?;
+            file.flush().await.context("Flush failed")?;
+            progress.mark(offset, offset + data.len() as u64);
+            tracker.set_chunk_state(index, CHUNK_DONE);
+            tracker.update(data.len() as u64);
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = process_result {
+        drop(file);
+        let _ = tokio::fs::remove_file(&file_path).await;
+        // Callers tell a cancel from a failure by the status
+        if e.status == Status::Cancelled {
+            return Err(e);
+        }
+        return Err(Error::from_reason(format!("Range download failed: {e}")));
+    }
+
+    tracker.finish();
+    Ok(())
+}
+
+// Fetches one byte range (or the whole resource when `range` is `None`)
+async fn download_chunk_with_retry(
+    client: reqwest::Client,
+    source: Arc<UrlSource>,
+    referer: Option<String>,
+    range: Option<(u64, u64)>,
+    token: CancellationToken,
+    tracker: &ProgressTracker,
+) -> Result<bytes::Bytes> {
+    let mut attempts = 0;
+    let mut last_error = String::new();
+    let expected_len = range.map(|(start, end)| end - start + 1);
+
+    while attempts < MAX_RETRIES {
+        if token.is_cancelled() {
+            return Err(Error::new(
+                Status::Cancelled,
+                "Download cancelled".to_string(),
+            ));
+        }
+
+        let (url, generation) = source.current();
+        let mut req = client.get(&url);
+        if let Some((start, end)) = range {
+            req = req.header("Range", format!("bytes={start}-{end}"));
+        }
+        if let Some(ref r) = referer {
+            req = req.header("Referer", r);
+        }
+
+        let connection = tracker.connection();
+        match req.send().await {
+            Ok(resp) => {
+                let status = resp.status();
+                if is_url_expired(status) {
+                    // Expired signed URL: switch and retry without burning an attempt
+                    source.switch_on_expired(generation, status).await?;
+                    continue;
+                }
+
+                if status.is_success() {
+                    match resp.bytes().await {
+                        Ok(bytes) if expected_len.is_none_or(|len| bytes.len() as u64 == len) => {
+                            return Ok(bytes)
+                        }
+                        Ok(bytes) => {
+                            last_error = format!(
+                                "Unexpected chunk length {} (expected {})",
+                                bytes.len(),
+                                expected_len.unwrap_or_default()
+                            );
+                        }
+                        Err(e) => {
+                            last_error = format!("Read bytes failed: {e}");
+                        }
+                    }
+                } else {
+                    last_error = format!("HTTP status {status}");
+                }
+            }
+            Err(e) => {
+                last_error = e.to_string();
+            }
+        }
+        drop(connection);
+
+        attempts += 1;
+        tracker.record_retry();
+        if attempts >= MAX_RETRIES && source.switch_on_failure(generation).await {
+            attempts = 0;
+            continue;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    }
+    let target = range.map_or_else(
+        || source.current().0,
+        |(start, end)| format!("{start}-{end}"),
+    );
+    Err(Error::from_reason(format!(
+        "Chunk {target} failed after {MAX_RETRIES} retries. Last error: {last_error}"
+    )))
+}
+
+async fn process_metadata(
+    client: reqwest::Client,
+    file_path: String,
+    meta: SongMetadata,
+) -> Result<()> {
+    let cover_data = fetch_cover(&client, &meta).await;
+
+    tokio::task::spawn_blocking(move || {
+        write_metadata(&file_path, meta, cover_data, &WriteTagOptions::default())
+    })
+    .await
+    .context("Metadata task panicked or cancelled")??;
+
+    Ok(())
+}
+
+async fn fetch_cover(client: &reqwest::Client, meta: &SongMetadata) -> Option<bytes::Bytes> {
+    let cover_url = meta.cover_url.as_ref()?;
+    if cover_url.is_empty() {
+        return None;
+    }
+
+    let resp = tokio::time::timeout(
+        std::time::Duration::from_secs(TIMEOUT_SECS),
+        client.get(cover_url).send(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    if resp.status().is_success() {
+        resp.bytes().await.ok()
+    } else {
+        None
+    }
+}
@@ -0,0 +1,374 @@
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::SongMetadata;
+
+const DEFAULT_REPLACEMENT: &str = "_";
+const MAX_COMPONENT_UNITS: usize = 255;
+// MAX_PATH is 260 including the terminating NUL
+const WINDOWS_MAX_PATH: usize = 259;
+const UNIX_MAX_PATH: usize = 4096;
+// Never shrink a component below this while fitting the total path length
+const MIN_COMPONENT_UNITS: usize = 16;
+pub(super) const MAX_COLLISION_SUFFIX: u32 = 9999;
+
+const WINDOWS_INVALID_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM0", "COM1", "COM2", "COM3", "COM4",
+    "COM5", "COM6", "COM7", "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathTarget {
+    /// The filesystem rules of the current platform
+    Auto,
+    /// NTFS / FAT / exFAT, also the safe choice for removable drives
+    Windows,
+    MacOs,
+    Linux,
+}
+
+impl PathTarget {
+    const fn resolve(self) -> Self {
+        match self {
+            Self::Auto => {
+                if cfg!(target_os = "windows") {
+                    Self::Windows
+                } else if cfg!(target_os = "macos") {
+                    Self::MacOs
+                } else {
+                    Self::Linux
+                }
+            }
+            other => other,
+        }
+    }
+
+    // Windows limits are in UTF-16 code units, the others in UTF-8 bytes
+    fn char_units(self, c: char) -> usize {
+        if self == Self::Windows {
+            c.len_utf16()
+        } else {
+            c.len_utf8()
+        }
+    }
+
+    fn units(self, s: &str) -> usize {
+        s.chars().map(|c| self.char_units(c)).sum()
+    }
+
+    fn is_invalid_char(self, c: char) -> bool {
+        if c == '/' || c.is_control() {
+            return true;
+        }
+        match self {
+            Self::Windows => WINDOWS_INVALID_CHARS.contains(&c),
+            Self::MacOs => c == ':',
+            _ => false,
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct RenderPathOptions {
+    /// Directory the rendered relative path is joined to
+    pub base_dir: Option<String>,
+    /// File extension, with or without the leading dot
+    pub extension: Option<String>,
+    pub target: Option<PathTarget>,
+    /// Replaces characters the target filesystem does not allow, defaults to `_`
+    pub replacement: Option<String>,
+    /// Maximum length of a single path component, defaults to 255
+    pub max_component_length: Option<u32>,
+    /// Maximum length of the whole path, defaults to 259 on Windows and 4096 elsewhere
+    pub max_path_length: Option<u32>,
+    /// Append ` (1)`, ` (2)`, ... when the path already exists, defaults to `true`
+    pub resolve_collisions: Option<bool>,
+    /// Paths already claimed by other pending downloads, treated as existing
+    pub reserved_paths: Option<Vec<String>>,
+}
+
+/// Renders a download path from a template such as
+/// `{album_artist}/{album}/{disc}-{track:02} {title}`
+///
+/// Supported fields: `title`, `artist`, `album`, `album_artist` (falls back to `artist`),
+/// `genre`, `year`, `track`, `disc`. A `:0N` suffix zero-pads numbers to `N` digits.
+/// `/` in the template separates directories, while `/` inside field values does not.
+#[napi]
+#[allow(clippy::needless_pass_by_value, clippy::missing_errors_doc)]
+pub fn render_download_path(
+    template: String,
+    metadata: SongMetadata,
+    options: Option<RenderPathOptions>,
+) -> Result<String> {
+    let options = options.unwrap_or_default();
+    let target = options.target.unwrap_or(PathTarget::Auto).resolve();
+    let replacement = options
+        .replacement
+        .as_deref()
+        .filter(|r| !r.chars().any(|c| target.is_invalid_char(c)))
+        .unwrap_or(DEFAULT_REPLACEMENT);
+    let max_component = options
+        .max_component_length
+        .map_or(MAX_COMPONENT_UNITS, |n| n as usize)
+        .max(1);
+    let max_path = options.max_path_length.map_or(
+        if target == PathTarget::Windows {
+            WINDOWS_MAX_PATH
+        } else {
+            UNIX_MAX_PATH
+        },
+        |n| n as usize,
+    );
+    let extension = options
+        .extension
+        .as_deref()
+        .map(|e| sanitize_component(e.trim_start_matches('.'), target, replacement))
+        .filter(|e| !e.is_empty())
+        .map(|e| format!(".{e}"))
+        .unwrap_or_default();
+
+    let mut components = Vec::new();
+    for part in template.split(['/', '\\']) {
+        let rendered = render_component(part, &metadata)?;
+        let sanitized = sanitize_component(&rendered, target, replacement);
+        if !sanitized.is_empty() {
+            components.push(sanitized);
+        }
+    }
+    let stem = components
+        .pop()
+        .ok_or_else(|| Error::from_reason("Rendered path is empty"))?;
+
+    let base_dir = options
+        .base_dir
+        .as_deref()
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    let mut dirs = components;
+    let stem = fit_lengths(
+        &base_dir,
+        &mut dirs,
+        &stem,
+        &extension,
+        target,
+        replacement,
+        max_component,
+        max_path,
+    );
+
+    let dir_path = dirs.iter().fold(base_dir, |path, dir| path.join(dir));
+    let reserved: HashSet<PathBuf> = options
+        .reserved_paths
+        .iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    let is_taken = |p: &Path| p.exists() || reserved.contains(p);
+
+    let mut path = dir_path.join(format!("{stem}{extension}"));
+    if options.resolve_collisions.unwrap_or(true) && is_taken(&path) {
+        path = (1..=MAX_COLLISION_SUFFIX)
+            .map(|n| {
+                let suffix = format!(" ({n})");
+                let room =
+                    max_component.saturating_sub(target.units(&suffix) + target.units(&extension));
+                let base = shorten(&stem, room, target, replacement);
+                dir_path.join(format!("{base}{suffix}{extension}"))
+            })
+            .find(|p| !is_taken(p))
+            .ok_or_else(|| Error::from_reason("Too many files with the same name"))?;
+    }
+
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn format_number(value: Option<u32>, spec: Option<&str>) -> Result<String> {
+    let Some(value) = value else {
+        return Ok(String::new());
+    };
+    match spec {
+        None => Ok(value.to_string()),
+        Some(spec) => {
+            let width: usize = spec
+                .trim_start_matches('0')
+                .parse()
+                .map_err(|_| Error::from_reason(format!("Invalid format spec ':{spec}'")))?;
+            Ok(format!("{value:0width$}"))
+        }
+    }
+}
+
+fn render_component(template: &str, meta: &SongMetadata) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let close = after
+            .find('}')
+            .ok_or_else(|| Error::from_reason(format!("Unclosed '{{' in template '{template}'")))?;
+        let (field, spec) = match after[..close].split_once(':') {
+            Some((field, spec)) => (field.trim(), Some(spec.trim())),
+            None => (after[..close].trim(), None),
+        };
+
+        let value = match field {
+            "title" => meta.title.clone(),
+            "artist" => meta.artist.clone(),
+            "album" => meta.album.clone(),
+            "album_artist" => meta
+                .album_artist
+                .clone()
+                .filter(|a| !a.is_empty())
+                .unwrap_or_else(|| meta.artist.clone()),
+            "genre" => meta.genre.clone().unwrap_or_default(),
+            "year" => format_number(meta.year, spec)?,
+            "track" => format_number(meta.track_number, spec)?,
+            "disc" => format_number(meta.disc_number, spec)?,
+            other => {
+                return Err(Error::from_reason(format!(
+                    "Unknown template field '{{{other}}}'"
+                )))
+            }
+        };
+        out.push_str(&value);
+        rest = &after[close + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn trim_trailing(name: &str, target: PathTarget) -> &str {
+    let name = name.trim_end();
+    if target == PathTarget::Windows {
+        // Windows silently drops trailing dots and spaces
+        name.trim_end_matches(['.', ' '])
+    } else {
+        name
+    }
+}
+
+fn sanitize_component(name: &str, target: PathTarget, replacement: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if target.is_invalid_char(c) {
+            out.push_str(replacement);
+        } else {
+            out.push(c);
+        }
+    }
+
+    fix_reserved(trim_trailing(out.trim_start(), target), target, replacement)
+}
+
+fn fix_reserved(name: &str, target: PathTarget, replacement: &str) -> String {
+    if name.chars().all(|c| c == '.') {
+        // "", "." and ".." are never usable as names
+        return name.replace('.', replacement);
+    }
+
+    if target == PathTarget::Windows {
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(stem))
+        {
+            return format!("{stem}{replacement}{}", &name[stem.len()..]);
+        }
+    }
+    name.to_string()
+}
+
+// Truncates a sanitized name to `max` units. Cutting can expose a reserved name ("CONCERT"
+// becomes "CON"), trailing dots or nothing at all, so the checks run again on the result.
+fn shorten(name: &str, max: usize, target: PathTarget, replacement: &str) -> String {
+    let mut room = max;
+    loop {
+        let short = trim_trailing(truncate_units(name, room, target), target);
+        let short = if short.is_empty() {
+            replacement.to_string()
+        } else {
+            fix_reserved(short, target, replacement)
+        };
+        // A fix may have made it longer again
+        let over = target.units(&short).saturating_sub(max);
+        if over == 0 || room == 0 {
+            return short;
+        }
+        room = room.saturating_sub(over);
+    }
+}
+
+// Truncates to at most `max` units without splitting a character
+fn truncate_units(s: &str, max: usize, target: PathTarget) -> &str {
+    let mut units = 0;
+    for (idx, c) in s.char_indices() {
+        units += target.char_units(c);
+        if units > max {
+            return &s[..idx];
+        }
+    }
+    s
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fit_lengths(
+    base_dir: &Path,
+    dirs: &mut [String],
+    stem: &str,
+    extension: &str,
+    target: PathTarget,
+    replacement: &str,
+    max_component: usize,
+    max_path: usize,
+) -> String {
+    let ext_units = target.units(extension);
+    for dir in dirs.iter_mut() {
+        *dir = shorten(dir, max_component, target, replacement);
+    }
+    let mut stem = shorten(
+        stem,
+        max_component.saturating_sub(ext_units).max(1),
+        target,
+        replacement,
+    );
+
+    // Separators between base, dirs and file name
+    let total = |dirs: &[String], stem: &str| {
+        let base = target.units(&base_dir.to_string_lossy());
+        let separators = dirs.len() + usize::from(base > 0);
+        base + separators
+            + dirs.iter().map(|d| target.units(d)).sum::<usize>()
+            + target.units(stem)
+            + ext_units
+    };
+
+    // Shrink the file name first, then directories from the innermost outwards
+    let mut excess = total(dirs, &stem).saturating_sub(max_path);
+    if excess > 0 {
+        let units = target.units(&stem);
+        let keep = units
+            .saturating_sub(excess)
+            .max(MIN_COMPONENT_UNITS.min(units));
+        stem = shorten(&stem, keep, target, replacement);
+        excess = total(dirs, &stem).saturating_sub(max_path);
+    }
+    for dir in dirs.iter_mut().rev() {
+        if excess == 0 {
+            break;
+        }
+        let units = target.units(dir);
+        let keep = units
+            .saturating_sub(excess)
+            .max(MIN_COMPONENT_UNITS.min(units));
+        *dir = shorten(dir, keep, target, replacement);
+        excess = excess.saturating_sub(units.saturating_sub(target.units(dir)));
+    }
+    stem
+}
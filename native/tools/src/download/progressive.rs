@@ -0,0 +1,215 @@
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError};
+use tokio::sync::Notify;
+
+const STATE_RUNNING: u8 = 0;
+const STATE_DONE: u8 = 1;
+const STATE_FAILED: u8 = 2;
+
+// Downloads in progress, keyed by target path, so the stream server can follow them
+static ACTIVE: LazyLock<DashMap<PathBuf, Arc<FileProgress>>> = LazyLock::new(DashMap::new);
+// How the latest finished download of each path ended: its sequence number and whether it
+// completed. A failed one leaves a partial file the stream server must not serve.
+static FINISHED: LazyLock<DashMap<PathBuf, (u64, bool)>> = LazyLock::new(DashMap::new);
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ProgressState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Which byte ranges of a file being downloaded are already on disk
+pub(super) struct FileProgress {
+    // 0 while unknown
+    total_size: AtomicU64,
+    // Sorted, merged, half-open
+    ranges: Mutex<Vec<(u64, u64)>>,
+    // Offset a reader is blocked on, picked up by the chunk scheduler
+    wanted: Mutex<Option<u64>>,
+    state: AtomicU8,
+    notify: Notify,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl FileProgress {
+    fn new(total_size: u64) -> Self {
+        Self {
+            total_size: AtomicU64::new(total_size),
+            ranges: Mutex::new(Vec::new()),
+            wanted: Mutex::new(None),
+            state: AtomicU8::new(STATE_RUNNING),
+            notify: Notify::new(),
+        }
+    }
+
+    pub(super) fn total_size(&self) -> Option<u64> {
+        Some(self.total_size.load(Ordering::Acquire)).filter(|&s| s > 0)
+    }
+
+    pub(super) fn set_total_size(&self, total_size: u64) {
+        self.total_size.store(total_size, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    pub(super) fn state(&self) -> ProgressState {
+        match self.state.load(Ordering::Acquire) {
+            STATE_RUNNING => ProgressState::Running,
+            STATE_DONE => ProgressState::Done,
+            _ => ProgressState::Failed,
+        }
+    }
+
+    /// Records that `[start, end)` has been written
+    pub(super) fn mark(&self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        {
+            let mut ranges = lock(&self.ranges);
+            let idx = ranges.partition_point(|&(_, e)| e < start);
+            let mut merged = (start, end);
+            let mut last = idx;
+            while last < ranges.len() && ranges[last].0 <= merged.1 {
+                merged.0 = merged.0.min(ranges[last].0);
+                merged.1 = merged.1.max(ranges[last].1);
+                last += 1;
+            }
+            ranges.splice(idx..last, [merged]);
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// End of the written region containing `offset`, `None` if `offset` is not written yet
+    pub(super) fn available_until(&self, offset: u64) -> Option<u64> {
+        let ranges = lock(&self.ranges);
+        let idx = ranges.partition_point(|&(_, e)| e <= offset);
+        ranges
+            .get(idx)
+            .filter(|&&(s, _)| s <= offset)
+            .map(|&(_, e)| e)
+    }
+
+    /// Asks the scheduler to fetch the chunk covering `offset` next
+    pub(super) fn request(&self, offset: u64) {
+        *lock(&self.wanted) = Some(offset);
+    }
+
+    fn take_wanted(&self) -> Option<u64> {
+        lock(&self.wanted).take()
+    }
+
+    /// Future that resolves on the next progress change. Create it before checking the state
+    /// so no change is missed.
+    pub(super) fn changed(&self) -> tokio::sync::futures::Notified<'_> {
+        self.notify.notified()
+    }
+
+    fn finish(&self, state: u8) {
+        self.state.store(state, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+}
+
+/// Keeps a download visible to the stream server, marks it failed unless `complete` is called
+pub(super) struct Registration {
+    path: PathBuf,
+    progress: Arc<FileProgress>,
+    seq: u64,
+    done: bool,
+}
+
+impl Registration {
+    pub(super) fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let progress = Arc::new(FileProgress::new(0));
+        let seq = NEXT_SEQ.fetch_add(1, Ordering::AcqRel);
+        ACTIVE.insert(path.clone(), progress.clone());
+        Self {
+            path,
+            progress,
+            seq,
+            done: false,
+        }
+    }
+
+    pub(super) fn progress(&self) -> Arc<FileProgress> {
+        self.progress.clone()
+    }
+
+    pub(super) fn complete(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // Only remove our own entry, a new download of the same path may have replaced it
+        ACTIVE.remove_if(&self.path, |_, p| Arc::ptr_eq(p, &self.progress));
+        FINISHED
+            .entry(self.path.clone())
+            .and_modify(|finished| {
+                if finished.0 < self.seq {
+                    *finished = (self.seq, self.done);
+                }
+            })
+            .or_insert((self.seq, self.done));
+        self.progress
+            .finish(if self.done { STATE_DONE } else { STATE_FAILED });
+    }
+}
+
+pub(super) fn lookup(path: &Path) -> Option<Arc<FileProgress>> {
+    ACTIVE.get(path).map(|p| p.value().clone())
+}
+
+/// Sequence number the next download will get, downloads from before have a lower one
+pub(super) fn next_seq() -> u64 {
+    NEXT_SEQ.load(Ordering::Acquire)
+}
+
+/// Sequence number of the latest finished download of `path` and whether it completed
+pub(super) fn finished(path: &Path) -> Option<(u64, bool)> {
+    FINISHED.get(path).map(|f| *f.value())
+}
+
+/// Hands out chunk indices in order, except that a chunk a reader is waiting for jumps
+/// the queue
+pub(super) struct ChunkScheduler {
+    pending: Mutex<BTreeSet<u64>>,
+    chunk_size: u64,
+    progress: Arc<FileProgress>,
+}
+
+impl ChunkScheduler {
+    pub(super) fn new(chunk_count: u64, chunk_size: u64, progress: Arc<FileProgress>) -> Self {
+        Self {
+            pending: Mutex::new((0..chunk_count).collect()),
+            chunk_size,
+            progress,
+        }
+    }
+
+    pub(super) fn next(&self) -> Option<u64> {
+        let mut pending = lock(&self.pending);
+        if let Some(offset) = self.progress.take_wanted() {
+            let index = offset / self.chunk_size;
+            if pending.remove(&index) {
+                return Some(index);
+            }
+            // Already in flight, continue with the chunks right after it
+            if let Some(&next) = pending.range(index..).next() {
+                pending.remove(&next);
+                return Some(next);
+            }
+        }
+        pending.pop_first()
+    }
+}
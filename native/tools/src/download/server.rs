@@ -0,0 +1,487 @@
+use dashmap::DashMap;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use super::progressive::{self, FileProgress, ProgressState};
+use crate::utils::Context;
+
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const READ_BUFFER_SIZE: u64 = 256 * 1024;
+// How long a request waits for missing bytes (or for the download to start) before giving up
+const WAIT_TIMEOUT_SECS: u64 = 30;
+const STREAM_PATH_PREFIX: &str = "/stream/";
+// Random bytes in a stream id, enough that other local processes and web pages can't guess one
+const STREAM_ID_BYTES: usize = 16;
+
+struct ServedFile {
+    path: PathBuf,
+    content_type: String,
+    // Set when a download is about to start, downloads older than this are ignored
+    since: Option<u64>,
+}
+
+/// Loopback HTTP server that serves songs while `DownloadTask` is still writing them.
+///
+/// Byte ranges that are already on disk are answered immediately, requests for missing
+/// ranges wait and move the chunks they need to the front of the download queue.
+/// Tags written after the download rewrite the file, so pass the metadata to `download`
+/// only for files that are not being played through the server.
+///
+/// URLs carry random ids and no CORS header is sent unless `allowed_origin` is given, so
+/// other web pages can't read files through the server.
+#[napi]
+pub struct StreamServer {
+    port: u16,
+    files: Arc<DashMap<String, ServedFile>>,
+    token: CancellationToken,
+}
+
+#[napi]
+impl StreamServer {
+    /// Binds `127.0.0.1:port`, port `0` (the default) picks a free one. `allowed_origin` is
+    /// the origin of the app's own pages, e.g. for Web Audio on the stream.
+    #[napi(constructor)]
+    #[allow(clippy::missing_errors_doc)]
+    pub fn new(port: Option<u32>, allowed_origin: Option<String>) -> Result<Self> {
+        let port = u16::try_from(port.unwrap_or(0))
+            .map_err(|_| Error::from_reason("Port out of range"))?;
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+            .context("Bind stream server failed")?;
+        listener
+            .set_nonblocking(true)
+            .context("Configure stream server failed")?;
+        let port = listener
+            .local_addr()
+            .context("Read stream server address failed")?
+            .port();
+
+        let files = Arc::new(DashMap::new());
+        let token = CancellationToken::new();
+        let origin: Option<Arc<str>> = allowed_origin.map(Into::into);
+        napi::bindgen_prelude::spawn(accept_loop(listener, files.clone(), origin, token.clone()));
+        println!("[StreamServer] Listening on 127.0.0.1:{port}");
+
+        Ok(Self { port, files, token })
+    }
+
+    #[napi(getter)]
+    pub fn port(&self) -> u32 {
+        u32::from(self.port)
+    }
+
+    /// Returns the URL the player should use for `file_path`. Set `await_download` if a
+    /// download to it is about to start, a file already there is then not served until that
+    /// download has begun.
+    #[napi]
+    #[allow(clippy::missing_errors_doc)]
+    pub fn serve(
+        &self,
+        file_path: String,
+        content_type: Option<String>,
+        await_download: Option<bool>,
+    ) -> Result<String> {
+        let path = PathBuf::from(file_path);
+        let content_type = content_type.unwrap_or_else(|| guess_content_type(&path).to_string());
+        let mut random = [0u8; STREAM_ID_BYTES];
+        getrandom::fill(&mut random).context("Generate stream id failed")?;
+        let id = random.iter().fold(String::new(), |mut id, b| {
+            let _ = write!(id, "{b:02x}");
+            id
+        });
+        let extension = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let since = await_download.unwrap_or(false).then(progressive::next_seq);
+        self.files.insert(
+            id.clone(),
+            ServedFile {
+                path,
+                content_type,
+                since,
+            },
+        );
+        Ok(format!(
+            "http://127.0.0.1:{}{STREAM_PATH_PREFIX}{id}{extension}",
+            self.port
+        ))
+    }
+
+    /// Stops serving a URL returned by `serve`
+    #[napi]
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn unserve(&self, url: String) -> bool {
+        self.files.remove(&id_from_path(&url)).is_some()
+    }
+
+    #[napi]
+    pub fn stop(&self) {
+        self.files.clear();
+        self.token.cancel();
+    }
+}
+
+impl Drop for StreamServer {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+fn guess_content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match ext.as_str() {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "m4a" | "mp4" | "aac" => "audio/mp4",
+        "ogg" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "webm" => "audio/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+// "/stream/<id>.flac?x" or a full URL -> "<id>"
+fn id_from_path(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+async fn accept_loop(
+    listener: std::net::TcpListener,
+    files: Arc<DashMap<String, ServedFile>>,
+    origin: Option<Arc<str>>,
+    token: CancellationToken,
+) {
+    let listener = match TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("[StreamServer] Start failed: {e}");
+            return;
+        }
+    };
+    loop {
+        let (stream, _) = tokio::select! {
+            () = token.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("[StreamServer] Accept failed: {e}");
+                    continue;
+                }
+            },
+        };
+        let files = files.clone();
+        let origin = origin.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                () = token.cancelled() => {}
+                result = handle_connection(stream, &files, origin.as_deref()) => {
+                    if let Err(e) = result {
+                        // Players routinely drop connections when seeking
+                        println!("[StreamServer] Connection closed: {e}");
+                    }
+                }
+            }
+        });
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+async fn read_request(reader: &mut BufReader<TcpStream>) -> std::io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut range = None;
+    let mut header_bytes = line.len();
+    loop {
+        line.clear();
+        let n = reader.read_line(&mut line).await?;
+        header_bytes += n;
+        if n == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+        if header_bytes > MAX_HEADER_BYTES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Request header too large",
+            ));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok(Some(Request {
+        method,
+        path,
+        range,
+    }))
+}
+
+// The response side of a connection, `origin` is the only origin allowed to read it
+struct Response<'a> {
+    stream: TcpStream,
+    origin: Option<&'a str>,
+}
+
+impl Response<'_> {
+    async fn write_head(
+        &mut self,
+        status: &str,
+        headers: &[(&str, String)],
+    ) -> std::io::Result<()> {
+        let mut head = format!("HTTP/1.1 {status}\r\n");
+        for (name, value) in headers {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+        if let Some(origin) = self.origin {
+            let _ = write!(head, "Access-Control-Allow-Origin: {origin}\r\n");
+        }
+        head.push_str("Accept-Ranges: bytes\r\nConnection: close\r\n\r\n");
+        self.stream.write_all(head.as_bytes()).await
+    }
+
+    async fn write_empty(&mut self, status: &str) -> std::io::Result<()> {
+        self.write_head(status, &[("Content-Length", "0".to_string())])
+            .await
+    }
+}
+
+enum Source {
+    Complete(u64),
+    Progressive(Arc<FileProgress>),
+}
+
+impl Source {
+    // Files left behind by a failed download are partial, and with `since` set anything on
+    // disk before that download started is stale
+    async fn resolve(path: &Path, since: Option<u64>) -> Option<Self> {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(WAIT_TIMEOUT_SECS);
+        loop {
+            if let Some(progress) = progressive::lookup(path) {
+                return Some(Self::Progressive(progress));
+            }
+            let finished = progressive::finished(path)
+                .filter(|&(seq, _)| since.is_none_or(|since| seq >= since));
+            match finished {
+                Some((_, false)) => return None,
+                Some((_, true)) => {
+                    let meta = tokio::fs::metadata(path).await.ok()?;
+                    return Some(Self::Complete(meta.len()));
+                }
+                None if since.is_none() => {
+                    if let Ok(meta) = tokio::fs::metadata(path).await {
+                        return Some(Self::Complete(meta.len()));
+                    }
+                }
+                None => {}
+            }
+            // The download may not have started yet
+            if tokio::time::Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    // `None` while the size is not known yet
+    async fn total_size(&self) -> Option<u64> {
+        let progress = match self {
+            Self::Complete(size) => return Some(*size),
+            Self::Progressive(progress) => progress,
+        };
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(WAIT_TIMEOUT_SECS);
+        loop {
+            let changed = progress.changed();
+            if let Some(size) = progress.total_size() {
+                return Some(size);
+            }
+            if progress.state() != ProgressState::Running {
+                return None;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    /// Waits until `offset` is on disk and returns the end of the available region.
+    /// `None` if it never will be.
+    async fn available_until(&self, offset: u64, path: &Path) -> Option<u64> {
+        let progress = match self {
+            Self::Complete(size) => return (offset < *size).then_some(*size),
+            Self::Progressive(progress) => progress,
+        };
+        loop {
+            let changed = progress.changed();
+            if let Some(end) = progress.available_until(offset) {
+                return Some(end);
+            }
+            match progress.state() {
+                ProgressState::Running => {}
+                // Done but not written means past the end of a file of unknown size
+                ProgressState::Done => {
+                    let size = tokio::fs::metadata(path).await.ok()?.len();
+                    return (offset < size).then_some(size);
+                }
+                ProgressState::Failed => return None,
+            }
+            progress.request(offset);
+            tokio::time::timeout(Duration::from_secs(WAIT_TIMEOUT_SECS), changed)
+                .await
+                .ok()?;
+        }
+    }
+}
+
+// Single range only, which is all media players send. `None` means unsatisfiable.
+fn parse_range(header: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let len: u64 = suffix.parse().ok()?;
+            (total.checked_sub(len.min(total))?, total.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, total.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(total.checked_sub(1)?),
+        ),
+    };
+    (start <= end && start < total).then_some((start, end))
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    files: &DashMap<String, ServedFile>,
+    origin: Option<&str>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let Some(request) = read_request(&mut reader).await? else {
+        return Ok(());
+    };
+    let mut response = Response {
+        stream: reader.into_inner(),
+        origin,
+    };
+
+    if request.method != "GET" && request.method != "HEAD" {
+        return response.write_empty("405 Method Not Allowed").await;
+    }
+    let served = request
+        .path
+        .strip_prefix(STREAM_PATH_PREFIX)
+        .and_then(|rest| files.get(&id_from_path(rest)))
+        .map(|f| (f.path.clone(), f.content_type.clone(), f.since));
+    let Some((path, content_type, since)) = served else {
+        return response.write_empty("404 Not Found").await;
+    };
+    let Some(source) = Source::resolve(&path, since).await else {
+        return response.write_empty("404 Not Found").await;
+    };
+    let head_only = request.method == "HEAD";
+
+    let Some(total) = source.total_size().await else {
+        // Unknown length: stream everything and close
+        response
+            .write_head("200 OK", &[("Content-Type", content_type)])
+            .await?;
+        if head_only {
+            return Ok(());
+        }
+        return send_body(&mut response.stream, &source, &path, 0, None).await;
+    };
+
+    let (status, start, end) = match request.range.as_deref() {
+        None => ("200 OK", 0, total.saturating_sub(1)),
+        Some(range) => match parse_range(range, total) {
+            Some((start, end)) => ("206 Partial Content", start, end),
+            None => {
+                return response
+                    .write_head(
+                        "416 Range Not Satisfiable",
+                        &[
+                            ("Content-Range", format!("bytes */{total}")),
+                            ("Content-Length", "0".to_string()),
+                        ],
+                    )
+                    .await;
+            }
+        },
+    };
+
+    let length = if total == 0 { 0 } else { end - start + 1 };
+    let mut headers = vec![
+        ("Content-Type", content_type),
+        ("Content-Length", length.to_string()),
+    ];
+    if status.starts_with("206") {
+        headers.push(("Content-Range", format!("bytes {start}-{end}/{total}")));
+    }
+    response.write_head(status, &headers).await?;
+    if head_only || length == 0 {
+        return Ok(());
+    }
+    send_body(&mut response.stream, &source, &path, start, Some(end)).await
+}
+
+// Sends `[start, end]`, or everything from `start` when `end` is `None`
+async fn send_body(
+    stream: &mut TcpStream,
+    source: &Source,
+    path: &Path,
+    start: u64,
+    end: Option<u64>,
+) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = Vec::new();
+    let mut pos = start;
+    while end.is_none_or(|end| pos <= end) {
+        let Some(available) = source.available_until(pos, path).await else {
+            if end.is_none() {
+                return Ok(());
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Download failed or timed out",
+            ));
+        };
+        let limit = end.map_or(available, |end| available.min(end + 1));
+        let len = (limit - pos).min(READ_BUFFER_SIZE);
+
+        buf.resize(len as usize, 0);
+        file.seek(std::io::SeekFrom::Start(pos)).await?;
+        file.read_exact(&mut buf).await?;
+        stream.write_all(&buf).await?;
+        pos += len;
+    }
+    stream.flush().await
+}
@@ -0,0 +1,165 @@
+use napi_derive::napi;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::utils::id3v2_len;
+
+// MPEG-TS packets start with a sync byte every 188 bytes
+const TS_PACKET_SIZE: usize = 188;
+const HEAD_LEN: usize = TS_PACKET_SIZE * 2 + 1;
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioContainer {
+    Mp3,
+    Flac,
+    /// MP4 / M4A, usually AAC or ALAC
+    Mp4,
+    /// Raw ADTS AAC
+    Aac,
+    /// Ogg Vorbis (or Ogg FLAC)
+    Ogg,
+    Opus,
+    Wav,
+    Aiff,
+    Ape,
+    WavPack,
+    /// MPEG transport stream, what HLS usually delivers. Cannot carry tags
+    MpegTs,
+}
+
+impl AudioContainer {
+    /// Preferred extension, without the dot
+    pub(super) const fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Mp4 => "m4a",
+            Self::Aac => "aac",
+            Self::Ogg => "ogg",
+            Self::Opus => "opus",
+            Self::Wav => "wav",
+            Self::Aiff => "aiff",
+            Self::Ape => "ape",
+            Self::WavPack => "wv",
+            Self::MpegTs => "ts",
+        }
+    }
+
+    /// Whether `ext` is a usual extension for this container
+    pub(super) fn matches_extension(self, ext: &str) -> bool {
+        let ext = ext.to_ascii_lowercase();
+        let aliases: &[&str] = match self {
+            Self::Mp4 => &["m4a", "mp4", "m4b", "alac"],
+            Self::Ogg => &["ogg", "oga"],
+            Self::Opus => &["opus", "ogg"],
+            Self::Aiff => &["aiff", "aif", "aifc"],
+            _ => &[],
+        };
+        ext == self.extension() || aliases.contains(&ext.as_str())
+    }
+
+    pub(super) const fn supports_tags(self) -> bool {
+        !matches!(self, Self::MpegTs)
+    }
+
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        Some(match mime.as_str() {
+            "audio/mpeg" | "audio/mp3" | "audio/mpeg3" => Self::Mp3,
+            "audio/flac" | "audio/x-flac" => Self::Flac,
+            "audio/mp4" | "audio/m4a" | "audio/x-m4a" | "video/mp4" => Self::Mp4,
+            "audio/aac" | "audio/aacp" | "audio/x-aac" => Self::Aac,
+            "audio/ogg" | "audio/vorbis" | "application/ogg" => Self::Ogg,
+            "audio/opus" => Self::Opus,
+            "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => Self::Wav,
+            "audio/aiff" | "audio/x-aiff" => Self::Aiff,
+            "audio/ape" | "audio/x-ape" => Self::Ape,
+            "audio/wavpack" | "audio/x-wavpack" => Self::WavPack,
+            "video/mp2t" => Self::MpegTs,
+            _ => return None,
+        })
+    }
+}
+
+/// Detects the container of a downloaded file from its magic bytes, falling back to the
+/// `Content-Type` the server sent when the content is not recognized
+pub(super) fn detect_container(path: &Path, content_type: Option<&str>) -> Option<AudioContainer> {
+    let sniffed = sniff_file(path).unwrap_or_else(|e| {
+        println!("[Download] Sniff failed: {e}");
+        None
+    });
+    sniffed.or_else(|| content_type.and_then(AudioContainer::from_content_type))
+}
+
+fn sniff_file(path: &Path) -> io::Result<Option<AudioContainer>> {
+    let mut file = File::open(path)?;
+    let mut head = read_head(&mut file)?;
+
+    // An ID3v2 tag can prefix MP3, AAC and (rarely) FLAC, look behind it
+    if let Some(tag_len) = id3v2_len(&head) {
+        file.seek(SeekFrom::Start(tag_len))?;
+        head = read_head(&mut file)?;
+        return Ok(sniff_bytes(&head).or(Some(AudioContainer::Mp3)));
+    }
+
+    Ok(sniff_bytes(&head))
+}
+
+fn read_head(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    file.take(HEAD_LEN as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+fn sniff_bytes(head: &[u8]) -> Option<AudioContainer> {
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+
+    if at(0, b"fLaC") {
+        Some(AudioContainer::Flac)
+    } else if at(0, b"OggS") {
+        // The first page holds the codec identification header
+        Some(if at(28, b"OpusHead") {
+            AudioContainer::Opus
+        } else {
+            AudioContainer::Ogg
+        })
+    } else if at(4, b"ftyp") {
+        Some(AudioContainer::Mp4)
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Some(AudioContainer::Wav)
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        Some(AudioContainer::Aiff)
+    } else if at(0, b"MAC ") {
+        Some(AudioContainer::Ape)
+    } else if at(0, b"wvpk") {
+        Some(AudioContainer::WavPack)
+    } else if head.len() > TS_PACKET_SIZE * 2
+        && head[0] == 0x47
+        && head[TS_PACKET_SIZE] == 0x47
+        && head[TS_PACKET_SIZE * 2] == 0x47
+    {
+        Some(AudioContainer::MpegTs)
+    } else {
+        sniff_frame_sync(head)
+    }
+}
+
+// MPEG audio and ADTS share the 12 bit frame sync, the layer bits tell them apart
+fn sniff_frame_sync(head: &[u8]) -> Option<AudioContainer> {
+    let (&b0, &b1) = (head.first()?, head.get(1)?);
+    if b0 != 0xFF || b1 & 0xE0 != 0xE0 {
+        return None;
+    }
+    match (b1 >> 1) & 0x03 {
+        0 if b1 & 0xF0 == 0xF0 => Some(AudioContainer::Aac),
+        0 => None,
+        _ => Some(AudioContainer::Mp3),
+    }
+}
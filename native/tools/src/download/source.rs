@@ -0,0 +1,168 @@
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunction;
+use napi_derive::napi;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// Upper bound on how often the JS side is asked for a fresh URL during one download
+const MAX_URL_REFRESHES: u32 = 5;
+
+#[napi(object)]
+pub struct UrlRefreshRequest {
+    /// The URL that stopped working
+    pub url: String,
+    /// HTTP status that triggered the refresh (401, 403 or 410)
+    pub status: u32,
+}
+
+pub type UrlRefreshCallback =
+    ThreadsafeFunction<UrlRefreshRequest, Either<String, Promise<String>>>;
+
+struct SourceState {
+    url: String,
+    next_mirror: usize,
+    refreshes: u32,
+    // Bumped on every switch so concurrent chunks only switch once per failure
+    generation: u64,
+}
+
+/// The URL that chunks are currently fetched from, shared by all workers of one download.
+///
+/// Signed URLs expire mid-download, so a chunk that sees 401/403/410 asks the JS side for a
+/// fresh URL and falls back to the next mirror. Chunks that are already written stay valid.
+pub(super) struct UrlSource {
+    state: Mutex<SourceState>,
+    mirrors: Vec<String>,
+    on_refresh: Option<UrlRefreshCallback>,
+    switch_lock: tokio::sync::Mutex<()>,
+}
+
+pub(super) const fn is_url_expired(status: reqwest::StatusCode) -> bool {
+    matches!(
+        status,
+        reqwest::StatusCode::UNAUTHORIZED
+            | reqwest::StatusCode::FORBIDDEN
+            | reqwest::StatusCode::GONE
+    )
+}
+
+impl UrlSource {
+    pub(super) fn new(
+        url: String,
+        mirrors: Option<Vec<String>>,
+        on_refresh: Option<UrlRefreshCallback>,
+    ) -> Self {
+        let mirrors = mirrors
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|m| !m.is_empty() && *m != url)
+            .collect();
+        Self {
+            state: Mutex::new(SourceState {
+                url,
+                next_mirror: 0,
+                refreshes: 0,
+                generation: 0,
+            }),
+            mirrors,
+            on_refresh,
+            switch_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, SourceState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Current URL and its generation
+    pub(super) fn current(&self) -> (String, u64) {
+        let state = self.lock_state();
+        (state.url.clone(), state.generation)
+    }
+
+    /// Called when the URL of `generation` was rejected as expired.
+    ///
+    /// Asks the JS callback for a fresh URL first and rotates to the next mirror otherwise.
+    pub(super) async fn switch_on_expired(
+        &self,
+        generation: u64,
+        status: reqwest::StatusCode,
+    ) -> Result<()> {
+        let _guard = self.switch_lock.lock().await;
+        let (url, current_generation, refreshes) = {
+            let state = self.lock_state();
+            (state.url.clone(), state.generation, state.refreshes)
+        };
+        if current_generation != generation {
+            // Another chunk already switched
+            return Ok(());
+        }
+
+        if refreshes < MAX_URL_REFRESHES {
+            if let Some(fresh) = self.request_fresh_url(url.clone(), status).await {
+                if fresh != url {
+                    println!("[Download] URL refreshed after HTTP {status}");
+                    let mut state = self.lock_state();
+                    state.url = fresh;
+                    state.refreshes += 1;
+                    state.generation += 1;
+                    drop(state);
+                    return Ok(());
+                }
+            }
+        }
+
+        if self.rotate_mirror() {
+            Ok(())
+        } else {
+            Err(Error::from_reason(format!(
+                "URL expired (HTTP {status}) and no fresh URL or mirror is available"
+            )))
+        }
+    }
+
+    /// Called when the URL of `generation` keeps failing for other reasons.
+    ///
+    /// Returns `true` if a different URL should be tried.
+    pub(super) async fn switch_on_failure(&self, generation: u64) -> bool {
+        let _guard = self.switch_lock.lock().await;
+        if self.current().1 != generation {
+            return true;
+        }
+        self.rotate_mirror()
+    }
+
+    fn rotate_mirror(&self) -> bool {
+        let mut state = self.lock_state();
+        let Some(mirror) = self.mirrors.get(state.next_mirror) else {
+            return false;
+        };
+        println!("[Download] Switching to mirror: {mirror}");
+        state.url.clone_from(mirror);
+        state.next_mirror += 1;
+        state.generation += 1;
+        true
+    }
+
+    async fn request_fresh_url(&self, url: String, status: reqwest::StatusCode) -> Option<String> {
+        let callback = self.on_refresh.as_ref()?;
+        let request = UrlRefreshRequest {
+            url,
+            status: u32::from(status.as_u16()),
+        };
+        let fresh = match callback.call_async(Ok(request)).await {
+            Ok(Either::A(url)) => url,
+            Ok(Either::B(promise)) => match promise.await {
+                Ok(url) => url,
+                Err(e) => {
+                    eprintln!("[Download] URL refresh callback rejected: {e}");
+                    return None;
+                }
+            },
+            Err(e) => {
+                eprintln!("[Download] URL refresh callback failed: {e}");
+                return None;
+            }
+        };
+        Some(fresh).filter(|u| !u.is_empty())
+    }
+}
@@ -6,11 +6,16 @@
 //! 即使它在其他平台是空操作以防止 JS 端在其他平台编译时找不到对应的函数声明
 
 mod analysis;
+mod cache;
 mod download;
+mod metadata;
 mod scanner;
+mod utils;
 
 pub use analysis::*;
+pub use cache::*;
 pub use download::*;
+pub use metadata::*;
 use napi_derive::napi;
 pub use scanner::scan_music_library;
 #[cfg(target_os = "windows")]
@@ -0,0 +1,553 @@
+use lofty::prelude::*;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagItem};
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{read_tagged_file, save_tag};
+use crate::utils::Context;
+
+const APP_DIR_NAME: &str = "SPlayer";
+const JOURNAL_DIR_NAME: &str = "tag-journal";
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagEditOp {
+    Set,
+    Clear,
+    /// Appends `value` to the current value, or sets it if the field is empty
+    Append,
+    /// Replaces all matches of `pattern` with `value`, `$1` style groups are supported
+    RegexReplace,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct TagEdit {
+    /// `title`, `artist`, `album`, `albumArtist`, `genre`, `year`, `trackNumber`, `trackTotal`,
+    /// `discNumber`, `discTotal`, `comment`, `composer` or `lyric`
+    pub field: String,
+    pub op: TagEditOp,
+    pub value: Option<String>,
+    pub pattern: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct BatchEditOptions {
+    /// Where undo journals are stored, defaults to a directory in the user's app data dir
+    pub journal_dir: Option<String>,
+    /// Restore every already edited file if any file fails, defaults to `false`
+    pub atomic: Option<bool>,
+    /// Use the same crash-safe write as `WriteTagOptions.safeWrite`
+    pub safe_write: Option<bool>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct BatchEditProgress {
+    pub current: u32,
+    pub total: u32,
+    pub path: String,
+    /// Set if this file failed
+    pub error: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct BatchEditFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct BatchEditResult {
+    /// Pass to `revert_batch` to restore the previous values, `None` if nothing was changed
+    pub journal_id: Option<String>,
+    pub succeeded: u32,
+    pub failed: Vec<BatchEditFailure>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Text(&'static str),
+    Year,
+    Track,
+    TrackTotal,
+    Disc,
+    DiscTotal,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        let field = match name {
+            "title" => Self::Text("title"),
+            "artist" => Self::Text("artist"),
+            "album" => Self::Text("album"),
+            "albumArtist" => Self::Text("albumArtist"),
+            "genre" => Self::Text("genre"),
+            "comment" => Self::Text("comment"),
+            "composer" => Self::Text("composer"),
+            "lyric" => Self::Text("lyric"),
+            "year" => Self::Year,
+            "trackNumber" => Self::Track,
+            "trackTotal" => Self::TrackTotal,
+            "discNumber" => Self::Disc,
+            "discTotal" => Self::DiscTotal,
+            _ => return None,
+        };
+        Some(field)
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Text(name) => name,
+            Self::Year => "year",
+            Self::Track => "trackNumber",
+            Self::TrackTotal => "trackTotal",
+            Self::Disc => "discNumber",
+            Self::DiscTotal => "discTotal",
+        }
+    }
+
+    fn text_key(name: &str) -> ItemKey {
+        match name {
+            "title" => ItemKey::TrackTitle,
+            "artist" => ItemKey::TrackArtist,
+            "album" => ItemKey::AlbumTitle,
+            "albumArtist" => ItemKey::AlbumArtist,
+            "genre" => ItemKey::Genre,
+            "comment" => ItemKey::Comment,
+            "composer" => ItemKey::Composer,
+            _ => ItemKey::Lyrics,
+        }
+    }
+
+    // All values, so multi-value fields such as several artists survive a revert
+    fn get(self, tag: &Tag) -> Vec<String> {
+        let number = match self {
+            Self::Text(name) => {
+                return tag
+                    .get_strings(&Self::text_key(name))
+                    .map(ToString::to_string)
+                    .collect();
+            }
+            Self::Year => tag.year(),
+            Self::Track => tag.track(),
+            Self::TrackTotal => tag.track_total(),
+            Self::Disc => tag.disk(),
+            Self::DiscTotal => tag.disk_total(),
+        };
+        number.map(|n| n.to_string()).into_iter().collect()
+    }
+
+    fn set(self, tag: &mut Tag, values: &[String]) -> Result<()> {
+        if values.is_empty() {
+            self.clear(tag);
+            return Ok(());
+        }
+        let number = || {
+            values[0].trim().parse::<u32>().map_err(|_| {
+                Error::from_reason(format!("'{}' is not a valid {}", values[0], self.name()))
+            })
+        };
+        match self {
+            Self::Text(name) => {
+                let key = Self::text_key(name);
+                tag.remove_key(&key);
+                for value in values {
+                    tag.push(TagItem::new(key.clone(), ItemValue::Text(value.clone())));
+                }
+            }
+            Self::Year => tag.set_year(number()?),
+            Self::Track => tag.set_track(number()?),
+            Self::TrackTotal => tag.set_track_total(number()?),
+            Self::Disc => tag.set_disk(number()?),
+            Self::DiscTotal => tag.set_disk_total(number()?),
+        }
+        Ok(())
+    }
+
+    fn clear(self, tag: &mut Tag) {
+        match self {
+            Self::Text(name) => tag.remove_key(&Self::text_key(name)),
+            Self::Year => tag.remove_year(),
+            Self::Track => tag.remove_track(),
+            Self::TrackTotal => tag.remove_track_total(),
+            Self::Disc => tag.remove_disk(),
+            Self::DiscTotal => tag.remove_disk_total(),
+        }
+    }
+}
+
+enum Operation {
+    Set(String),
+    Clear,
+    Append(String),
+    RegexReplace(Regex, String),
+}
+
+struct CompiledEdit {
+    field: Field,
+    op: Operation,
+}
+
+impl CompiledEdit {
+    fn compile(edit: TagEdit) -> Result<Self> {
+        let field = Field::parse(&edit.field)
+            .ok_or_else(|| Error::from_reason(format!("Unknown field '{}'", edit.field)))?;
+        let value = || {
+            edit.value.clone().ok_or_else(|| {
+                Error::from_reason(format!("Edit of '{}' needs a value", edit.field))
+            })
+        };
+        let op = match edit.op {
+            TagEditOp::Set => Operation::Set(value()?),
+            TagEditOp::Clear => Operation::Clear,
+            TagEditOp::Append => Operation::Append(value()?),
+            TagEditOp::RegexReplace => {
+                let pattern = edit.pattern.as_deref().ok_or_else(|| {
+                    Error::from_reason(format!("Regex edit of '{}' needs a pattern", edit.field))
+                })?;
+                let regex = Regex::new(pattern).context(format!("Invalid pattern '{pattern}'"))?;
+                Operation::RegexReplace(regex, edit.value.unwrap_or_default())
+            }
+        };
+        Ok(Self { field, op })
+    }
+
+    fn apply(&self, tag: &mut Tag) -> Result<()> {
+        let current = self.field.get(tag);
+        let next: Vec<String> = match &self.op {
+            Operation::Set(value) => vec![value.clone()],
+            Operation::Clear => Vec::new(),
+            Operation::Append(value) if current.is_empty() => vec![value.clone()],
+            Operation::Append(value) => {
+                let mut next = current.clone();
+                if let Some(last) = next.last_mut() {
+                    last.push_str(value);
+                }
+                next
+            }
+            Operation::RegexReplace(regex, replacement) => current
+                .iter()
+                .map(|v| regex.replace_all(v, replacement.as_str()).into_owned())
+                .collect(),
+        };
+        let next: Vec<String> = next.into_iter().filter(|v| !v.is_empty()).collect();
+        if next != current {
+            self.field.set(tag, &next)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FieldSnapshot {
+    field: String,
+    values: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    path: String,
+    previous: Vec<FieldSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    created_at: u64,
+    safe_write: bool,
+    entries: Vec<JournalEntry>,
+}
+
+// Journals have to outlive a reboot, the temp dir may be cleaned in between
+fn app_data_dir() -> Option<PathBuf> {
+    let var = |name| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+    }
+}
+
+fn journal_dir(dir: Option<&str>) -> PathBuf {
+    dir.map_or_else(
+        || {
+            app_data_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join(APP_DIR_NAME)
+                .join(JOURNAL_DIR_NAME)
+        },
+        PathBuf::from,
+    )
+}
+
+fn write_journal(path: &Path, journal: &Journal) -> Result<()> {
+    let json = serde_json::to_vec(journal).context("Serialize journal failed")?;
+    std::fs::write(path, json).context("Write journal failed")
+}
+
+fn journal_path(dir: &Path, journal_id: &str) -> Result<PathBuf> {
+    if journal_id.is_empty()
+        || !journal_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(Error::from_reason(format!(
+            "Invalid journal id '{journal_id}'"
+        )));
+    }
+    Ok(dir.join(format!("{journal_id}.json")))
+}
+
+// The values `edits` are about to overwrite, these go into the journal before anything is saved
+fn snapshot_file(path: &Path, edits: &[CompiledEdit]) -> Result<JournalEntry> {
+    let tagged_file = read_tagged_file(path).context("Read tag failed")?;
+    let previous = edits
+        .iter()
+        .map(|edit| FieldSnapshot {
+            field: edit.field.name().to_string(),
+            values: super::main_tag(&tagged_file)
+                .map(|tag| edit.field.get(tag))
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(JournalEntry {
+        path: path.to_string_lossy().into_owned(),
+        previous,
+    })
+}
+
+fn edit_file(path: &Path, edits: &[CompiledEdit], safe_write: bool) -> Result<()> {
+    save_tag(path, safe_write, |tag| {
+        edits.iter().try_for_each(|edit| edit.apply(tag))
+    })
+}
+
+// Restores `edited` and returns the entries that could not be restored
+fn roll_back(
+    edited: Vec<JournalEntry>,
+    safe_write: bool,
+    failed: &mut Vec<BatchEditFailure>,
+) -> Vec<JournalEntry> {
+    let mut unrestored = Vec::new();
+    for entry in edited {
+        if let Err(e) = restore_entry(&entry, safe_write) {
+            eprintln!("回滚 {} 失败: {e}", entry.path);
+            failed.push(BatchEditFailure {
+                path: entry.path.clone(),
+                error: format!("Rollback failed: {}", e.reason),
+            });
+            unrestored.push(entry);
+        }
+    }
+    unrestored
+}
+
+// Rewrites the journal with the entries left to revert, or removes it if there are none.
+// Returns whether it was kept.
+fn finish_journal(path: &Path, journal: &Journal) -> Result<bool> {
+    if journal.entries.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path).context("Remove journal failed")?;
+        }
+        return Ok(false);
+    }
+    write_journal(path, journal)?;
+    Ok(true)
+}
+
+fn restore_entry(entry: &JournalEntry, safe_write: bool) -> Result<()> {
+    save_tag(Path::new(&entry.path), safe_write, |tag| {
+        // Reverse order so a field edited twice ends up at its oldest value
+        for snapshot in entry.previous.iter().rev() {
+            let field = Field::parse(&snapshot.field).ok_or_else(|| {
+                Error::from_reason(format!("Unknown field '{}' in journal", snapshot.field))
+            })?;
+            field.set(tag, &snapshot.values)?;
+        }
+        Ok(())
+    })
+}
+
+/// Applies field edits to many files in parallel and records the previous values in an
+/// undo journal for `revert_batch`
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn batch_edit_tags(
+    paths: Vec<String>,
+    edits: Vec<TagEdit>,
+    on_progress: Option<ThreadsafeFunction<BatchEditProgress>>,
+    options: Option<BatchEditOptions>,
+) -> Result<BatchEditResult> {
+    let options = options.unwrap_or_default();
+    let edits = edits
+        .into_iter()
+        .map(CompiledEdit::compile)
+        .collect::<Result<Vec<_>>>()?;
+
+    tokio::task::spawn_blocking(move || {
+        let safe_write = options.safe_write.unwrap_or(false);
+        // The same file twice would be edited concurrently and journalled with its edited values
+        let mut seen = HashSet::new();
+        let paths: Vec<String> = paths
+            .into_iter()
+            .filter(|path| seen.insert(path.clone()))
+            .collect();
+        let total = paths.len() as u32;
+        let current = AtomicU32::new(0);
+        let report = |path: &str, error: Option<&Error>| {
+            if let Some(callback) = &on_progress {
+                callback.call(
+                    Ok(BatchEditProgress {
+                        current: current.fetch_add(1, Ordering::Relaxed) + 1,
+                        total,
+                        path: path.to_string(),
+                        error: error.map(|e| e.reason.clone()),
+                    }),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        };
+
+        let snapshots: Vec<(String, Result<JournalEntry>)> = paths
+            .into_par_iter()
+            .map(|path| {
+                let result = snapshot_file(Path::new(&path), &edits);
+                (path, result)
+            })
+            .collect();
+
+        let mut entries = Vec::new();
+        let mut failed = Vec::new();
+        for (path, result) in snapshots {
+            match result {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    report(&path, Some(&e));
+                    failed.push(BatchEditFailure {
+                        path,
+                        error: e.reason.clone(),
+                    });
+                }
+            }
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut journal = Journal {
+            created_at: now.as_millis() as u64,
+            safe_write,
+            entries,
+        };
+        let id = format!("{:x}", now.as_nanos());
+        let dir = journal_dir(options.journal_dir.as_deref());
+        let path = journal_path(&dir, &id)?;
+        // Written before the first file is touched so a crash midway can still be reverted
+        if !journal.entries.is_empty() {
+            std::fs::create_dir_all(&dir).context("Create journal dir failed")?;
+            write_journal(&path, &journal)?;
+        }
+
+        let results: Vec<(JournalEntry, Result<()>)> = journal
+            .entries
+            .into_par_iter()
+            .map(|entry| {
+                let result = edit_file(Path::new(&entry.path), &edits, safe_write);
+                report(&entry.path, result.as_ref().err());
+                (entry, result)
+            })
+            .collect();
+
+        let mut edited = Vec::new();
+        for (entry, result) in results {
+            match result {
+                Ok(()) => edited.push(entry),
+                Err(e) => failed.push(BatchEditFailure {
+                    path: entry.path,
+                    error: e.reason.clone(),
+                }),
+            }
+        }
+
+        let (succeeded, remaining) = if !failed.is_empty() && options.atomic.unwrap_or(false) {
+            // Files that could not be rolled back stay in the journal for `revert_batch`
+            (0, roll_back(edited, safe_write, &mut failed))
+        } else {
+            (edited.len() as u32, edited)
+        };
+
+        journal.entries = remaining;
+        Ok(BatchEditResult {
+            journal_id: finish_journal(&path, &journal)?.then_some(id),
+            succeeded,
+            failed,
+        })
+    })
+    .await
+    .context("Batch edit task panicked or cancelled")?
+}
+
+/// Restores the values recorded by `batch_edit_tags`. The journal is removed once every
+/// file was restored, failed files are returned and stay in the journal for another try.
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn revert_batch(
+    journal_id: String,
+    journal_dir: Option<String>,
+) -> Result<Vec<BatchEditFailure>> {
+    tokio::task::spawn_blocking(move || {
+        let dir = self::journal_dir(journal_dir.as_deref());
+        let path = journal_path(&dir, &journal_id)?;
+        let data = std::fs::read(&path).context(format!("Journal '{journal_id}' not found"))?;
+        let mut journal: Journal = serde_json::from_slice(&data).context("Parse journal failed")?;
+
+        let results: Vec<(JournalEntry, Result<()>)> = journal
+            .entries
+            .into_par_iter()
+            .map(|entry| {
+                let result = restore_entry(&entry, journal.safe_write);
+                (entry, result)
+            })
+            .collect();
+
+        let mut remaining = Vec::new();
+        let mut failed = Vec::new();
+        for (entry, result) in results {
+            if let Err(e) = result {
+                failed.push(BatchEditFailure {
+                    path: entry.path.clone(),
+                    error: e.reason.clone(),
+                });
+                remaining.push(entry);
+            }
+        }
+
+        if remaining.is_empty() {
+            std::fs::remove_file(&path).context("Remove journal failed")?;
+        } else {
+            journal.entries = remaining;
+            write_journal(&path, &journal)?;
+        }
+        Ok(failed)
+    })
+    .await
+    .context("Revert task panicked or cancelled")?
+}
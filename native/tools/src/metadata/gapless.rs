@@ -0,0 +1,308 @@
+// Encoder delay and padding, so players can cut lossy tracks at their exact original length
+
+use lofty::file::{FileType, TaggedFile};
+use lofty::prelude::*;
+use lofty::tag::ItemKey;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::{main_tag, read_tagged_file, MP4_FREEFORM_PREFIX};
+use crate::utils::{id3v2_len, Context};
+
+// The Xing/Info frame is the first frame, right after any ID3v2 tag
+const MP3_PROBE_LEN: u64 = 8192;
+// An Ogg page is at most 65307 bytes, so the last one starts within this distance of the end
+const OGG_TAIL_LEN: u64 = 65536;
+const OPUS_SAMPLE_RATE: u32 = 48000;
+const ITUNSMPB_NAME: &str = "iTunSMPB";
+
+#[napi(string_enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GaplessSource {
+    /// LAME extension of the MP3 Xing/Info header, also written by `FFmpeg`
+    Lame,
+    /// `iTunSMPB` atom written by iTunes and most AAC encoders
+    ITunSmpb,
+    /// Opus pre-skip and the last granule position
+    Opus,
+    /// Last granule position of an Ogg Vorbis stream
+    Vorbis,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct GaplessInfo {
+    pub source: GaplessSource,
+    /// Rate the sample counts are in, always 48000 for Opus
+    pub sample_rate: u32,
+    /// Priming samples to drop from the start. For MP3 this excludes the 529 samples of
+    /// decoder delay most decoders add on top
+    pub encoder_delay: u32,
+    /// Samples to drop from the end, `None` for Ogg where the last granule position
+    /// already ends the stream
+    pub padding: Option<u32>,
+    /// Samples per channel left after trimming
+    pub total_samples: f64,
+    /// `total_samples` in milliseconds, like `MusicTrack.duration`
+    pub duration: f64,
+}
+
+impl GaplessInfo {
+    fn new(
+        source: GaplessSource,
+        sample_rate: u32,
+        encoder_delay: u32,
+        padding: Option<u32>,
+        total_samples: u64,
+    ) -> Option<Self> {
+        (sample_rate > 0 && total_samples > 0).then(|| Self {
+            source,
+            sample_rate,
+            encoder_delay,
+            padding,
+            total_samples: total_samples as f64,
+            duration: total_samples as f64 * 1000.0 / f64::from(sample_rate),
+        })
+    }
+}
+
+/// Reads encoder delay, padding and the exact sample count of MP3 (LAME header), MP4/AAC
+/// (`iTunSMPB`), Opus and Vorbis files. Resolves to `null` if the file carries none.
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn read_gapless_info(file_path: String) -> Result<Option<GaplessInfo>> {
+    tokio::task::spawn_blocking(move || {
+        let path = Path::new(&file_path);
+        let tagged_file = read_tagged_file(path).context("Read file failed")?;
+        Ok(read_gapless(path, &tagged_file))
+    })
+    .await
+    .context("Gapless task panicked or cancelled")?
+}
+
+pub fn read_gapless(path: &Path, tagged_file: &TaggedFile) -> Option<GaplessInfo> {
+    match tagged_file.file_type() {
+        FileType::Mpeg => read_lame(&mut File::open(path).ok()?),
+        FileType::Mp4 => read_itunsmpb(tagged_file),
+        FileType::Opus => read_ogg(&mut File::open(path).ok()?, GaplessSource::Opus),
+        FileType::Vorbis => read_ogg(&mut File::open(path).ok()?, GaplessSource::Vorbis),
+        _ => None,
+    }
+}
+
+fn read_head(file: &mut File, start: u64, len: u64) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut buf = Vec::new();
+    file.take(len).read_to_end(&mut buf).ok()?;
+    Some(buf)
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn le_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+// --- MP3 ---
+
+struct Mp3Frame {
+    mpeg1: bool,
+    sample_rate: u32,
+    // Offset of the Xing/Info tag from the frame start
+    xing_offset: usize,
+}
+
+impl Mp3Frame {
+    // Layer III only, LAME headers are not written to other layers
+    fn parse(data: &[u8]) -> Option<Self> {
+        let &[b0, b1, b2, b3] = data.get(..4)? else {
+            return None;
+        };
+        let version = (b1 >> 3) & 3;
+        let layer = (b1 >> 1) & 3;
+        let bitrate = b2 >> 4;
+        let rate_index = (b2 >> 2) & 3;
+        if b0 != 0xFF || b1 & 0xE0 != 0xE0 || version == 1 || layer != 1 {
+            return None;
+        }
+        if bitrate == 0 || bitrate == 15 || rate_index == 3 {
+            return None;
+        }
+
+        let mpeg1 = version == 3;
+        let mono = b3 >> 6 == 3;
+        let side_info = match (mpeg1, mono) {
+            (true, false) => 32,
+            (false, true) => 9,
+            _ => 17,
+        };
+        let crc = if b1 & 1 == 0 { 2 } else { 0 };
+        // MPEG 2 halves the rate, MPEG 2.5 quarters it
+        let shift = match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+        Some(Self {
+            mpeg1,
+            sample_rate: [44100, 48000, 32000][usize::from(rate_index)] >> shift,
+            xing_offset: 4 + crc + side_info,
+        })
+    }
+
+    const fn samples_per_frame(&self) -> u64 {
+        if self.mpeg1 {
+            1152
+        } else {
+            576
+        }
+    }
+}
+
+fn read_lame(file: &mut File) -> Option<GaplessInfo> {
+    let head = read_head(file, 0, 10)?;
+    let start = id3v2_len(&head).unwrap_or(0);
+    parse_lame(&read_head(file, start, MP3_PROBE_LEN)?)
+}
+
+fn parse_lame(data: &[u8]) -> Option<GaplessInfo> {
+    let (pos, frame) = (0..data.len()).find_map(|i| Some((i, Mp3Frame::parse(&data[i..])?)))?;
+    let xing = pos + frame.xing_offset;
+    let tag = data.get(xing..xing + 4)?;
+    if tag != b"Xing" && tag != b"Info" {
+        return None;
+    }
+
+    // Optional fields, present if their flag is set: frames, bytes, TOC, quality
+    let flags = be_u32(data, xing + 4)?;
+    if flags & 1 == 0 {
+        return None;
+    }
+    let frames = be_u32(data, xing + 8)?;
+    let mut lame = xing + 12;
+    for (flag, len) in [(2, 4), (4, 100), (8, 4)] {
+        if flags & flag != 0 {
+            lame += len;
+        }
+    }
+
+    // Encoder version (e.g. `LAME3.100`, `Lavc60.3`), then 12-bit delay and 12-bit padding
+    // at offset 21
+    let lame = data.get(lame..lame + 24)?;
+    if !lame[..4].iter().all(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+    let delay = (u32::from(lame[21]) << 4) | u32::from(lame[22] >> 4);
+    let padding = (u32::from(lame[22] & 0x0F) << 8) | u32::from(lame[23]);
+    let total = (u64::from(frames) * frame.samples_per_frame())
+        .saturating_sub(u64::from(delay) + u64::from(padding));
+    GaplessInfo::new(
+        GaplessSource::Lame,
+        frame.sample_rate,
+        delay,
+        Some(padding),
+        total,
+    )
+}
+
+// --- MP4 ---
+
+fn read_itunsmpb(tagged_file: &TaggedFile) -> Option<GaplessInfo> {
+    let name = format!("{MP4_FREEFORM_PREFIX}{ITUNSMPB_NAME}");
+    let value = main_tag(tagged_file)?
+        .items()
+        .find_map(|item| match item.key() {
+            ItemKey::Unknown(key) if key.eq_ignore_ascii_case(&name) => item.value().text(),
+            _ => None,
+        })?;
+    let sample_rate = tagged_file.properties().sample_rate()?;
+    let (delay, padding, total) = parse_itunsmpb(value)?;
+    GaplessInfo::new(
+        GaplessSource::ITunSmpb,
+        sample_rate,
+        delay,
+        Some(padding),
+        total,
+    )
+}
+
+// " 00000000 00000840 000001C4 0000000000A0B7FC 00000000 ...", hex fields of which the
+// second to fourth are delay, padding and the sample count after trimming
+fn parse_itunsmpb(value: &str) -> Option<(u32, u32, u64)> {
+    let mut fields = value.split_whitespace().skip(1);
+    let delay = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let padding = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let total = u64::from_str_radix(fields.next()?, 16).ok()?;
+    Some((delay, padding, total))
+}
+
+// --- Ogg ---
+
+struct OggPage<'a> {
+    granule: u64,
+    serial: u32,
+    // Payload of the page, the first packet starts at its beginning
+    body: &'a [u8],
+}
+
+impl<'a> OggPage<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        if !data.starts_with(b"OggS") || *data.get(4)? != 0 {
+            return None;
+        }
+        let segments = usize::from(*data.get(26)?);
+        let body_len: usize = data
+            .get(27..27 + segments)?
+            .iter()
+            .map(|&len| usize::from(len))
+            .sum();
+        let body_start = 27 + segments;
+        Some(Self {
+            granule: le_u64(data, 6)?,
+            serial: u32::from_le_bytes(data.get(14..18)?.try_into().ok()?),
+            body: data.get(body_start..(body_start + body_len).min(data.len()))?,
+        })
+    }
+}
+
+fn read_ogg(file: &mut File, source: GaplessSource) -> Option<GaplessInfo> {
+    let head = read_head(file, 0, OGG_TAIL_LEN)?;
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+    let tail_start = len.saturating_sub(OGG_TAIL_LEN);
+    let tail = read_head(file, tail_start, OGG_TAIL_LEN)?;
+    parse_ogg(&head, &tail, source)
+}
+
+fn parse_ogg(head: &[u8], tail: &[u8], source: GaplessSource) -> Option<GaplessInfo> {
+    let first = OggPage::parse(head)?;
+    let (sample_rate, pre_skip) = match source {
+        GaplessSource::Opus if first.body.starts_with(b"OpusHead") => {
+            let pre_skip = u16::from_le_bytes(first.body.get(10..12)?.try_into().ok()?);
+            (OPUS_SAMPLE_RATE, u32::from(pre_skip))
+        }
+        GaplessSource::Vorbis if first.body.starts_with(b"\x01vorbis") => {
+            let rate = u32::from_le_bytes(first.body.get(12..16)?.try_into().ok()?);
+            (rate, 0)
+        }
+        _ => return None,
+    };
+
+    // The last page of this stream that ends a packet, -1 means none does
+    let last_granule = (0..tail.len().saturating_sub(27))
+        .rev()
+        .filter_map(|i| OggPage::parse(&tail[i..]))
+        .find(|page| page.serial == first.serial && page.granule != u64::MAX)?
+        .granule;
+    GaplessInfo::new(
+        source,
+        sample_rate,
+        pre_skip,
+        None,
+        last_granule.saturating_sub(u64::from(pre_skip)),
+    )
+}
@@ -0,0 +1,85 @@
+//! Minimal LRC parsing, used to convert between LRC text and ID3 SYLT frames
+
+// Parses `mm:ss`, `mm:ss.xx`, `mm:ss.xxx` and `mm:ss:xx` into milliseconds
+fn parse_timestamp(tag: &str) -> Option<u32> {
+    let (min, rest) = tag.split_once(':')?;
+    let (sec, frac) = match rest.split_once(['.', ':']) {
+        Some((sec, frac)) => (sec, frac),
+        None => (rest, ""),
+    };
+    let min: u32 = min.trim().parse().ok()?;
+    let sec: u32 = sec.trim().parse().ok()?;
+    let frac_ms = match frac.len() {
+        0 => 0,
+        1 => frac.parse::<u32>().ok()? * 100,
+        2 => frac.parse::<u32>().ok()? * 10,
+        _ => frac.get(..3)?.parse().ok()?,
+    };
+    // Absurd timestamps would overflow, the line is dropped instead
+    min.checked_mul(60_000)?
+        .checked_add(sec.checked_mul(1000)?)?
+        .checked_add(frac_ms)
+}
+
+/// Returns `(time_ms, text)` pairs sorted by time
+pub fn parse_lrc(text: &str) -> Vec<(u32, String)> {
+    let mut offset: i64 = 0;
+    let mut lines = Vec::new();
+
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut stamps = Vec::new();
+        while let Some(inner) = rest.strip_prefix('[') {
+            let Some(close) = inner.find(']') else {
+                break;
+            };
+            let tag = &inner[..close];
+            if let Some(ms) = parse_timestamp(tag) {
+                stamps.push(ms);
+            } else if let Some(value) = tag.strip_prefix("offset:") {
+                offset = value.trim().parse().unwrap_or(0);
+            }
+            rest = &inner[close + 1..];
+        }
+
+        for ms in stamps {
+            // A positive offset shows lyrics earlier
+            let time = u32::try_from((i64::from(ms) - offset).max(0)).unwrap_or(u32::MAX);
+            lines.push((time, rest.trim().to_string()));
+        }
+    }
+
+    lines.sort_by_key(|(time, _)| *time);
+    lines
+}
+
+pub fn is_lrc(text: &str) -> bool {
+    text.lines().take(50).any(|line| {
+        line.trim()
+            .strip_prefix('[')
+            .and_then(|inner| inner.split_once(']'))
+            .and_then(|(tag, _)| parse_timestamp(tag))
+            .is_some()
+    })
+}
+
+/// Lyric text without timestamps, for fields that cannot carry them
+pub fn strip_lrc(text: &str) -> String {
+    parse_lrc(text)
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Formats `(time_ms, text)` pairs back into LRC
+pub fn to_lrc(lines: &[(u32, String)]) -> String {
+    lines
+        .iter()
+        .map(|(ms, text)| {
+            let (min, sec, centis) = (ms / 60_000, ms / 1000 % 60, ms % 1000 / 10);
+            format!("[{min:02}:{sec:02}.{centis:02}]{text}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
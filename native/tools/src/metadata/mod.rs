@@ -0,0 +1,512 @@
+use lofty::config::WriteOptions;
+use lofty::file::TaggedFile;
+use lofty::id3::v2::{
+    BinaryFrame, Frame, FrameId, Id3v2Tag, SyncTextContentType, SynchronizedTextFrame,
+    TimestampFormat, UnsynchronizedTextFrame,
+};
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagItem, TagType};
+use lofty::TextEncoding;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::utils::Context;
+
+mod batch;
+mod gapless;
+mod lrc;
+mod read;
+mod safe;
+
+pub use batch::{
+    batch_edit_tags, revert_batch, BatchEditFailure, BatchEditOptions, BatchEditProgress,
+    BatchEditResult, TagEdit, TagEditOp,
+};
+pub use gapless::{read_gapless, read_gapless_info, GaplessInfo, GaplessSource};
+pub use read::main_tag;
+use read::read_id3v2;
+pub use read::{read_music_metadata, AudioProperties, EmbeddedPicture, MusicFileMetadata};
+
+const JPEG_MAGIC: &[u8] = &[0xFF, 0xD8, 0xFF];
+const PNG_MAGIC: &[u8] = &[0x89, 0x50, 0x4E, 0x47];
+
+// Custom field holding translated lyrics (Vorbis/APE key, TXXX/USLT description, MP4 freeform name)
+const TRANSLATED_LYRICS_KEY: &str = "TRANSLATED_LYRICS";
+const MP4_FREEFORM_PREFIX: &str = "----:com.apple.iTunes:";
+// Source ID field names end in this, it is what tells them apart from other custom fields
+const SOURCE_ID_SUFFIX: &str = "_ID";
+const ID3_LYRICS_LANGUAGE: [u8; 3] = *b"XXX";
+
+#[napi(object)]
+#[derive(Debug, Default)]
+pub struct SongMetadata {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub cover_url: Option<String>,
+    pub lyric: Option<String>,
+    pub description: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    /// Individual artists, written as a multi-value field. `artist` stays the display string
+    pub artists: Option<Vec<String>>,
+    /// LRC formatted lyric, written as ID3 SYLT, Vorbis `LYRICS` or MP4 `©lyr`
+    pub synced_lyric: Option<String>,
+    /// Translated lyric, LRC or plain text
+    pub translated_lyric: Option<String>,
+    /// Source IDs written as custom fields (TXXX in ID3), e.g. `{ "NCM_ID": "1234" }`. Names are
+    /// upper-cased and get an `_ID` suffix if they lack one
+    pub source_ids: Option<HashMap<String, String>>,
+    pub musicbrainz_recording_id: Option<String>,
+    pub musicbrainz_release_track_id: Option<String>,
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+    pub musicbrainz_artist_id: Option<String>,
+    pub musicbrainz_album_artist_id: Option<String>,
+    /// Gain in dB
+    pub replaygain_track_gain: Option<f64>,
+    /// Linear sample peak, 1.0 is full scale
+    pub replaygain_track_peak: Option<f64>,
+    pub replaygain_album_gain: Option<f64>,
+    pub replaygain_album_peak: Option<f64>,
+}
+
+#[napi(object)]
+#[derive(Debug, Clone, Default)]
+pub struct WriteTagOptions {
+    /// Downscale the cover so neither side exceeds this many pixels before embedding
+    pub cover_max_size: Option<u32>,
+    /// Write to a temporary copy, verify it still decodes and then atomically replace the
+    /// original, defaults to `false`
+    pub safe_write: Option<bool>,
+    /// With `safe_write`, keep the original file as `<name>.bak`
+    pub keep_backup: Option<bool>,
+}
+
+#[napi]
+#[allow(
+    clippy::trailing_empty_array,
+    clippy::missing_errors_doc,
+    clippy::option_if_let_else
+)]
+pub async fn write_music_metadata(
+    file_path: String,
+    metadata: SongMetadata,
+    cover_path: Option<String>,
+    options: Option<WriteTagOptions>,
+) -> Result<()> {
+    let cover_data = if let Some(path) = cover_path {
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Some(bytes::Bytes::from(bytes)),
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+    let options = options.unwrap_or_default();
+
+    tokio::task::spawn_blocking(move || write_metadata(&file_path, metadata, cover_data, &options))
+        .await
+        .context("Metadata task panicked or cancelled")??;
+
+    Ok(())
+}
+
+fn get_or_create_tag(tagged_file: &mut lofty::file::TaggedFile) -> Result<&mut Tag> {
+    if tagged_file.primary_tag_mut().is_some() {
+        return Ok(tagged_file.primary_tag_mut().unwrap());
+    }
+
+    if tagged_file.first_tag_mut().is_some() {
+        return Ok(tagged_file.first_tag_mut().unwrap());
+    }
+
+    let tag_type = tagged_file.primary_tag_type();
+    tagged_file.insert_tag(Tag::new(tag_type));
+
+    tagged_file
+        .primary_tag_mut()
+        .ok_or_else(|| Error::from_reason("Create tag failed"))
+}
+
+// Key for a custom field in formats that support arbitrary keys through the generic tag
+fn custom_key(tag_type: TagType, name: &str) -> Option<ItemKey> {
+    match tag_type {
+        TagType::VorbisComments | TagType::Ape => Some(ItemKey::Unknown(name.to_uppercase())),
+        TagType::Mp4Ilst => Some(ItemKey::Unknown(format!(
+            "{MP4_FREEFORM_PREFIX}{}",
+            name.to_uppercase()
+        ))),
+        _ => None,
+    }
+}
+
+// Field name a source ID is stored under
+fn source_id_name(name: &str) -> String {
+    let name = name.to_uppercase();
+    if name.ends_with(SOURCE_ID_SUFFIX) {
+        name
+    } else {
+        format!("{name}{SOURCE_ID_SUFFIX}")
+    }
+}
+
+fn set_text(tag: &mut Tag, key: ItemKey, value: Option<String>) {
+    if let Some(value) = value.filter(|v| !v.is_empty()) {
+        tag.insert_text(key, value);
+    }
+}
+
+fn prepare_cover(data: &[u8], max_size: Option<u32>) -> Picture {
+    let mut data = data.to_vec();
+    let mut mime_type = if data.starts_with(JPEG_MAGIC) {
+        MimeType::Jpeg
+    } else if data.starts_with(PNG_MAGIC) {
+        MimeType::Png
+    } else {
+        MimeType::Jpeg
+    };
+
+    if let Some(max_size) = max_size.filter(|&s| s > 0) {
+        match downscale_cover(&data, max_size) {
+            Ok(Some(jpeg)) => {
+                data = jpeg;
+                mime_type = MimeType::Jpeg;
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("缩小封面失败，使用原图: {e}"),
+        }
+    }
+
+    Picture::new_unchecked(PictureType::CoverFront, Some(mime_type), None, data)
+}
+
+// Returns `None` if the cover already fits
+fn downscale_cover(data: &[u8], max_size: u32) -> image::ImageResult<Option<Vec<u8>>> {
+    let img = image::load_from_memory(data)?;
+    if img.width() <= max_size && img.height() <= max_size {
+        return Ok(None);
+    }
+    let resized = img.resize(max_size, max_size, image::imageops::FilterType::Lanczos3);
+    let mut buf = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(resized.to_rgb8())
+        .write_to(&mut buf, image::ImageFormat::Jpeg)?;
+    Ok(Some(buf.into_inner()))
+}
+
+fn apply_common_fields(tag: &mut Tag, meta: &mut SongMetadata) {
+    tag.set_title(std::mem::take(&mut meta.title));
+    tag.set_album(std::mem::take(&mut meta.album));
+
+    // Players that only know the single artist field still get the display string
+    let artist = std::mem::take(&mut meta.artist);
+    // `None` leaves the existing `ARTISTS` alone
+    let artists: Option<Vec<String>> = meta
+        .artists
+        .take()
+        .map(|artists| artists.into_iter().filter(|a| !a.is_empty()).collect());
+    match artists {
+        Some(artists) if artists.len() > 1 => {
+            tag.remove_key(&ItemKey::TrackArtists);
+            tag.set_artist(if artist.is_empty() {
+                artists.join("/")
+            } else {
+                artist
+            });
+            for artist in artists {
+                tag.push(TagItem::new(ItemKey::TrackArtists, ItemValue::Text(artist)));
+            }
+        }
+        Some(_) => {
+            tag.remove_key(&ItemKey::TrackArtists);
+            tag.set_artist(artist);
+        }
+        None => tag.set_artist(artist),
+    }
+
+    if let Some(album_artist) = meta.album_artist.take() {
+        tag.insert_text(ItemKey::AlbumArtist, album_artist);
+    }
+
+    if let Some(genre) = meta.genre.take() {
+        tag.set_genre(genre);
+    }
+
+    if let Some(year) = meta.year {
+        tag.set_year(year);
+    }
+
+    if let Some(track) = meta.track_number {
+        tag.set_track(track);
+    }
+
+    if let Some(disc) = meta.disc_number {
+        tag.set_disk(disc);
+    }
+
+    if let Some(desc) = meta.description.take() {
+        tag.set_comment(desc);
+    }
+
+    set_text(
+        tag,
+        ItemKey::MusicBrainzRecordingId,
+        meta.musicbrainz_recording_id.take(),
+    );
+    set_text(
+        tag,
+        ItemKey::MusicBrainzTrackId,
+        meta.musicbrainz_release_track_id.take(),
+    );
+    set_text(
+        tag,
+        ItemKey::MusicBrainzReleaseId,
+        meta.musicbrainz_release_id.take(),
+    );
+    set_text(
+        tag,
+        ItemKey::MusicBrainzReleaseGroupId,
+        meta.musicbrainz_release_group_id.take(),
+    );
+    set_text(
+        tag,
+        ItemKey::MusicBrainzArtistId,
+        meta.musicbrainz_artist_id.take(),
+    );
+    set_text(
+        tag,
+        ItemKey::MusicBrainzReleaseArtistId,
+        meta.musicbrainz_album_artist_id.take(),
+    );
+
+    set_replaygain(tag, meta);
+}
+
+fn set_replaygain(tag: &mut Tag, meta: &SongMetadata) {
+    let gain = |g: Option<f64>| g.map(|g| format!("{g:.2} dB"));
+    let peak = |p: Option<f64>| p.map(|p| format!("{p:.6}"));
+    set_text(
+        tag,
+        ItemKey::ReplayGainTrackGain,
+        gain(meta.replaygain_track_gain),
+    );
+    set_text(
+        tag,
+        ItemKey::ReplayGainTrackPeak,
+        peak(meta.replaygain_track_peak),
+    );
+    set_text(
+        tag,
+        ItemKey::ReplayGainAlbumGain,
+        gain(meta.replaygain_album_gain),
+    );
+    set_text(
+        tag,
+        ItemKey::ReplayGainAlbumPeak,
+        peak(meta.replaygain_album_peak),
+    );
+}
+
+// Frames of the same kind replace each other, lyrics and user text also need the same description
+fn frame_key(frame: &Frame<'_>) -> String {
+    match frame {
+        Frame::UnsynchronizedText(f) => format!("USLT:{}", f.description),
+        Frame::UserText(f) => format!("TXXX:{}", f.description),
+        Frame::Comment(f) => format!("COMM:{}", f.description),
+        other => other.id_str().to_string(),
+    }
+}
+
+// Frames the generic tag cannot express: SYLT, described USLT and TXXX. Other frames it drops
+// (GEOB, PRIV, ...) are carried over from the file's own tag.
+fn build_id3v2(
+    tag: Tag,
+    existing: Option<Id3v2Tag>,
+    meta: &mut SongMetadata,
+    synced: Option<&str>,
+) -> Result<Id3v2Tag> {
+    let mut id3v2 = Id3v2Tag::from(tag);
+
+    if let Some(lrc_text) = synced {
+        let content = lrc::parse_lrc(lrc_text);
+        if !content.is_empty() {
+            let sylt = SynchronizedTextFrame::new(
+                TextEncoding::UTF8,
+                ID3_LYRICS_LANGUAGE,
+                TimestampFormat::MS,
+                SyncTextContentType::Lyrics,
+                None,
+                content,
+            );
+            let bytes = sylt.as_bytes().context("Encode SYLT frame failed")?;
+            id3v2.insert(Frame::Binary(BinaryFrame::new(
+                FrameId::Valid(Cow::Borrowed("SYLT")),
+                bytes,
+            )));
+        }
+    }
+
+    if let Some(translated) = meta.translated_lyric.take().filter(|t| !t.is_empty()) {
+        id3v2.insert(Frame::UnsynchronizedText(UnsynchronizedTextFrame::new(
+            TextEncoding::UTF8,
+            ID3_LYRICS_LANGUAGE,
+            TRANSLATED_LYRICS_KEY.to_string(),
+            translated,
+        )));
+    }
+
+    for (name, value) in meta.source_ids.take().unwrap_or_default() {
+        id3v2.insert_user_text(source_id_name(&name), value);
+    }
+
+    if let Some(existing) = existing {
+        // What survives a trip through the generic tag is already in `id3v2` or was removed
+        let round_trip = Id3v2Tag::from(Tag::from(existing.clone()));
+        let written: HashSet<String> = (&round_trip)
+            .into_iter()
+            .chain(&id3v2)
+            .map(frame_key)
+            .collect();
+        for frame in existing {
+            if !written.contains(&frame_key(&frame)) {
+                id3v2.insert(frame);
+            }
+        }
+    }
+
+    Ok(id3v2)
+}
+
+/// Reads a file, detecting its format from the content rather than the extension so
+/// mislabeled downloads (FLAC saved as `.mp3`) still get the right tag type
+#[allow(clippy::missing_errors_doc)]
+pub fn read_tagged_file(path: &Path) -> lofty::error::Result<TaggedFile> {
+    Probe::open(path)?.guess_file_type()?.read()
+}
+
+// Saves the main tag. ID3v2 goes through `build_id3v2` so frames the generic tag cannot hold
+// (SYLT, described USLT, TXXX, ...) are not lost.
+fn save_main_tag(
+    path: &Path,
+    tagged_file: &TaggedFile,
+    meta: &mut SongMetadata,
+    synced: Option<&str>,
+) -> Result<()> {
+    match main_tag(tagged_file).filter(|t| t.tag_type() == TagType::Id3v2) {
+        Some(tag) => {
+            let existing = read_id3v2(path, tagged_file.file_type())?;
+            build_id3v2(tag.clone(), existing, meta, synced)?
+                .save_to_path(path, WriteOptions::default())
+                .context("Save tag failed")
+        }
+        None => tagged_file
+            .save_to_path(path, WriteOptions::default())
+            .context("Save tag failed"),
+    }
+}
+
+// Edits the main tag in place, keeping everything not touched by `edit`
+fn save_tag(path: &Path, safe_write: bool, edit: impl Fn(&mut Tag) -> Result<()>) -> Result<()> {
+    let write = |target: &Path| {
+        let mut tagged_file = read_tagged_file(target).context("Read tag failed")?;
+        edit(get_or_create_tag(&mut tagged_file)?)?;
+        save_main_tag(target, &tagged_file, &mut SongMetadata::default(), None)
+    };
+    if safe_write {
+        safe::write_safely(path, false, write)
+    } else {
+        write(path)
+    }
+}
+
+/// Writes only the `replaygain_*` fields of `meta`, leaving the rest of the tag alone
+#[allow(clippy::missing_errors_doc)]
+pub fn write_replaygain(path: &Path, meta: &SongMetadata, safe_write: bool) -> Result<()> {
+    save_tag(path, safe_write, |tag| {
+        set_replaygain(tag, meta);
+        Ok(())
+    })
+}
+
+#[allow(clippy::missing_errors_doc)]
+pub fn write_metadata(
+    path: &str,
+    meta: SongMetadata,
+    cover_data: Option<bytes::Bytes>,
+    options: &WriteTagOptions,
+) -> Result<()> {
+    let path_obj = Path::new(path);
+    if options.safe_write.unwrap_or(false) {
+        safe::write_safely(path_obj, options.keep_backup.unwrap_or(false), |target| {
+            write_tags(target, meta, cover_data, options)
+        })
+    } else {
+        write_tags(path_obj, meta, cover_data, options)
+    }
+}
+
+fn write_tags(
+    path_obj: &Path,
+    mut meta: SongMetadata,
+    cover_data: Option<bytes::Bytes>,
+    options: &WriteTagOptions,
+) -> Result<()> {
+    let mut tagged_file = read_tagged_file(path_obj).context("Read tag failed")?;
+    let tag = get_or_create_tag(&mut tagged_file)?;
+    let tag_type = tag.tag_type();
+
+    apply_common_fields(tag, &mut meta);
+
+    // Synced lyrics may also arrive as LRC in the plain `lyric` field
+    let synced = meta
+        .synced_lyric
+        .take()
+        .filter(|l| !l.is_empty())
+        .or_else(|| meta.lyric.clone().filter(|l| lrc::is_lrc(l)));
+
+    if tag_type == TagType::Id3v2 {
+        // USLT cannot carry timestamps
+        let plain = meta
+            .lyric
+            .take()
+            .map(|l| {
+                if lrc::is_lrc(&l) {
+                    lrc::strip_lrc(&l)
+                } else {
+                    l
+                }
+            })
+            .or_else(|| synced.as_deref().map(lrc::strip_lrc));
+        set_text(tag, ItemKey::Lyrics, plain);
+    } else {
+        set_text(
+            tag,
+            ItemKey::Lyrics,
+            synced.clone().or_else(|| meta.lyric.take()),
+        );
+        if let Some(key) = custom_key(tag_type, TRANSLATED_LYRICS_KEY) {
+            set_text(tag, key, meta.translated_lyric.take());
+        }
+        for (name, value) in meta.source_ids.take().unwrap_or_default() {
+            if let Some(key) = custom_key(tag_type, &source_id_name(&name)) {
+                set_text(tag, key, Some(value));
+            }
+        }
+    }
+
+    if let Some(data) = cover_data {
+        // Replace instead of piling up front covers on every re-tag
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(prepare_cover(&data, options.cover_max_size));
+    }
+
+    save_main_tag(path_obj, &tagged_file, &mut meta, synced.as_deref())
+}
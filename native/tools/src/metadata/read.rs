@@ -0,0 +1,268 @@
+use lofty::file::{FileType, TaggedFile};
+use lofty::id3::v2::{Frame, FrameFlags, Id3v2Tag, SynchronizedTextFrame};
+use lofty::prelude::*;
+use lofty::tag::{ItemKey, Tag};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use super::{
+    custom_key, lrc, read_tagged_file, source_id_name, SongMetadata, MP4_FREEFORM_PREFIX,
+    SOURCE_ID_SUFFIX, TRANSLATED_LYRICS_KEY,
+};
+use crate::utils::Context;
+
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct AudioProperties {
+    pub duration: f64,
+    /// kbps
+    pub overall_bitrate: Option<u32>,
+    /// kbps
+    pub audio_bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+    pub channels: Option<u32>,
+    /// Container format, e.g. `Flac`, `Mpeg`, `Mp4`
+    pub file_type: String,
+    /// Tag formats present in the file, e.g. `Id3v2`, `VorbisComments`
+    pub tag_types: Vec<String>,
+}
+
+#[napi(object)]
+pub struct EmbeddedPicture {
+    /// e.g. `CoverFront`, `CoverBack`, `Artist`
+    pub picture_type: String,
+    pub mime_type: Option<String>,
+    pub description: Option<String>,
+    pub data: Buffer,
+}
+
+#[napi(object)]
+pub struct MusicFileMetadata {
+    /// Same shape `write_music_metadata` accepts, `lyric` is always plain text and
+    /// `synced_lyric` is LRC
+    pub metadata: SongMetadata,
+    pub properties: AudioProperties,
+    pub pictures: Vec<EmbeddedPicture>,
+}
+
+/// Reads tags, technical properties, embedded pictures and lyrics of a local file
+#[napi]
+#[allow(clippy::missing_errors_doc, clippy::trailing_empty_array)]
+pub async fn read_music_metadata(file_path: String) -> Result<MusicFileMetadata> {
+    tokio::task::spawn_blocking(move || read_metadata(Path::new(&file_path)))
+        .await
+        .context("Metadata task panicked or cancelled")?
+}
+
+/// The tag every reader and writer in this crate treats as the file's tag
+pub fn main_tag(tagged_file: &TaggedFile) -> Option<&Tag> {
+    tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.tags().first())
+}
+
+fn read_metadata(path: &Path) -> Result<MusicFileMetadata> {
+    let tagged_file = read_tagged_file(path).context("Read tag failed")?;
+
+    let properties = read_properties(&tagged_file);
+    let (mut metadata, pictures) = main_tag(&tagged_file).map_or_else(
+        || (SongMetadata::default(), Vec::new()),
+        |tag| (read_tag(tag), read_pictures(tag)),
+    );
+
+    if main_tag(&tagged_file).is_some_and(|t| t.tag_type() == lofty::tag::TagType::Id3v2) {
+        // SYLT and described USLT frames are lost in the generic tag
+        match read_id3v2(path, tagged_file.file_type()) {
+            Ok(Some(id3v2)) => apply_id3v2_lyrics(&id3v2, &mut metadata),
+            Ok(None) => {}
+            Err(e) => eprintln!("读取 ID3v2 歌词失败: {e}"),
+        }
+    }
+
+    Ok(MusicFileMetadata {
+        metadata,
+        properties,
+        pictures,
+    })
+}
+
+fn read_properties(tagged_file: &TaggedFile) -> AudioProperties {
+    let props = tagged_file.properties();
+    AudioProperties {
+        duration: props.duration().as_millis() as f64,
+        overall_bitrate: props.overall_bitrate(),
+        audio_bitrate: props.audio_bitrate(),
+        sample_rate: props.sample_rate(),
+        bit_depth: props.bit_depth().map(u32::from),
+        channels: props.channels().map(u32::from),
+        file_type: format!("{:?}", tagged_file.file_type()),
+        tag_types: tagged_file
+            .tags()
+            .iter()
+            .map(|t| format!("{:?}", t.tag_type()))
+            .collect(),
+    }
+}
+
+fn read_pictures(tag: &Tag) -> Vec<EmbeddedPicture> {
+    tag.pictures()
+        .iter()
+        .map(|p| EmbeddedPicture {
+            picture_type: format!("{:?}", p.pic_type()),
+            mime_type: p.mime_type().map(|m| m.as_str().to_string()),
+            description: p.description().map(ToString::to_string),
+            data: p.data().to_vec().into(),
+        })
+        .collect()
+}
+
+fn get_text(tag: &Tag, key: &ItemKey) -> Option<String> {
+    tag.get_string(key)
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(ToString::to_string)
+}
+
+// "-6.52 dB" -> -6.52
+fn get_number(tag: &Tag, key: &ItemKey) -> Option<f64> {
+    let text = get_text(tag, key)?;
+    let number = text
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic() || c.is_whitespace())
+        .trim();
+    number.parse().ok()
+}
+
+fn read_tag(tag: &Tag) -> SongMetadata {
+    let tag_type = tag.tag_type();
+    // Files written before `ARTISTS` was used hold them as repeated artist fields
+    let mut artists: Vec<String> = tag
+        .get_strings(&ItemKey::TrackArtists)
+        .flat_map(|a| a.split('\0'))
+        .filter(|a| !a.is_empty())
+        .map(ToString::to_string)
+        .collect();
+    if artists.is_empty() {
+        artists = tag
+            .get_strings(&ItemKey::TrackArtist)
+            .map(ToString::to_string)
+            .collect();
+    }
+
+    let mut metadata = SongMetadata {
+        title: tag.title().unwrap_or_default().into_owned(),
+        artist: tag.artist().unwrap_or_default().into_owned(),
+        album: tag.album().unwrap_or_default().into_owned(),
+        description: tag.comment().map(std::borrow::Cow::into_owned),
+        album_artist: get_text(tag, &ItemKey::AlbumArtist),
+        genre: tag.genre().map(std::borrow::Cow::into_owned),
+        year: tag.year(),
+        track_number: tag.track(),
+        disc_number: tag.disk(),
+        musicbrainz_recording_id: get_text(tag, &ItemKey::MusicBrainzRecordingId),
+        musicbrainz_release_track_id: get_text(tag, &ItemKey::MusicBrainzTrackId),
+        musicbrainz_release_id: get_text(tag, &ItemKey::MusicBrainzReleaseId),
+        musicbrainz_release_group_id: get_text(tag, &ItemKey::MusicBrainzReleaseGroupId),
+        musicbrainz_artist_id: get_text(tag, &ItemKey::MusicBrainzArtistId),
+        musicbrainz_album_artist_id: get_text(tag, &ItemKey::MusicBrainzReleaseArtistId),
+        replaygain_track_gain: get_number(tag, &ItemKey::ReplayGainTrackGain),
+        replaygain_track_peak: get_number(tag, &ItemKey::ReplayGainTrackPeak),
+        replaygain_album_gain: get_number(tag, &ItemKey::ReplayGainAlbumGain),
+        replaygain_album_peak: get_number(tag, &ItemKey::ReplayGainAlbumPeak),
+        ..SongMetadata::default()
+    };
+    if metadata.artist.is_empty() && !artists.is_empty() {
+        metadata.artist = artists.join("/");
+    }
+    if artists.len() > 1 {
+        metadata.artists = Some(artists);
+    }
+
+    if let Some(lyric) = get_text(tag, &ItemKey::Lyrics) {
+        if lrc::is_lrc(&lyric) {
+            metadata.lyric = Some(lrc::strip_lrc(&lyric));
+            metadata.synced_lyric = Some(lyric);
+        } else {
+            metadata.lyric = Some(lyric);
+        }
+    }
+    if let Some(key) = custom_key(tag_type, TRANSLATED_LYRICS_KEY) {
+        metadata.translated_lyric = get_text(tag, &key);
+    }
+
+    let source_ids: HashMap<String, String> = tag
+        .items()
+        .filter_map(|item| {
+            let ItemKey::Unknown(key) = item.key() else {
+                return None;
+            };
+            let name = key.strip_prefix(MP4_FREEFORM_PREFIX).unwrap_or(key);
+            let value = item.value().text()?;
+            // Only what the writer emits for `source_ids`, not every custom field
+            let is_source_id = name.len() > SOURCE_ID_SUFFIX.len() && source_id_name(name) == name;
+            (is_source_id && !value.is_empty()).then(|| (name.to_string(), value.to_string()))
+        })
+        .collect();
+    if !source_ids.is_empty() {
+        metadata.source_ids = Some(source_ids);
+    }
+
+    metadata
+}
+
+pub(super) fn read_id3v2(path: &Path, file_type: FileType) -> Result<Option<Id3v2Tag>> {
+    let mut file = File::open(path).context("Open file failed")?;
+    let options = lofty::config::ParseOptions::new();
+    let tag = match file_type {
+        FileType::Mpeg => lofty::mpeg::MpegFile::read_from(&mut file, options)
+            .context("Read ID3v2 failed")?
+            .id3v2()
+            .cloned(),
+        FileType::Aiff => lofty::iff::aiff::AiffFile::read_from(&mut file, options)
+            .context("Read ID3v2 failed")?
+            .id3v2()
+            .cloned(),
+        FileType::Wav => lofty::iff::wav::WavFile::read_from(&mut file, options)
+            .context("Read ID3v2 failed")?
+            .id3v2()
+            .cloned(),
+        _ => None,
+    };
+    Ok(tag)
+}
+
+fn apply_id3v2_lyrics(id3v2: &Id3v2Tag, metadata: &mut SongMetadata) {
+    let mut plain = None;
+    for frame in id3v2 {
+        match frame {
+            Frame::UnsynchronizedText(uslt) if uslt.description == TRANSLATED_LYRICS_KEY => {
+                metadata.translated_lyric = Some(uslt.content.clone());
+            }
+            Frame::UnsynchronizedText(uslt) if plain.is_none() => {
+                plain = Some(uslt.content.clone());
+            }
+            Frame::Binary(binary) if frame.id_str() == "SYLT" => {
+                match SynchronizedTextFrame::parse(&binary.data, FrameFlags::default()) {
+                    Ok(sylt) if !sylt.content.is_empty() => {
+                        metadata.synced_lyric = Some(lrc::to_lrc(&sylt.content));
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("解析 SYLT 帧失败: {e}"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(plain) = plain.filter(|p| !p.is_empty()) {
+        if lrc::is_lrc(&plain) {
+            metadata.lyric = Some(lrc::strip_lrc(&plain));
+            metadata.synced_lyric.get_or_insert(plain);
+        } else {
+            metadata.lyric = Some(plain);
+        }
+    }
+}
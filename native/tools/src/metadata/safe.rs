@@ -0,0 +1,201 @@
+use lofty::prelude::*;
+use napi::bindgen_prelude::*;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::read_tagged_file;
+
+// Packets decoded to make sure the audio stream survived the tag write
+const VERIFY_PACKETS: usize = 32;
+// Tag writes never change the audio, so the duration must stay (almost) the same
+const DURATION_TOLERANCE_MS: u128 = 1000;
+
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Probe,
+    Copy,
+    WriteTags,
+    Sync,
+    Verify,
+    Backup,
+    Replace,
+}
+
+impl Stage {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Probe => "probe original",
+            Self::Copy => "copy to temporary file",
+            Self::WriteTags => "write tags",
+            Self::Sync => "sync temporary file",
+            Self::Verify => "verify",
+            Self::Backup => "backup",
+            Self::Replace => "replace original",
+        }
+    }
+
+    fn error(self, e: impl std::fmt::Display) -> Error {
+        Error::from_reason(format!(
+            "Safe write failed at stage '{}': {e}",
+            self.as_str()
+        ))
+    }
+}
+
+// Deletes the temporary file unless the write went through
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// Runs `write` against a copy of `path` in the same directory, verifies the copy and then
+/// renames it over the original. The original is never touched until the copy is known good.
+pub(super) fn write_safely(
+    path: &Path,
+    keep_backup: bool,
+    write: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+    let original_duration = read_tagged_file(path)
+        .map(|f| f.properties().duration().as_millis())
+        .map_err(|e| Stage::Probe.error(e))?;
+
+    let temp = TempFile(temp_path(path));
+    fs::copy(path, &temp.0).map_err(|e| Stage::Copy.error(e))?;
+
+    write(&temp.0).map_err(|e| Stage::WriteTags.error(e.reason.clone()))?;
+
+    // Flushing needs a writable handle on Windows
+    File::options()
+        .write(true)
+        .open(&temp.0)
+        .and_then(|f| f.sync_all())
+        .map_err(|e| Stage::Sync.error(e))?;
+
+    verify(&temp.0, path, original_duration).map_err(|e| Stage::Verify.error(e))?;
+
+    if keep_backup {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        fs::copy(path, PathBuf::from(backup)).map_err(|e| Stage::Backup.error(e))?;
+    }
+
+    // Same directory, so this is a rename within one filesystem
+    fs::rename(&temp.0, path).map_err(|e| Stage::Replace.error(e))?;
+    // Makes the rename itself durable, Windows has no directory handles to flush
+    #[cfg(unix)]
+    sync_parent(path);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Err(e) = File::open(dir).and_then(|d| d.sync_all()) {
+        eprintln!("同步目录失败 {}: {e}", dir.display());
+    }
+}
+
+// `.<name>.<nanos>.tmp.<ext>`, keeping the extension so lofty and symphonia detect the format
+fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let name = path.extension().map_or_else(
+        || format!(".{stem}.{nanos}.tmp"),
+        |ext| format!(".{stem}.{nanos}.tmp.{}", ext.to_string_lossy()),
+    );
+    path.with_file_name(name)
+}
+
+fn verify(
+    path: &Path,
+    original: &Path,
+    original_duration: u128,
+) -> std::result::Result<(), String> {
+    let tagged_file = read_tagged_file(path).map_err(|e| format!("Re-probe failed: {e}"))?;
+    let duration = tagged_file.properties().duration().as_millis();
+    if duration.abs_diff(original_duration) > DURATION_TOLERANCE_MS {
+        return Err(format!(
+            "Duration changed from {original_duration}ms to {duration}ms"
+        ));
+    }
+
+    decode_check(path).or_else(|e| {
+        // Files symphonia can't handle in the first place (APE, WMA, ...) are only re-probed
+        if decode_check(original).is_err() {
+            Ok(())
+        } else {
+            Err(e)
+        }
+    })
+}
+
+fn decode_check(path: &Path) -> std::result::Result<(), String> {
+    let src = File::open(path).map_err(|e| e.to_string())?;
+    let mss = MediaSourceStream::new(Box::new(src), MediaSourceStreamOptions::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| format!("Decoder probe failed: {e}"))?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| "No audio track".to_string())?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Create decoder failed: {e}"))?;
+
+    let mut packets = 0;
+    while packets < VERIFY_PACKETS {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(symphonia::core::errors::Error::IoError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(e) => return Err(format!("Read packet failed: {e}")),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        decoder
+            .decode(&packet)
+            .map_err(|e| format!("Decode failed: {e}"))?;
+        packets += 1;
+    }
+
+    if packets == 0 {
+        return Err("No audio packets".to_string());
+    }
+    Ok(())
+}
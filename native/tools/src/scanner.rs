@@ -11,8 +11,7 @@ use anyhow::Result;
 use crossbeam_channel::{bounded, Receiver};
 use jwalk::WalkDir;
 use lofty::{
-    file::{AudioFile, TaggedFile, TaggedFileExt},
-    probe::Probe,
+    file::{AudioFile, TaggedFile},
     tag::Accessor,
 };
 use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
@@ -20,6 +19,8 @@ use napi_derive::napi;
 use rayon::prelude::*;
 use rusqlite::{Connection, OpenFlags};
 
+use crate::metadata::{main_tag, read_gapless, read_tagged_file};
+
 #[napi(object)]
 #[derive(Debug, Clone)]
 pub struct MusicTrack {
@@ -117,10 +118,7 @@ fn load_db_snapshot(db_path: &str) -> Result<HashMap<String, TrackSnapshot>> {
 }
 
 fn process_cover(tag: &TaggedFile, file_id: &str, cover_dir: &Path) -> Option<String> {
-    let picture = tag
-        .primary_tag()
-        .or_else(|| tag.tags().first())
-        .and_then(|t| t.pictures().first())?;
+    let picture = main_tag(tag).and_then(|t| t.pictures().first())?;
 
     let file_name = format!("{file_id}.jpg");
     let save_path = cover_dir.join(&file_name);
@@ -268,10 +266,8 @@ fn process_single_track(
     }
 
     // TODO: 返回打不开的文件列表给前端？
-    let tagged_file = Probe::open(path_buf).ok()?.read().ok()?;
-    let tag = tagged_file
-        .primary_tag()
-        .or_else(|| tagged_file.tags().first())?;
+    let tagged_file = read_tagged_file(path_buf).ok()?;
+    let tag = main_tag(&tagged_file)?;
     let properties = tagged_file.properties();
 
     let title = tag
@@ -284,7 +280,11 @@ fn process_single_track(
                 .unwrap_or("未知歌曲")
         })
         .to_string();
-    let duration = properties.duration().as_millis() as f64;
+    // Lossy formats with gapless info know their exact length
+    let duration = read_gapless(path_buf, &tagged_file).map_or_else(
+        || properties.duration().as_millis() as f64,
+        |gapless| gapless.duration,
+    );
 
     if tag.title().is_none() && duration < 30_000.0 {
         return None;
@@ -0,0 +1,33 @@
+use napi::{Error, Result};
+
+// Error handling helper trait
+pub trait Context<T> {
+    fn context<C>(self, context: C) -> Result<T>
+    where
+        C: std::fmt::Display;
+}
+
+impl<T, E> Context<T> for std::result::Result<T, E>
+where
+    E: std::fmt::Display,
+{
+    fn context<C>(self, context: C) -> Result<T>
+    where
+        C: std::fmt::Display,
+    {
+        self.map_err(|e| Error::from_reason(format!("{context}: {e}")))
+    }
+}
+
+// Size of a leading ID3v2 tag, including header and footer
+pub fn id3v2_len(head: &[u8]) -> Option<u64> {
+    if head.len() < 10 || &head[..3] != b"ID3" {
+        return None;
+    }
+    // Syncsafe integer, 7 bits per byte
+    let size = head[6..10]
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 7) | u64::from(b & 0x7F));
+    let footer = if head[5] & 0x10 == 0 { 0 } else { 10 };
+    Some(10 + size + footer)
+}